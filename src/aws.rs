@@ -0,0 +1,41 @@
+// Module for the bits of AWS client setup shared by the S3 state backend
+// and the Route53 provider.
+
+use std::str::FromStr;
+use rusoto_core::Region;
+
+// Work out where AWS requests go: the named region, or the default if it's
+// missing or unknown.  With an endpoint they go to that URL instead (an
+// S3-compatible server or a local Route53 stand-in, say), and the region
+// is only used for signing, so it can be anything the server accepts, like
+// MinIO's default 'us-east-1'.
+pub fn build_region(region: &Option<String>, endpoint: &Option<String>) -> Region {
+    let named = match region {
+        Some(x) => Region::from_str(x).unwrap_or(Region::default()),
+        None => Region::default()
+    };
+    match endpoint {
+        Some(x) => Region::Custom {
+            name: region.clone().unwrap_or(named.name().to_string()),
+            endpoint: x.to_string()
+        },
+        None => named
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_region() {
+        let region = Some("us-west-2".to_string());
+        assert_eq!(build_region(&region, &None), Region::from_str("us-west-2").unwrap());
+        assert_eq!(build_region(&None, &None), Region::default());
+        let endpoint = Some("http://localhost:9000".to_string());
+        assert_eq!(build_region(&Some("minio".to_string()), &endpoint), Region::Custom {
+            name: "minio".to_string(),
+            endpoint: "http://localhost:9000".to_string()
+        });
+    }
+}
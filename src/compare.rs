@@ -37,10 +37,10 @@ pub fn local_state(lo: &ResHash, st: &ResHash) -> (ResHash, ResHash, ResHash) {
 	let st_hash = &st.0;
 	let lo_hash = &lo.0;
 	for (key, rec) in lo_hash {
-        match st_hash.get(key) {
-            Some(x) => { if x != rec { u.insert(key.clone(), x.clone()); },
-            None => n.insert(key.clone(), x.clone())
-        }
+		match st_hash.get(key) {
+			Some(x) => { if x != rec { u.insert(key.clone(), rec.clone()); } },
+			None => { n.insert(key.clone(), rec.clone()); }
+		}
 	}
 	for (key, rec) in st_hash {
 		if !lo_hash.contains_key(key) {
@@ -0,0 +1,164 @@
+// Module for client-side envelope encryption of statefiles.  Each save
+// gets a fresh random data key that encrypts the state; the data key is
+// then encrypted ('wrapped') with the master key from the key file, and
+// both go into a small JSON envelope.  All encryption is AES-256-GCM.
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+// What gets stored in place of the plain statefile
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    macrotis_envelope: u32,
+    algorithm: String,
+    wrapped_key: String,
+    ciphertext: String
+}
+
+// Associated data, so a wrapped key can't be passed off as a statefile
+// or vice versa
+const KEY_AAD: &[u8] = b"macrotis data key";
+const STATE_AAD: &[u8] = b"macrotis statefile";
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err("bad hex string".to_string());
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| "bad hex string".to_string()))
+        .collect()
+}
+
+// Load a 256-bit master key from a file, either as 32 raw bytes or as 64
+// hex digits (surrounding whitespace is ignored)
+pub fn load_key(fname: &str) -> Result<Vec<u8>, String> {
+    let data = match std::fs::read(fname) {
+        Ok(x) => x,
+        Err(e) => { return Err(format!("Error reading key file {}: {}", fname, e)); }
+    };
+    if data.len() == 32 {
+        return Ok(data);
+    }
+    let text = String::from_utf8_lossy(&data);
+    match from_hex(text.trim()) {
+        Ok(ref x) if x.len() == 32 => Ok(x.to_vec()),
+        _ => Err(format!("Key file {} should hold 32 bytes or 64 hex digits", fname))
+    }
+}
+
+// Encrypt with a random nonce, which goes on the front of the result
+fn seal(key: &[u8], aad: &[u8], data: &[u8], rng: &SystemRandom) -> Result<Vec<u8>, String> {
+    let key = match UnboundKey::new(&AES_256_GCM, key) {
+        Ok(x) => LessSafeKey::new(x),
+        Err(_) => { return Err("encryption key must be 32 bytes".to_string()); }
+    };
+    let mut nonce = [0u8; NONCE_LEN];
+    if rng.fill(&mut nonce).is_err() {
+        return Err("couldn't generate a nonce".to_string());
+    }
+    let mut in_out = data.to_vec();
+    if key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut in_out).is_err() {
+        return Err("encryption failed".to_string());
+    }
+    let mut retval = nonce.to_vec();
+    retval.append(&mut in_out);
+    Ok(retval)
+}
+
+// Reverse of seal
+fn open(key: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let key = match UnboundKey::new(&AES_256_GCM, key) {
+        Ok(x) => LessSafeKey::new(x),
+        Err(_) => { return Err("encryption key must be 32 bytes".to_string()); }
+    };
+    if data.len() < NONCE_LEN {
+        return Err("encrypted data is too short".to_string());
+    }
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let nonce = match Nonce::try_assume_unique_for_key(nonce) {
+        Ok(x) => x,
+        Err(_) => { return Err("bad nonce".to_string()); }
+    };
+    let mut in_out = sealed.to_vec();
+    match key.open_in_place(nonce, Aad::from(aad), &mut in_out) {
+        Ok(x) => Ok(x.to_vec()),
+        Err(_) => Err("decryption failed (wrong key, or the data has been tampered with)".to_string())
+    }
+}
+
+// Whether some statefile text is an encrypted envelope
+pub fn is_encrypted(text: &str) -> bool {
+    serde_json::from_str::<Envelope>(text).is_ok()
+}
+
+// Encrypt a statefile with a new data key, wrapped with 'master'
+pub fn encrypt(master: &[u8], plaintext: &str) -> Result<String, String> {
+    let rng = SystemRandom::new();
+    let mut data_key = [0u8; 32];
+    if rng.fill(&mut data_key).is_err() {
+        return Err("couldn't generate a data key".to_string());
+    }
+    let envelope = Envelope {
+        macrotis_envelope: 1,
+        algorithm: "AES-256-GCM".to_string(),
+        wrapped_key: to_hex(&seal(master, KEY_AAD, &data_key, &rng)?),
+        ciphertext: to_hex(&seal(&data_key, STATE_AAD, plaintext.as_bytes(), &rng)?)
+    };
+    match serde_json::to_string_pretty(&envelope) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+// Decrypt a statefile envelope made by encrypt
+pub fn decrypt(master: &[u8], text: &str) -> Result<String, String> {
+    let envelope: Envelope = match serde_json::from_str(text) {
+        Ok(x) => x,
+        Err(e) => { return Err(format!("bad encrypted statefile: {}", e)); }
+    };
+    if envelope.macrotis_envelope != 1 || envelope.algorithm != "AES-256-GCM" {
+        return Err(format!("unsupported statefile encryption {} version {}",
+                           envelope.algorithm, envelope.macrotis_envelope));
+    }
+    let data_key = open(master, KEY_AAD, &from_hex(&envelope.wrapped_key)?)?;
+    let plaintext = open(&data_key, STATE_AAD, &from_hex(&envelope.ciphertext)?)?;
+    match String::from_utf8(plaintext) {
+        Ok(x) => Ok(x),
+        Err(_) => Err("decrypted statefile isn't valid UTF-8".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = [7u8; 32];
+        let text = r#"{ "version": 2 }"#;
+        let sealed = encrypt(&key, text).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!is_encrypted(text));
+        assert!(!sealed.contains("version"));
+        assert_eq!(decrypt(&key, &sealed).unwrap(), text);
+
+        // Same input, different data key and nonces every time
+        assert!(encrypt(&key, text).unwrap() != sealed);
+        assert!(decrypt(&[8u8; 32], &sealed).is_err());
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let key = [7u8; 32];
+        let sealed = encrypt(&key, "hello").unwrap();
+        let mut envelope: Envelope = serde_json::from_str(&sealed).unwrap();
+        let last = envelope.ciphertext.pop().unwrap();
+        envelope.ciphertext.push(if last == '0' { '1' } else { '0' });
+        let tampered = serde_json::to_string(&envelope).unwrap();
+        assert!(decrypt(&key, &tampered).is_err());
+    }
+}
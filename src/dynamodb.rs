@@ -0,0 +1,123 @@
+// Functions for keeping the S3 backend's statefile lock in a DynamoDB
+// table.  S3 can't be asked to write an object only if it doesn't exist
+// yet (not by the client we're built with, anyway), but DynamoDB can do a
+// conditional put, so only one runner can ever create the lock item.
+//
+// The table needs a string partition key called LockID.  The item for a
+// statefile has a LockID of <bucket>/<key> and the lock's JSON in Info.
+use std::collections::HashMap;
+use MacrotisStateConfig;
+use aws;
+use s3;
+use state::MacrotisLock;
+use rusoto_core::{HttpClient, RusotoError};
+use rusoto_dynamodb::{DynamoDb, DynamoDbClient, AttributeValue, PutItemInput, PutItemError,
+                      GetItemInput, DeleteItemInput};
+
+// Build a DynamoDbClient, assuming the state config's role if it has one.
+// The table is in the state config's region, or at lock_endpoint (a
+// DynamoDB-compatible stand-in, say) if that's set.  The bucket's endpoint
+// isn't used, since an S3-compatible server won't speak DynamoDB.
+fn build_client(conf: &MacrotisStateConfig) -> Result<DynamoDbClient, String> {
+    let region = aws::build_region(&conf.region, &conf.lock_endpoint);
+    match s3::role_provider(&conf) {
+        Some(provider) => match HttpClient::new() {
+            Ok(x) => Ok(DynamoDbClient::new_with(x, provider, region)),
+            Err(e) => Err(format!("Error creating DynamoDB client: {}", e))
+        },
+        None => Ok(DynamoDbClient::new(region))
+    }
+}
+
+fn string_value(s: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(s.to_string()),
+        ..Default::default()
+    }
+}
+
+// The table and the key of the lock item
+fn lock_location(conf: &MacrotisStateConfig) -> Option<(String, HashMap<String, AttributeValue>)> {
+    match (&conf.lock_table, &conf.bucket, &conf.key) {
+        (Some(t), Some(b), Some(k)) => {
+            let mut key = HashMap::new();
+            key.insert("LockID".to_string(), string_value(&format!("{}/{}", b, k)));
+            Some((t.to_string(), key))
+        },
+        _ => None
+    }
+}
+
+// Fetch the current lock, if there is one
+pub fn fetch_lock(conf: &MacrotisStateConfig) -> Result<Option<MacrotisLock>, String> {
+    let (table, key) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let req = GetItemInput {
+        table_name: table,
+        key: key,
+        consistent_read: Some(true),
+        ..Default::default()
+    };
+    let item = match build_client(&conf)?.get_item(req).sync() {
+        Ok(x) => x.item,
+        Err(e) => { return Err(format!("Error reading DynamoDB lock: {}", e)); }
+    };
+    let info = match item.as_ref().and_then(|x| x.get("Info")).and_then(|x| x.s.as_ref()) {
+        Some(x) => x,
+        None => { return Ok(None); }
+    };
+    match serde_json::from_str(info) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Err(format!("Error reading lock JSON: {}", e))
+    }
+}
+
+// Try to take the lock.  Returns None if we got it, or whoever holds it.
+// The put only succeeds if there's no lock item already, so two runners
+// can't both get it.
+pub fn put_lock(conf: &MacrotisStateConfig, lock: &MacrotisLock) -> Result<Option<MacrotisLock>, String> {
+    let (table, mut item) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let info = match serde_json::to_string(lock) {
+        Ok(x) => x,
+        Err(e) => { return Err(e.to_string()); }
+    };
+    item.insert("Info".to_string(), string_value(&info));
+    let req = PutItemInput {
+        table_name: table,
+        item: item,
+        condition_expression: Some("attribute_not_exists(LockID)".to_string()),
+        ..Default::default()
+    };
+    match build_client(&conf)?.put_item(req).sync() {
+        Ok(_) => Ok(None),
+        Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+            match fetch_lock(&conf)? {
+                Some(x) => Ok(Some(x)),
+                None => Err("DynamoDB lock was released while taking it, try again".to_string())
+            }
+        },
+        Err(e) => Err(format!("Error writing DynamoDB lock: {}", e))
+    }
+}
+
+// Remove the lock item
+pub fn delete_lock(conf: &MacrotisStateConfig) -> Result<bool, String> {
+    let (table, key) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let req = DeleteItemInput {
+        table_name: table,
+        key: key,
+        ..Default::default()
+    };
+    match build_client(&conf)?.delete_item(req).sync() {
+        Ok(_) => Ok(true),
+        Err(e) => Err(format!("Error deleting DynamoDB lock: {}", e))
+    }
+}
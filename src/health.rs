@@ -0,0 +1,521 @@
+// Module for Route53 health checks.  Checks are declared by name in the
+// config's health_checks section, and the statefile remembers the ID each
+// one was given, so changes to them can be planned and pushed like changes
+// to records.  Records refer to a check by its name (the health field of a
+// '!' line), which gets swapped for the check's ID when changes are pushed.
+
+use std::collections::HashMap;
+use resource::{Resource, ResHash};
+use provider::DnsProvider;
+use plan;
+
+// The kinds of check that can be declared.  Calculated checks are healthy
+// when enough of their children are.
+pub const CHECK_TYPES: [&str; 4] = ["HTTP", "HTTPS", "TCP", "CALCULATED"];
+
+// A health check as declared in the config.  Endpoint checks (HTTP, HTTPS
+// and TCP) need an ip_address or fqdn to check; calculated ones need the
+// names of their children and how many of those have to be healthy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct HealthCheck {
+    #[serde(rename = "type")]
+    pub check_type: String,
+    pub ip_address: Option<String>,
+    pub fqdn: Option<String>,
+    pub port: Option<i64>,
+    pub resource_path: Option<String>,
+    pub request_interval: Option<i64>,
+    pub failure_threshold: Option<i64>,
+    pub children: Option<Vec<String>>,
+    pub health_threshold: Option<i64>,
+    pub inverted: Option<bool>
+}
+
+// A check as the statefile has it: the ID it was given, plus what it was
+// last pushed as
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StoredCheck {
+    pub id: String,
+    pub check: HealthCheck
+}
+
+impl HealthCheck {
+    pub fn is_calculated(&self) -> bool {
+        self.check_type.to_uppercase() == "CALCULATED"
+    }
+
+    // Fill in the defaults Route53 uses, so a check compares the same
+    // whether or not they were spelled out
+    pub fn normalized(&self) -> HealthCheck {
+        let mut retval = self.clone();
+        retval.check_type = self.check_type.to_uppercase();
+        if retval.inverted == Some(false) {
+            retval.inverted = None;
+        }
+        if retval.is_calculated() {
+            if let Some(x) = &mut retval.children {
+                x.sort();
+            }
+            return retval;
+        }
+        if retval.port.is_none() {
+            retval.port = match retval.check_type.as_str() {
+                "HTTP" => Some(80),
+                "HTTPS" => Some(443),
+                _ => None
+            };
+        }
+        retval.request_interval = Some(retval.request_interval.unwrap_or(30));
+        retval.failure_threshold = Some(retval.failure_threshold.unwrap_or(3));
+        retval
+    }
+
+    // Route53 can't change these on an existing check, so it has to be
+    // replaced with a new one instead
+    fn needs_replacing(&self, other: &HealthCheck) -> bool {
+        self.check_type != other.check_type || self.request_interval != other.request_interval
+    }
+
+    // Everything wrong with a check, given the rest of the checks it's
+    // declared with
+    fn problems(&self, all: &HashMap<String, HealthCheck>) -> Vec<String> {
+        let mut retval = Vec::new();
+        let check_type = self.check_type.to_uppercase();
+        if !CHECK_TYPES.contains(&check_type.as_str()) {
+            retval.push(format!("unknown type '{}'", self.check_type));
+            return retval;
+        }
+        if self.is_calculated() {
+            if self.ip_address.is_some() || self.fqdn.is_some() || self.port.is_some() ||
+               self.resource_path.is_some() || self.request_interval.is_some() || self.failure_threshold.is_some() {
+                retval.push("calculated checks only take children, health_threshold, and inverted".to_string());
+            }
+            let children = self.children.clone().unwrap_or_default();
+            if children.is_empty() {
+                retval.push("calculated checks need children".to_string());
+            }
+            for c in &children {
+                match all.get(c) {
+                    Some(x) if x.is_calculated() => {
+                        retval.push(format!("child '{}' is itself a calculated check", c));
+                    },
+                    Some(_) => {},
+                    None => { retval.push(format!("child '{}' isn't a declared check", c)); }
+                };
+            }
+            match self.health_threshold {
+                Some(x) if x >= 0 && x as usize <= children.len() => {},
+                Some(x) => { retval.push(format!("health_threshold {} is more than the number of children", x)); },
+                None => { retval.push("calculated checks need a health_threshold".to_string()); }
+            };
+            return retval;
+        }
+        if self.children.is_some() || self.health_threshold.is_some() {
+            retval.push("only calculated checks take children or health_threshold".to_string());
+        }
+        if self.ip_address.is_none() && self.fqdn.is_none() {
+            retval.push("needs an ip_address or fqdn to check".to_string());
+        }
+        if check_type == "TCP" && self.port.is_none() {
+            retval.push("TCP checks need a port".to_string());
+        }
+        if check_type == "TCP" && self.resource_path.is_some() {
+            retval.push("TCP checks don't take a resource_path".to_string());
+        }
+        match self.request_interval {
+            None | Some(10) | Some(30) => {},
+            Some(x) => { retval.push(format!("request_interval should be 10 or 30, not {}", x)); }
+        };
+        match self.failure_threshold {
+            Some(x) if !(1..=10).contains(&x) => {
+                retval.push(format!("failure_threshold should be 1-10, not {}", x));
+            },
+            _ => {}
+        };
+        retval
+    }
+}
+
+// Check every declared health check, printing what's wrong with any that
+// won't work.  Returns false if there were any.
+pub fn validate(checks: &HashMap<String, HealthCheck>) -> bool {
+    let mut names: Vec<&String> = checks.keys().collect();
+    names.sort();
+    let mut retval = true;
+    for name in names {
+        for p in checks[name].problems(&checks) {
+            println!("Error: health check {}: {}", name, p);
+            retval = false;
+        }
+    }
+    retval
+}
+
+// What has to happen to the checks in the statefile to match the config,
+// by name.  Checks to be replaced get a new ID, with the old one deleted
+// once nothing uses it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct HealthPlan {
+    pub create: Vec<String>,
+    pub update: Vec<String>,
+    pub replace: Vec<String>,
+    pub delete: Vec<String>
+}
+
+impl HealthPlan {
+    pub fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.update.is_empty() && self.replace.is_empty() && self.delete.is_empty()
+    }
+}
+
+// Compare the declared checks with the ones in the statefile.  Calculated
+// checks whose children are getting new IDs have to be updated with them,
+// even if they haven't changed themselves.
+pub fn diff(local: &HashMap<String, HealthCheck>, stored: &HashMap<String, StoredCheck>) -> HealthPlan {
+    let mut retval = HealthPlan::default();
+    for (name, check) in local {
+        let check = check.normalized();
+        match stored.get(name) {
+            None => { retval.create.push(name.to_string()); },
+            Some(x) if x.check.needs_replacing(&check) => { retval.replace.push(name.to_string()); },
+            Some(x) if x.check != check => { retval.update.push(name.to_string()); },
+            Some(_) => {}
+        };
+    }
+    for (name, check) in local {
+        if retval.create.contains(name) || retval.replace.contains(name) || retval.update.contains(name) {
+            continue;
+        }
+        let children = check.children.clone().unwrap_or_default();
+        if children.iter().any(|c| retval.create.contains(c) || retval.replace.contains(c)) {
+            retval.update.push(name.to_string());
+        }
+    }
+    for name in stored.keys() {
+        if !local.contains_key(name) {
+            retval.delete.push(name.to_string());
+        }
+    }
+    retval.create.sort();
+    retval.update.sort();
+    retval.replace.sort();
+    retval.delete.sort();
+    retval
+}
+
+// Print out the planned changes to health checks, in the same style as
+// record changes
+pub fn print_plan(hplan: &HealthPlan, local: &HashMap<String, HealthCheck>, stored: &HashMap<String, StoredCheck>) {
+    for name in &hplan.create {
+        println!("[ADD] health check {} ({})", name, local[name].check_type.to_uppercase());
+    }
+    for name in &hplan.update {
+        println!("[UPD] health check {} ({})", name, stored[name].id);
+    }
+    for name in &hplan.replace {
+        println!("[REP] health check {} ({}), type or request_interval changed", name, stored[name].id);
+    }
+    for name in &hplan.delete {
+        println!("[DEL] health check {} ({})", name, stored[name].id);
+    }
+}
+
+// Swap the IDs of child checks for their names, or back again
+fn map_children(check: &HealthCheck, map: &HashMap<String, String>) -> HealthCheck {
+    let mut retval = check.clone();
+    if let Some(x) = &check.children {
+        retval.children = Some(x.iter().map(|c| map.get(c).cloned().unwrap_or(c.to_string())).collect());
+    }
+    retval
+}
+
+fn ids_by_name(stored: &HashMap<String, StoredCheck>) -> HashMap<String, String> {
+    stored.iter().map(|(k, v)| (k.to_string(), v.id.to_string())).collect()
+}
+
+fn names_by_id(stored: &HashMap<String, StoredCheck>) -> HashMap<String, String> {
+    stored.iter().map(|(k, v)| (v.id.to_string(), k.to_string())).collect()
+}
+
+// Compare the checks in the statefile with the ones the remote end has (by
+// ID), the way compare::state_remote does for records.  Checks that have
+// gone missing are dropped, so they get created again, and ones that have
+// been changed take on the remote settings, so they get changed back.
+pub fn state_remote(stored: &mut HashMap<String, StoredCheck>, remote: &HashMap<String, HealthCheck>) {
+    let names = names_by_id(&stored);
+    let mut gone = Vec::new();
+    for (name, st) in stored.iter_mut() {
+        match remote.get(&st.id) {
+            Some(x) => {
+                let x = map_children(x, &names).normalized();
+                if x != st.check {
+                    println!("[WARNING] Remote health check {} ({}) does not match statefile", name, st.id);
+                    st.check = x;
+                }
+            },
+            None => {
+                println!("[WARNING] Health check {} ({}) appears in state but not remote", name, st.id);
+                gone.push(name.to_string());
+            }
+        };
+    }
+    for name in gone {
+        stored.remove(&name);
+    }
+}
+
+// Name records' health checks by name instead of ID, for records fetched
+// from the remote end.  IDs of checks the statefile doesn't know about are
+// left alone.
+pub fn name_records<'a, I: IntoIterator<Item = &'a mut Resource>>(recs: I, stored: &HashMap<String, StoredCheck>) {
+    let names = names_by_id(&stored);
+    for res in recs {
+        if let Some(x) = res.health_check_id.as_ref().and_then(|x| names.get(x)) {
+            res.health_check_id = Some(x.to_string());
+        }
+    }
+}
+
+// The reverse of name_records, for records about to be pushed
+pub fn resolve_records(recs: &Vec<Resource>, stored: &HashMap<String, StoredCheck>) -> Vec<Resource> {
+    let mut retval = recs.clone();
+    for res in retval.iter_mut() {
+        if let Some(x) = res.health_check_id.as_ref().and_then(|x| stored.get(x)) {
+            res.health_check_id = Some(x.id.to_string());
+        }
+    }
+    retval
+}
+
+// Records can't go on using a check that's about to be deleted.  Prints
+// any that do and returns false.
+pub fn check_records(recs: &ResHash, hplan: &HealthPlan) -> bool {
+    let mut retval = true;
+    for res in recs.0.values() {
+        if let Some(x) = res.health_check_id.as_ref().filter(|x| hplan.delete.contains(x)) {
+            println!("Error: {} {} uses health check {}, which is no longer declared", res.rtype, res.name, x);
+            retval = false;
+        }
+    }
+    retval
+}
+
+// Whether a '!' line's health field is shaped like a Route53 health check
+// ID, which is a UUID
+pub fn looks_like_id(health: &str) -> bool {
+    let groups: Vec<&str> = health.split('-').collect();
+    groups.len() == 5 &&
+    groups.iter().zip([8, 4, 4, 4, 12].iter()).all(|(g, n)| g.len() == *n && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// Every record's health check has to be declared in the config or be the
+// ID of an existing one, so a misspelt name is caught by lint rather than
+// sent to Route53 as an ID.  Prints any that are neither and returns false.
+pub fn check_names(recs: &ResHash, checks: &HashMap<String, HealthCheck>) -> bool {
+    let mut retval = true;
+    for res in recs.0.values() {
+        if let Some(x) = res.health_check_id.as_ref().filter(|x| !checks.contains_key(*x) && !looks_like_id(x)) {
+            println!("Error: {} {} uses health check '{}', which isn't declared and isn't a health check ID", res.rtype, res.name, x);
+            retval = false;
+        }
+    }
+    retval
+}
+
+// Records using a check that's being replaced have to be pointed at the
+// new one, even when nothing about them has changed locally.  Adds them
+// to the updates, unless they're already being created or updated.
+pub fn repoint(local: &ResHash, hplan: &HealthPlan, new_recs: &ResHash, upd_recs: &mut ResHash) {
+    for (key, res) in &local.0 {
+        let replaced = match &res.health_check_id {
+            Some(x) => hplan.replace.contains(x),
+            None => false
+        };
+        if replaced && !new_recs.0.contains_key(key) && !upd_recs.0.contains_key(key) {
+            upd_recs.0.insert(key.to_string(), res.clone());
+        }
+    }
+}
+
+// Hash the declared checks, for telling whether they've changed since a
+// plan was made
+pub fn hash_checks(checks: &HashMap<String, HealthCheck>, mut hash: u64) -> u64 {
+    let mut names: Vec<&String> = checks.keys().collect();
+    names.sort();
+    for name in names {
+        let json = serde_json::to_string(&checks[name].normalized()).unwrap_or_default();
+        hash = plan::fnv1a(name.as_bytes(), hash);
+        hash = plan::fnv1a(b"\0", hash);
+        hash = plan::fnv1a(json.as_bytes(), hash);
+        hash = plan::fnv1a(b"\0", hash);
+    }
+    hash
+}
+
+// Route53 uses the caller reference to make creating a check safe to
+// retry: asking again with the same reference and settings hands back the
+// check that was already made.  Including the statefile serial means a
+// run that failed after creating checks reuses them when it's run again,
+// while a later change gets a fresh one.
+fn caller_reference(name: &str, check: &HealthCheck, serial: u64) -> String {
+    let json = serde_json::to_string(check).unwrap_or_default();
+    let hash = plan::fnv1a(json.as_bytes(), plan::fnv1a(name.as_bytes(), 0xcbf29ce484222325));
+    let short: String = name.chars().take(16).collect();
+    format!("macrotis-{}-{}-{:016x}", short, serial, hash)
+}
+
+// Make the checks match the config ahead of the record changes that might
+// use them: create new ones, replace and update changed ones (children
+// before the calculated checks that watch them), and forget removed ones.
+// 'stored' is updated as it goes.  Returns the IDs of the checks that are
+// no longer needed, to be deleted once records have stopped using them.
+pub fn apply_plan(prov: &dyn DnsProvider, hplan: &HealthPlan, local: &HashMap<String, HealthCheck>,
+                  stored: &mut HashMap<String, StoredCheck>, serial: u64) -> Result<Vec<String>, String> {
+    let mut old = Vec::new();
+    let mut making: Vec<&String> = hplan.create.iter().chain(hplan.replace.iter()).collect();
+    making.sort_by_key(|x| local[*x].is_calculated());
+    for name in making {
+        let check = local[name].normalized();
+        let resolved = map_children(&check, &ids_by_name(&stored));
+        let id = prov.create_health_check(&caller_reference(name, &check, serial), name, &resolved)?;
+        println!("Created health check {} ({})", name, id);
+        if let Some(x) = stored.insert(name.to_string(), StoredCheck { id: id, check: check }) {
+            old.push(x);
+        }
+    }
+    for name in &hplan.update {
+        let check = local[name].normalized();
+        let resolved = map_children(&check, &ids_by_name(&stored));
+        let id = stored[name].id.to_string();
+        prov.update_health_check(&id, &resolved)?;
+        println!("Updated health check {} ({})", name, id);
+        stored.insert(name.to_string(), StoredCheck { id: id, check: check });
+    }
+
+    for name in &hplan.delete {
+        if let Some(x) = stored.remove(name) {
+            old.push(x);
+        }
+    }
+
+    // Calculated checks have to go before the children they watch
+    old.sort_by_key(|x| !x.check.is_calculated());
+    Ok(old.into_iter().map(|x| x.id).collect())
+}
+
+// Delete checks that nothing uses any more.  Failures are only reported,
+// since the statefile has already let go of them; they'll need deleting
+// by hand.
+pub fn delete_checks(prov: &dyn DnsProvider, ids: &Vec<String>) -> bool {
+    let mut retval = true;
+    for id in ids {
+        match prov.delete_health_check(&id) {
+            Ok(_) => { println!("Deleted health check {}", id); },
+            Err(e) => {
+                println!("Error deleting health check {}, delete it by hand: {}", id, e);
+                retval = false;
+            }
+        };
+    }
+    retval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(fqdn: &str) -> HealthCheck {
+        HealthCheck {
+            check_type: "https".to_string(),
+            fqdn: Some(fqdn.to_string()),
+            resource_path: Some("/health".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn calculated(children: Vec<&str>, threshold: i64) -> HealthCheck {
+        HealthCheck {
+            check_type: "CALCULATED".to_string(),
+            children: Some(children.iter().map(|x| x.to_string()).collect()),
+            health_threshold: Some(threshold),
+            ..Default::default()
+        }
+    }
+
+    fn stored(id: &str, check: &HealthCheck) -> StoredCheck {
+        StoredCheck { id: id.to_string(), check: check.normalized() }
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut checks = HashMap::new();
+        checks.insert("web".to_string(), http("www.test.com"));
+        checks.insert("both".to_string(), calculated(vec!["web"], 1));
+        assert!(validate(&checks));
+        assert_eq!(checks["web"].normalized().port, Some(443));
+
+        checks.insert("tcp".to_string(), HealthCheck { check_type: "TCP".to_string(), ip_address: Some("1.2.3.4".to_string()), ..Default::default() });
+        checks.insert("all".to_string(), calculated(vec!["web", "both", "nope"], 4));
+        let problems = checks["all"].problems(&checks);
+        assert_eq!(problems.len(), 3);
+        assert_eq!(checks["tcp"].problems(&checks), vec!["TCP checks need a port".to_string()]);
+        assert!(!validate(&checks));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut local = HashMap::new();
+        local.insert("web".to_string(), http("www.test.com"));
+        local.insert("api".to_string(), http("api.test.com"));
+        local.insert("both".to_string(), calculated(vec!["web", "api"], 1));
+        local.insert("new".to_string(), http("new.test.com"));
+        let mut st = HashMap::new();
+        st.insert("web".to_string(), stored("1", &http("www.test.com")));
+        st.insert("api".to_string(), stored("2", &http("old.test.com")));
+        st.insert("both".to_string(), stored("3", &calculated(vec!["api", "web"], 1)));
+        st.insert("gone".to_string(), stored("4", &http("gone.test.com")));
+        let hplan = diff(&local, &st);
+        assert_eq!(hplan.create, vec!["new"]);
+        assert_eq!(hplan.update, vec!["api"]);
+        assert!(hplan.replace.is_empty());
+        assert_eq!(hplan.delete, vec!["gone"]);
+
+        // A new interval means a new check, and its parent has to follow
+        local.insert("web".to_string(), HealthCheck { request_interval: Some(10), ..http("www.test.com") });
+        let hplan = diff(&local, &st);
+        assert_eq!(hplan.replace, vec!["web"]);
+        assert_eq!(hplan.update, vec!["api", "both"]);
+    }
+
+    #[test]
+    fn test_check_names() {
+        assert!(looks_like_id("abcdef11-2222-3333-4444-555555fedcba"));
+        assert!(!looks_like_id("abcdef11-2222-3333-4444-555555fedcbg"));
+        assert!(!looks_like_id("web"));
+        let mut checks = HashMap::new();
+        checks.insert("web".to_string(), http("www.test.com"));
+        let rec = |health: &str| Resource {
+            name: "www.test.com".to_string(),
+            rtype: "A".to_string(),
+            set_identifier: Some("a".to_string()),
+            health_check_id: Some(health.to_string()),
+            ..Default::default()
+        };
+        let mut recs = ResHash(HashMap::new());
+        recs.0.insert("a".to_string(), rec("web"));
+        recs.0.insert("b".to_string(), rec("abcdef11-2222-3333-4444-555555fedcba"));
+        assert!(check_names(&recs, &checks));
+        recs.0.insert("c".to_string(), rec("wbe"));
+        assert!(!check_names(&recs, &checks));
+    }
+
+    #[test]
+    fn test_caller_reference() {
+        let check = http("www.test.com").normalized();
+        let a = caller_reference("web", &check, 5);
+        assert_eq!(a, caller_reference("web", &check, 5));
+        assert!(a != caller_reference("web", &check, 6));
+        assert!(a != caller_reference("web", &http("api.test.com").normalized(), 5));
+        assert!(caller_reference(&"x".repeat(100), &check, u64::max_value()).len() <= 64);
+    }
+}
@@ -5,40 +5,90 @@ extern crate rusoto_core;
 extern crate rusoto_route53;
 extern crate rusoto_sts;
 extern crate rusoto_s3;
+extern crate rusoto_dynamodb;
+extern crate ring;
 
 use std::collections::HashMap;
+use resource::GeoLocation;
+use health::HealthCheck;
+use ownership::Ownership;
 
 // Sub-modules for parsing tinydns and interacting with AWS
 pub mod tinydns;
 pub mod r53;
 pub mod resource;
 pub mod s3;
+pub mod dynamodb;
 pub mod state;
 pub mod compare;
+pub mod provider;
+pub mod mock;
+pub mod writer;
+pub mod plan;
+pub mod migrate;
+pub mod crypto;
+pub mod aws;
+pub mod retry;
+pub mod health;
+pub mod zones;
+pub mod ownership;
 
 // Define a struct for holding configuration metadata
+// Locations maps TinyDNS 'lo' codes to geolocations.
+// Health_checks declares Route53 health checks by name, for records to
+// refer to.
 #[derive(Deserialize, Debug)]
 pub struct MacrotisConfig {
     pub provider: MacrotisProviderConfig,
     pub statefile: MacrotisStateConfig,
-    pub zones: Vec<Zone>
+    pub zones: Vec<Zone>,
+    pub locations: Option<HashMap<String, GeoLocation>>,
+    pub health_checks: Option<HashMap<String, HealthCheck>>
 }
 
 // Define a struct for holding provider configuration metadata
 // If assume_role is true, role_arn needs to be populated
-// Region is optional as well
-#[derive(Serialize, Deserialize, Debug)]
+// Region is optional as well.  Name selects the DnsProvider backend.
+// Fixture and pending_polls are only used by the 'mock' provider.
+// Wait_timeout is how many seconds to wait for pushed changes to reach
+// every nameserver (default 300, 0 to not wait), checking every
+// poll_interval seconds (default 5).  Endpoint sends Route53 calls to that
+// URL instead of AWS (a local stand-in, say); STS calls for assume_role go
+// there too, unless sts_endpoint says otherwise.  Calls that get throttled
+// or otherwise fail in passing are tried up to max_attempts times (default
+// 5), waiting a random time up to retry_base_delay_ms (default 500) doubled
+// for each retry, but never more than retry_max_delay_ms (default 20000).
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MacrotisProviderConfig {
     pub name: String,
     pub region: Option<String>,
     pub assume_role: bool,
     pub role_arn: Option<String>,
-    pub session_name: Option<String>
+    pub session_name: Option<String>,
+    pub fixture: Option<String>,
+    pub pending_polls: Option<u32>,
+    pub wait_timeout: Option<u64>,
+    pub poll_interval: Option<u64>,
+    pub endpoint: Option<String>,
+    pub sts_endpoint: Option<String>,
+    pub max_attempts: Option<u32>,
+    pub retry_base_delay_ms: Option<u64>,
+    pub retry_max_delay_ms: Option<u64>
 }
 
 // Define a struct for holding State configuration metadata
 // If backend=local, only filename need be populated.  If backend=s3,
-// everything else should be populated.
+// everything else should be populated.  Backend=mock keeps state in memory,
+// or in filename if given.  Lock_timeout is how many seconds a statefile
+// lock lasts before it's considered stale (default 3600).  Lock_table is a
+// DynamoDB table to keep the s3 backend's lock in, reached at lock_endpoint
+// if that's set.  The s3 backend won't lock without one, since a lock object
+// in the bucket isn't atomic, unless unsafe_lock_object says to use that.
+// Server_side_encryption ("AES256" or "aws:kms") and kms_key_id ask S3 to
+// encrypt what it stores.  Encryption_key_file names a 256-bit key that
+// statefiles get encrypted with before they're written, for any backend.
+// Endpoint points the s3 backend at an S3-compatible server instead of AWS,
+// and sts_endpoint does the same for assuming role_arn.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MacrotisStateConfig {
     pub backend: String,
@@ -49,14 +99,40 @@ pub struct MacrotisStateConfig {
     pub role_arn: Option<String>,
     pub tags: Option<HashMap<String, String>>,
     pub session_name: Option<String>,
+    pub lock_timeout: Option<u64>,
+    pub lock_table: Option<String>,
+    pub lock_endpoint: Option<String>,
+    pub unsafe_lock_object: Option<bool>,
+    pub server_side_encryption: Option<String>,
+    pub kms_key_id: Option<String>,
+    pub encryption_key_file: Option<String>,
+    pub endpoint: Option<String>,
+    pub sts_endpoint: Option<String>,
 }
 
 // Helper struct for Zone data
+// Id can be left out to have the hosted zone looked up by domain.  If
+// there isn't one and create is true, it gets made: private and
+// associated with vpcs if any are given, otherwise public, using the
+// reusable delegation set delegation_set_id if given.  Ownership limits
+// which records in the zone Macrotis will touch.
 #[derive(Deserialize, Debug)]
 pub struct Zone {
     pub name: String,
     pub domain: String,
-    pub id: String
+    #[serde(default)]
+    pub id: String,
+    pub create: Option<bool>,
+    pub vpcs: Option<Vec<ZoneVpc>>,
+    pub delegation_set_id: Option<String>,
+    pub ownership: Option<Ownership>
+}
+
+// A VPC a private zone is served to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ZoneVpc {
+    pub vpc_id: String,
+    pub region: String
 }
 
 
@@ -1,13 +1,19 @@
 extern crate macrotis;
 #[macro_use] extern crate clap;
 
-use macrotis::r53;
 use macrotis::state;
 use macrotis::resource;
 use macrotis::compare;
+use macrotis::provider;
+use macrotis::provider::DnsProvider;
 use macrotis::{MacrotisConfig};
 use macrotis::resource::{Resource, ResHash};
 use macrotis::tinydns;
+use macrotis::writer;
+use macrotis::plan;
+use macrotis::health::{self, HealthPlan};
+use macrotis::zones;
+use macrotis::ownership;
 use std::collections::HashMap;
 //use macrotis::MacrotisRecord;
 //use std::env;
@@ -21,42 +27,166 @@ fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).version(clap::crate_version!()).get_matches();
 
-    // Safe to simply unwrap this value since it's marked as 'required'
-    let input = matches.value_of("input").unwrap();
+    // Check subcommand and bail if none provided
+    let sub = match matches.subcommand_name() {
+        Some("lint") => 0,
+        Some("noop") => 1,
+        Some("execute") => 2,
+        Some("import") => 3,
+        Some("fmt") => 4,
+        Some("apply") => 5,
+        Some("force-unlock") => 6,
+        Some("state") => match matches.subcommand_matches("state").unwrap().subcommand_name() {
+            Some("rollback") => 8,
+            Some(_) => 7,
+            None => {
+                println!("Missing state subcommand. Use 'macrotis state --help' for usage");
+                std::process::exit(1);
+            }
+        },
+        Some("zone") => 9,
+        _ => {
+            println!("Missing subcommand. Use 'macrotis --help' for usage");
+            std::process::exit(1);
+        }
+    };
+
+    // Everything but force-unlock and the state commands needs some input
+    let input = match matches.value_of("input") {
+        Some(x) => x,
+        None if sub >= 6 => "",
+        None => {
+            println!("Missing --input. Use 'macrotis --help' for usage");
+            std::process::exit(1);
+        }
+    };
+
+    // Formatting only touches the input files, no config needed
+    if sub == 4 {
+        if !format_files(&input) {
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // If no config file was specified, default to 'macrotis.conf'
     let conffile = matches.value_of("config").unwrap_or("macrotis.conf");
 
     // Attempt to load the config file, exit on failure
-    let config = match load_config(conffile) {
+    let mut config = match load_config(conffile) {
         Some(x) => x,
         None => {
             println!("Error loading config file {}. Bailing out.", conffile);
             std::process::exit(1);
         }
     };
+    
+    // Clearing a stale lock doesn't need anything else
+    if sub == 6 {
+        let id = matches.subcommand_matches("force-unlock").unwrap().value_of("LOCK_ID");
+        if !state::force_unlock(&config, id) {
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // Check subcommand and bail if none provided
-    let sub = match matches.subcommand_name() {
-        Some("lint") => 0,
-        Some("noop") => 1,
-        Some("execute") => 2,
-        _ => {
-            println!("Missing subcommand. Use 'macrotis --help' for usage");
+    // Neither do the state commands, except for rollback which carries on
+    // like 'execute' below
+    if sub == 7 {
+        if !state_command(matches.subcommand_matches("state").unwrap(), &config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Or the zone commands
+    if sub == 9 {
+        if !zone_command(matches.subcommand_matches("zone").unwrap(), &mut config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Import is its own thing - it writes the input files rather than
+    // reading them.  It does write the statefile, so hold the lock.
+    if sub == 3 {
+        let lock = match state::lock_state(&config, "import") {
+            Some(x) => x,
+            None => {
+                println!("Couldn't lock statefile, bailing out.");
+                std::process::exit(1);
+            }
+        };
+        let ok = import_remote(&input, &mut config);
+        if !state::unlock_state(&config, &lock) || !ok {
             std::process::exit(1);
         }
+        return;
+    }
+
+    // Anything that's going to push and save needs to hold the statefile
+    // lock from here on, so bail() releases it on the way out.  That
+    // includes creating any missing zones below.
+    let operation = match sub {
+        8 => "rollback",
+        _ => matches.subcommand_name().unwrap()
     };
-    
-    // Load up local records based on the 'input' argument provided.
-    // Bail out on error
-    let local_recs = match load_local(&input, &config) {
+    let lock = match sub {
+        2 | 5 | 8 => match state::lock_state(&config, operation) {
+            Some(x) => Some(x),
+            None => {
+                println!("Couldn't lock statefile, bailing out.");
+                std::process::exit(1);
+            }
+        },
+        _ => None
+    };
+
+    // Build the DnsProvider backend selected in the config
+    let prov = match provider::build_provider(&config.provider) {
+        Some(x) => x,
+        None => {
+            println!("Error setting up provider {}, bailing out.", &config.provider.name);
+            bail(&config, &lock);
+        }
+    };
+
+    // Records are filed by zone ID, so zones given by domain alone need
+    // theirs looked up first.  Missing ones that may be created are made
+    // by 'execute' and rollbacks, and only reported otherwise.
+    if sub != 0 && !zones::resolve(prov.as_ref(), &mut config.zones, sub == 2 || sub == 8) {
+        println!("Error looking up hosted zones, bailing out.");
+        bail(&config, &lock);
+    }
+
+    // Load up local records based on the 'input' argument provided, or
+    // from an old statefile for a rollback.  Bail out on error
+    let local_recs = match sub {
+        8 => rollback_records(matches.subcommand_matches("state").unwrap(), &config),
+        _ => load_local(&input, &config)
+    };
+    let mut local_recs = match local_recs {
         Some(x) => x,
         None => {
             println!("Error processing input file(s)");
-            std::process::exit(1);
+            bail(&config, &lock);
         }
     };
     println!("Processed {} local records.", local_recs.0.len());
+
+    // Everything in the input has to be Macrotis's to manage
+    if !ownership::check_local(&config.zones, &local_recs) {
+        println!("Error: input has records Macrotis doesn't own");
+        bail(&config, &lock);
+    }
+
+    // Health checks have to make sense before records can use them
+    let no_checks = HashMap::new();
+    let local_checks = config.health_checks.as_ref().unwrap_or(&no_checks);
+    if !health::validate(local_checks) || !health::check_names(&local_recs, local_checks) {
+        println!("Error in health checks");
+        bail(&config, &lock);
+    }
     
     // Exit now if 'lint' subcommand provided
     if sub == 0 {
@@ -65,70 +195,470 @@ fn main() {
 
     // Load and parse statefile to populate 'state' - Note that state could
     // be empty if this is the first run!
-    let st = match state::load_state(&config) {
+    let mut st = match state::load_state(&config) {
         Some(x) => x,
         None => {
             println!("Error processing statefile, bailing out.");
-            std::process::exit(1);
+            bail(&config, &lock);
         }
     };
     println!("Statefile: {}", st);
-    let mut state_recs = st.records;
-    
+    let state_serial = st.serial;
+    let mut state_recs = st.records.clone();
+    ownership::filter_state(&config.zones, &mut state_recs);
 
     // Load and parse remote provider zones to populate 'remote' - Note that
     // these could also be empty!  Bail out on errors.
-    let remote_recs = match load_remote(&config) {
+    let mut remote_recs = match load_remote(prov.as_ref(), &config) {
         Some(x) => x,
         None => {
             println!("Error downloading remote records, bailing out.");
-            std::process::exit(1);
+            bail(&config, &lock);
         }
     };
     println!("Got {} resources from remote", remote_recs.0.len());
 
+    // Set aside whatever Macrotis doesn't own so nothing gets planned for
+    // it, and mark what it does own if the zone asks for that
+    let unowned = ownership::split_remote(&config.zones, &mut remote_recs, &state_recs);
+    ownership::report(&unowned);
+    ownership::drop_conflicts(&mut local_recs, &unowned);
+    ownership::add_markers(&config.zones, &mut local_recs);
+
+    // Same for health checks, if there are any to look after.  Remote
+    // records get them by name, the way the input has them.
+    if !local_checks.is_empty() || !st.health_checks.is_empty() {
+        let remote_checks = match prov.fetch_health_checks() {
+            Some(x) => x,
+            None => {
+                println!("Error downloading health checks, bailing out.");
+                bail(&config, &lock);
+            }
+        };
+        health::state_remote(&mut st.health_checks, &remote_checks);
+        health::name_records(remote_recs.0.values_mut(), &st.health_checks);
+    }
+    let hplan = health::diff(local_checks, &st.health_checks);
+    if !health::check_records(&local_recs, &hplan) {
+        bail(&config, &lock);
+    }
+
+    // Load the plan now, before state_remote touches anything, so it can
+    // be checked against exactly what it was made from.
+    let saved_plan = match sub {
+        5 => {
+            let fname = matches.subcommand_matches("apply").unwrap().value_of("PLANFILE").unwrap();
+            let p = match plan::load_plan(fname) {
+                Some(x) => x,
+                None => {
+                    println!("Error loading plan file, bailing out.");
+                    bail(&config, &lock);
+                }
+            };
+            println!("{}", p);
+            let problems = p.check(state_serial, &local_recs, &remote_recs, local_checks, &hplan);
+            if !problems.is_empty() {
+                for x in problems {
+                    println!("Plan is stale: {}", x);
+                }
+                println!("Refusing to apply; run 'noop --out' again.");
+                bail(&config, &lock);
+            }
+            Some(p)
+        },
+        _ => None
+    };
+
     // Compare statefile records with remote records to ensure state accurately
     // reflects the 'source of truth'
     compare::state_remote(&mut state_recs, &remote_recs);
 
-    // Compare local records with updated statefile records to see what changes
-    // need to be sent to remote.
-    let (mut new_recs, mut upd_recs, del_recs) = compare::local_state(&local_recs, &state_recs);
+    // Turn the changes into something a little more palatable -
+    // simple &str,Vec<Resource> hashes where the &str part matches
+    // an AWS action (CREATE, UPSERT, DELETE).  'apply' takes them straight
+    // from the plan, everything else works them out.
+    let to_push: HashMap<&str, Vec<Resource>> = match saved_plan {
+        Some(p) => {
+            let empty = || ResHash(HashMap::new());
+            health::print_plan(&hplan, local_checks, &st.health_checks);
+            output_changes(&resource::build_reshash(p.create.clone()).unwrap_or_else(empty),
+                           &resource::build_reshash(p.upsert.clone()).unwrap_or_else(empty),
+                           &resource::build_reshash(p.delete.clone()).unwrap_or_else(empty),
+                           &state_recs, &remote_recs, &hplan);
+            p.changes()
+        },
+        None => {
+            // Compare local records with updated statefile records to see
+            // what changes need to be sent to remote.
+            let (mut new_recs, mut upd_recs, del_recs) = compare::local_state(&local_recs, &state_recs);
 
-    // Compare the 'new' change set to the remote records, since it contains
-    // records the statefile is unaware of but which might already exist
-    // remotely.
-    compare::new_remote(&mut new_recs, &mut upd_recs, &remote_recs);
-    
-    // Print out changes to be pushed
-    output_changes(&new_recs, &upd_recs, &del_recs, &state_recs);
+            // Compare the 'new' change set to the remote records, since it
+            // contains records the statefile is unaware of but which might
+            // already exist remotely.
+            compare::new_remote(&mut new_recs, &mut upd_recs, &remote_recs);
+
+            // Records whose health check is being replaced need pointing at
+            // the new one
+            health::repoint(&local_recs, &hplan, &new_recs, &mut upd_recs);
+
+            // Print out changes to be pushed
+            health::print_plan(&hplan, local_checks, &st.health_checks);
+            output_changes(&new_recs, &upd_recs, &del_recs, &state_recs, &remote_recs, &hplan);
+
+            // Exit now if 'noop' subcommand provided, saving the plan first
+            // if asked to
+            if sub == 1 {
+                let out = matches.subcommand_matches("noop").and_then(|m| m.value_of("out"));
+                if let Some(fname) = out {
+                    // Records in zones that don't exist yet have no zone ID
+                    // to plan them against
+                    if !zones::pending(&config.zones).is_empty() {
+                        println!("Can't save a plan until missing hosted zones are created; run 'execute' first.");
+                        bail(&config, &lock);
+                    }
+                    let p = plan::MacrotisPlan::new(state_serial, &local_recs, &remote_recs,
+                                                    new_recs, upd_recs, del_recs)
+                        .with_health(&local_recs, local_checks, &hplan);
+                    if !plan::save_plan(fname, &p) {
+                        bail(&config, &lock);
+                    }
+                    println!("Saved plan to {}", fname);
+                }
+                return;
+            }
+
+            let mut to_push = HashMap::new();
+            to_push.insert("CREATE", resource::hash_to_vec(new_recs));
+            to_push.insert("UPSERT", resource::hash_to_vec(upd_recs));
+            to_push.insert("DELETE", resource::hash_to_vec(del_recs));
+            to_push
+        }
+    };
+
+    // Health checks go first, so records can start using new ones.  Ones
+    // that are no longer needed wait until records have stopped using them.
+    let old_checks = st.health_checks.clone();
+    let retired = match health::apply_plan(prov.as_ref(), &hplan, local_checks, &mut st.health_checks, state_serial) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error updating health checks, bailing out: {}", e);
+            bail(&config, &lock);
+        }
+    };
+
+    // Records name their health checks, but the provider wants IDs.
+    // Deletes have to match what's there, so they get the old ones.
+    let to_push: HashMap<&str, Vec<Resource>> = to_push.iter().map(|(action, recs)| {
+        let checks = if *action == "DELETE" { &old_checks } else { &st.health_checks };
+        (*action, health::resolve_records(recs, checks))
+    }).collect();
 
-    // Exit now if 'noop' subcommand provided
-    if sub != 2 {
-		return;
-	}
-	
-	// Turn those ResHashes into something a little more palatable - 
-	// simple &str,Vec<Resource> hashes where the &str part matches
-	// an AWS action (CREATE, UPSERT, DELETE).
-	let mut to_push: HashMap<&str, Vec<Resource>> = HashMap::new();
-	to_push.insert("CREATE", resource::hash_to_vec(new_recs));
-	to_push.insert("UPSERT", resource::hash_to_vec(upd_recs));
-	to_push.insert("DELETE", resource::hash_to_vec(del_recs));
-	
     // Finally, send the changes up to the remote provider
-    match push_remote(&config, &to_push) {
-		true => {
+    let change_ids = match push_remote(prov.as_ref(), &to_push) {
+		Some(x) => {
 			println!("Successfully pushed changes.");
+			x
 		},
-		false => {
+		None => {
 			println!("Error pushing changes, bailing out.");
-			std::process::exit(1);
+			bail(&config, &lock);
 		}
 	};
-	
+
     // Make the current local into the new state and write the new statefile
-    state::save_state(&config, local_recs);
+    if !state::save_state(&config, local_recs, &st) {
+        println!("Error saving statefile!");
+        bail(&config, &lock);
+    }
+
+    // Only now that the statefile has forgotten them can retired checks go.
+    // If the save failed they're left alone; the statefile still lists them,
+    // so the next run retires them again.
+    health::delete_checks(prov.as_ref(), &retired);
+    if let Some(l) = lock {
+        if !state::unlock_state(&config, &l) {
+            std::process::exit(1);
+        }
+    }
+
+    // The changes are in and the statefile knows it, but they may not have
+    // reached every nameserver yet.  Wait for that unless told not to.
+    let timeout = config.provider.wait_timeout.unwrap_or(provider::DEFAULT_WAIT_TIMEOUT);
+    let interval = config.provider.poll_interval.unwrap_or(provider::DEFAULT_POLL_INTERVAL);
+    if timeout > 0 && !provider::wait_for_changes(prov.as_ref(), &change_ids, timeout, interval) {
+        std::process::exit(1);
+    }
+}
+
+// Run one of the 'state' subcommands that don't push anything
+fn state_command(matches: &clap::ArgMatches, config: &MacrotisConfig) -> bool {
+    let (name, args) = matches.subcommand();
+    let args = match args {
+        Some(x) => x,
+        None => {
+            println!("Missing state subcommand. Use 'macrotis state --help' for usage");
+            return false;
+        }
+    };
+
+    // The ones that change the statefile hold the lock while they do it
+    match name {
+        "rm" | "mv" | "push" => {
+            let lock = match state::lock_state(&config, &format!("state {}", name)) {
+                Some(x) => x,
+                None => {
+                    println!("Couldn't lock statefile, bailing out.");
+                    return false;
+                }
+            };
+            let ok = state_edit(name, args, &config);
+            state::unlock_state(&config, &lock) && ok
+        },
+        _ => state_read(name, args, &config)
+    }
+}
+
+// The 'state' subcommands that change the statefile: rm, mv, and push
+fn state_edit(name: &str, args: &clap::ArgMatches, config: &MacrotisConfig) -> bool {
+    let mut st = match state::load_state(&config) {
+        Some(x) => x,
+        None => {
+            println!("Error processing statefile");
+            return false;
+        }
+    };
+    let mut recs = st.records.clone();
+    match name {
+        "rm" => {
+            // Only forget about it; the remote record is left alone
+            let key = args.value_of("KEY").unwrap();
+            match recs.0.remove(key) {
+                Some(x) => println!("Removed {} {} from state", x.rtype, x.name),
+                None => {
+                    println!("No resource {} in state", key);
+                    return false;
+                }
+            };
+        },
+        "mv" => {
+            let from = args.value_of("FROM").unwrap();
+            let to = args.value_of("TO").unwrap();
+            if recs.0.contains_key(to) {
+                println!("{} is already in state", to);
+                return false;
+            }
+            match recs.0.remove(from) {
+                Some(x) => { recs.0.insert(to.to_string(), x); },
+                None => {
+                    println!("No resource {} in state", from);
+                    return false;
+                }
+            };
+            println!("Moved {} to {}", from, to);
+        },
+        _ => {
+            // Push: the file has to have come from the statefile as it is
+            // now, unless forced
+            let fname = args.value_of("FILE").unwrap();
+            let pushed = match state::read_state_file(&config.statefile, &fname) {
+                Some(x) => x,
+                None => { return false; }
+            };
+            if pushed.serial != st.serial && !args.is_present("force") {
+                println!("{} has serial {} but the statefile is at {}; use --force to push it anyway",
+                         fname, pushed.serial, st.serial);
+                return false;
+            }
+            recs = pushed.records;
+            st.health_checks = pushed.health_checks;
+            println!("Pushing {} resources and {} health checks from {}",
+                     recs.0.len(), st.health_checks.len(), fname);
+        }
+    };
+    if !state::save_state(&config, recs, &st) {
+        println!("Error saving statefile!");
+        return false;
+    }
+    true
+}
+
+// The 'state' subcommands that only read the statefile
+fn state_read(name: &str, args: &clap::ArgMatches, config: &MacrotisConfig) -> bool {
+    match name {
+        "list" => {
+            let st = match state::load_state(&config) {
+                Some(x) => x,
+                None => { return false; }
+            };
+            let mut keys: Vec<&String> = st.records.0.keys().collect();
+            keys.sort();
+            for k in keys {
+                println!("{}", k);
+            }
+            true
+        },
+        "show" => {
+            let st = match state::load_state(&config) {
+                Some(x) => x,
+                None => { return false; }
+            };
+            let key = args.value_of("KEY").unwrap();
+            match st.records.0.get(key) {
+                Some(x) => {
+                    println!("{}", serde_json::to_string_pretty(x).unwrap_or_default());
+                    true
+                },
+                None => {
+                    println!("No resource {} in state", key);
+                    false
+                }
+            }
+        },
+        "pull" => {
+            let st = match state::load_state(&config) {
+                Some(x) => x,
+                None => { return false; }
+            };
+            let fname = args.value_of("FILE").unwrap();
+            let json = match serde_json::to_string_pretty(&st) {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("Error serializing state to JSON: {}", e);
+                    return false;
+                }
+            };
+
+            // Keep it encrypted like the statefile is, unless asked not to
+            let json = if args.is_present("plaintext") {
+                json
+            } else {
+                match state::seal_state(&config.statefile, json) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        println!("Error encrypting statefile: {}", e);
+                        return false;
+                    }
+                }
+            };
+            match state::save_local_state(&fname, &json) {
+                Ok(_) => {
+                    println!("Saved {} to {}", st, fname);
+                    true
+                },
+                Err(_) => false
+            }
+        },
+        "history" => {
+            let serials = match state::list_history(&config) {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("Error listing statefile history: {}", e);
+                    return false;
+                }
+            };
+            if serials.is_empty() {
+                println!("No saved statefiles");
+            }
+            for serial in serials {
+                match state::load_history(&config, serial) {
+                    Some(x) => println!("{}", x),
+                    None => println!("Serial {}: unreadable", serial)
+                };
+            }
+            true
+        },
+        _ => {
+            println!("Unknown state subcommand. Use 'macrotis state --help' for usage");
+            false
+        }
+    }
+}
+
+// Run one of the 'zone' subcommands: list the configured zones along with
+// their nameservers, or delete one that's been emptied out
+fn zone_command(matches: &clap::ArgMatches, config: &mut MacrotisConfig) -> bool {
+    let prov = match provider::build_provider(&config.provider) {
+        Some(x) => x,
+        None => {
+            println!("Error setting up provider {}", &config.provider.name);
+            return false;
+        }
+    };
+    match matches.subcommand() {
+        ("list", Some(_)) => {
+            let mut ok = true;
+            for z in config.zones.iter_mut() {
+                if !zones::resolve_zone(prov.as_ref(), z, false) {
+                    ok = false;
+                    continue;
+                }
+                if z.id.is_empty() {
+                    continue;
+                }
+                println!("{} ({}): {}", z.name, z.domain, z.id);
+                ok &= zones::print_nameservers(prov.as_ref(), z, &z.id);
+            }
+            ok
+        },
+        ("delete", Some(args)) => {
+            let domain = args.value_of("DOMAIN").unwrap();
+            let idx = match config.zones.iter().position(|z| z.domain == domain || z.name == domain) {
+                Some(x) => x,
+                None => {
+                    println!("No zone {} in the config", domain);
+                    return false;
+                }
+            };
+            if !zones::resolve_zone(prov.as_ref(), &mut config.zones[idx], false) {
+                return false;
+            }
+
+            // Hold the lock so nothing adds records to it in the meantime
+            let lock = match state::lock_state(&config, "zone delete") {
+                Some(x) => x,
+                None => {
+                    println!("Couldn't lock statefile, bailing out.");
+                    return false;
+                }
+            };
+            let ok = match state::load_state(&config) {
+                Some(st) => zones::delete_zone(prov.as_ref(), &config.zones[idx], &st.records),
+                None => {
+                    println!("Error processing statefile");
+                    false
+                }
+            };
+            state::unlock_state(&config, &lock) && ok
+        },
+        _ => {
+            println!("Missing zone subcommand. Use 'macrotis zone --help' for usage");
+            false
+        }
+    }
+}
+
+// Load the records from the saved statefile 'state rollback' asked for,
+// to be treated like local input
+fn rollback_records(matches: &clap::ArgMatches, config: &MacrotisConfig) -> Option<ResHash> {
+    let serial = matches.subcommand_matches("rollback")?.value_of("SERIAL")?;
+    let serial = match serial.parse::<u64>() {
+        Ok(x) => x,
+        Err(_) => {
+            println!("Bad serial {}", serial);
+            return None;
+        }
+    };
+    let st = state::load_history(&config, serial)?;
+    println!("Rolling back to {}", st);
+    Some(st.records)
+}
+
+// Release the statefile lock, if one was taken, and exit with an error
+fn bail(config: &MacrotisConfig, lock: &Option<state::MacrotisLock>) -> ! {
+    if let Some(l) = lock {
+        state::unlock_state(config, l);
+    }
+    std::process::exit(1);
 }
 
 // Load in a config file and deserialize it into a MacrotisConfig struct
@@ -151,6 +681,9 @@ fn load_config(fname: &str) -> Option<MacrotisConfig> {
             return None;
         }
     };
+    if !zones::check_domains(&retval.zones) {
+        return None;
+    }
 
     Some(retval)
 }
@@ -164,7 +697,7 @@ fn load_local(fname: &str, config: &MacrotisConfig) -> Option<ResHash> {
         Ok(x) => x,
         Err(e) => {
             println!("Error reading {}: {}", fname, e);
-            std::process::exit(1);
+            return None;
         }
     };
 
@@ -173,14 +706,16 @@ fn load_local(fname: &str, config: &MacrotisConfig) -> Option<ResHash> {
     if meta.is_file() {
         println!("Processing {}", &fname);
         let tdns_records = match tinydns::from_file(&fname) {
-            Some(x) => x,
-            None => {
+            Ok(x) => x,
+            Err(errs) => {
+                print_parse_errors(&errs);
                 println!("Error processing input file {}", fname);
                 return None;
             }
         };
+        let tdns_records = active_records(tdns_records);
         println!("Converting TinyDNS records...");
-        let converted = match resource::vec_from_tiny(&tdns_records, &config.zones) {
+        let converted = match resource::vec_from_tiny(&tdns_records, &config) {
             Some(x) => x,
             None => {
                 println!("Error converting TDRs to Resources");
@@ -221,8 +756,9 @@ fn load_local(fname: &str, config: &MacrotisConfig) -> Option<ResHash> {
                         if ext == "tiny" {
                             println!("Processing {}...", &pathstring);
                             let mut recs = match tinydns::from_file(&pathstring) {
-                                Some(x) => x,
-                                None => {
+                                Ok(x) => x,
+                                Err(errs) => {
+                                    print_parse_errors(&errs);
                                     println!("Error processing {}", pathstring);
                                     error_flag = true;
                                     continue;
@@ -241,8 +777,9 @@ fn load_local(fname: &str, config: &MacrotisConfig) -> Option<ResHash> {
                     continue;
                 }
             } // End of loop, convert the big vec
+            let tdns_vec = active_records(tdns_vec);
             println!("Converting TinyDNS records...");
-            let converted = match resource::vec_from_tiny(&tdns_vec, &config.zones) {
+            let converted = match resource::vec_from_tiny(&tdns_vec, &config) {
                 Some(x) => x,
                 None => {
                     println!("Error converting TDRs to Resources");
@@ -268,12 +805,34 @@ fn load_local(fname: &str, config: &MacrotisConfig) -> Option<ResHash> {
     }
 }
 
-// Load and parse remote records
-fn load_remote(config: &MacrotisConfig) -> Option<ResHash> {
-    let prov = &config.provider;
+// Drop records whose TinyDNS timestamps say they shouldn't be served yet
+// (or anymore), letting the user know how many were skipped.
+fn active_records(recs: Vec<tinydns::TinyDNSRecord>) -> Vec<tinydns::TinyDNSRecord> {
+    let total = recs.len();
+    let retval = tinydns::filter_active(recs);
+    if retval.len() < total {
+        println!("Skipping {} record(s) outside their timestamp window", total - retval.len());
+    }
+    retval
+}
+
+// Print out every parse error from an input file
+fn print_parse_errors(errs: &Vec<tinydns::ParseError>) {
+    for e in errs {
+        println!("{}", e);
+    }
+    println!("{} error(s) found", errs.len());
+}
+
+// Load and parse remote records from whichever provider is configured
+fn load_remote(prov: &dyn DnsProvider, config: &MacrotisConfig) -> Option<ResHash> {
     let mut resources = Vec::new();
     for z in &config.zones {
-		match r53::bulk_fetch(prov, &z.id) {
+		// Zones still to be created have nothing in them yet
+		if z.id.is_empty() {
+			continue;
+		}
+		match prov.fetch_zone(&z.id) {
 			Some(mut x) => { resources.append(&mut x); },
 			None => { println!("No records for zone {}", z.name); }
 		};
@@ -289,60 +848,241 @@ fn load_remote(config: &MacrotisConfig) -> Option<ResHash> {
 }
 
 
+// Rewrite the input file (or every *.tiny file in the input dir) in
+// canonical TinyDNS form.  Files with parse errors are left alone.
+fn format_files(fname: &str) -> bool {
+    let path = Path::new(&fname);
+    let mut files = Vec::new();
+    if path.is_dir() {
+        let dir_iter = match std::fs::read_dir(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Error reading {}: {}", fname, e);
+                return false;
+            }
+        };
+        for entry in dir_iter {
+            if let Ok(f) = entry {
+                let fpath = f.path();
+                if fpath.is_file() && fpath.extension().map_or(false, |x| x == "tiny") {
+                    files.push(fpath);
+                }
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut ok = true;
+    for fpath in files {
+        let text = match std::fs::read_to_string(&fpath) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Error reading {}: {}", fpath.display(), e);
+                ok = false;
+                continue;
+            }
+        };
+        let formatted = match writer::format_text(&text) {
+            Ok(x) => x,
+            Err(mut errs) => {
+                for e in errs.iter_mut() {
+                    e.file = fpath.display().to_string();
+                }
+                print_parse_errors(&errs);
+                println!("Not formatting {}", fpath.display());
+                ok = false;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if let Err(e) = std::fs::write(&fpath, formatted) {
+            println!("Error writing {}: {}", fpath.display(), e);
+            ok = false;
+            continue;
+        }
+        println!("Formatted {}", fpath.display());
+    }
+    ok
+}
+
+// Fetch every configured zone from the remote provider and write each one
+// out as DIR/<zone name>.tiny, then save a statefile to match so the next
+// run doesn't see any changes.  Won't overwrite existing files.
+fn import_remote(dir: &str, config: &mut MacrotisConfig) -> bool {
+    let prov = match provider::build_provider(&config.provider) {
+        Some(x) => x,
+        None => {
+            println!("Error setting up provider {}", &config.provider.name);
+            return false;
+        }
+    };
+    if !zones::resolve(prov.as_ref(), &mut config.zones, false) {
+        return false;
+    }
+    if let Some(z) = zones::pending(&config.zones).first() {
+        println!("Hosted zone for {} doesn't exist yet, there's nothing to import", z.domain);
+        return false;
+    }
+    let path = Path::new(&dir);
+    if let Err(e) = std::fs::create_dir_all(&path) {
+        println!("Error creating {}: {}", dir, e);
+        return false;
+    }
+
+    // The state being replaced, so the new one gets the next serial
+    let st = match state::load_state(&config) {
+        Some(x) => x,
+        None => {
+            println!("Error processing statefile");
+            return false;
+        }
+    };
+
+    // Make sure we won't clobber anything before fetching
+    for z in &config.zones {
+        let fpath = path.join(format!("{}.tiny", z.name));
+        if fpath.exists() {
+            println!("{} already exists, refusing to overwrite it", fpath.display());
+            return false;
+        }
+    }
+
+    let mut resources: Vec<Resource> = Vec::new();
+    for z in &config.zones {
+        match prov.fetch_zone(&z.id) {
+            Some(mut x) => {
+                println!("Fetched {} resources from zone {}", x.len(), z.name);
+                resources.append(&mut x);
+            },
+            None => {
+                println!("Error fetching zone {}", z.name);
+                return false;
+            }
+        };
+    }
+
+    // Leave out records Macrotis doesn't own, and ownership markers
+    let total = resources.len();
+    resources.retain(|r| ownership::importable(&config.zones, r));
+    if resources.len() < total {
+        println!("Skipping {} resource(s) Macrotis doesn't own", total - resources.len());
+    }
+
+    // Turn it all into TinyDNS and write out a file per zone, naming the
+    // health checks the statefile knows about
+    health::name_records(resources.iter_mut(), &st.health_checks);
+    let (mut by_zone, exported) = writer::export(&resources);
+    for z in &config.zones {
+        let fpath = path.join(format!("{}.tiny", z.name));
+        let mut contents = format!("# Imported from zone {} ({})\n", z.id, z.domain);
+        for line in by_zone.remove(&z.id).unwrap_or(Vec::new()) {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        if let Err(e) = std::fs::write(&fpath, contents) {
+            println!("Error writing {}: {}", fpath.display(), e);
+            return false;
+        }
+        println!("Wrote {}", fpath.display());
+    }
+
+    // And the statefile to go with it
+    println!("Imported {} of {} resources", exported.len(), total);
+    let recs = match resource::build_reshash(exported) {
+        Some(x) => x,
+        None => {
+            println!("Error building ResHash");
+            return false;
+        }
+    };
+    if !state::save_state(&config, recs, &st) {
+        println!("Error saving statefile!");
+        return false;
+    }
+    true
+}
+
 // Push records up to remote
 // 'resources' should be a HashMap where the key is an action to take
-// (create, upsert, delete), and the values are Vecs of Resources
-fn push_remote(config: &MacrotisConfig, resources: &HashMap<&str,Vec<Resource>>) -> bool {
-	let mut retval = true;
-	let prov = &config.provider;
-	let mut by_zone: HashMap<&str, Vec<rusoto_route53::Change>> = HashMap::new();
+// (create, upsert, delete), and the values are Vecs of Resources.
+// Returns the IDs of every change submitted, or None on any error.
+fn push_remote(prov: &dyn DnsProvider, resources: &HashMap<&str,Vec<Resource>>) -> Option<Vec<String>> {
+	let mut retval = Some(Vec::new());
+	let mut by_zone: HashMap<&str, Vec<(String, Resource)>> = HashMap::new();
 	
-	// So for each of the possible actions, we want to turn the Resource
-	// struct into a rusoto_r53::Change struct, while simultaneously
-	// separating the Resources by their zone_id.  Because Route53 
-	// allows us to send multiple types of changes together so long as
-	// they are all within a single HostedZone, we should be able to do
-	// something that goes...a little bit a-like a-dis:
-	for (action, res) in resources {
+	// So for each of the possible actions, we want to pair the action up
+	// with the Resource while simultaneously separating the Resources by
+	// their zone_id.  Providers (Route53 at least) allow us to send
+	// multiple types of changes together so long as they are all within
+	// a single zone, so we should be able to do something that goes...a
+	// little bit a-like a-dis.  Deletes go first, so a record set that's
+	// being replaced by one with a different set identifier (a simple
+	// record becoming weighted, say) is out of the way before the new
+	// one is created.
+	for action in &["DELETE", "UPSERT", "CREATE"] {
+		let res = match resources.get(action) {
+			Some(x) => x,
+			None => { continue; }
+		};
 		for rec in res {
 			let z = &rec.zone_id[..];
-			let chg = r53::resource_to_change(&action, &rec);
-            by_zone.entry(z.clone()).or_insert(vec![]).push(chg);
+			let chg = (action.to_string(), rec.clone());
+            by_zone.entry(z).or_insert(vec![]).push(chg);
 		}
 	}
 	
-	// Now iterate through that by_zone hashmap and call bulk_put for
-	// each one.
+	// Now iterate through that by_zone hashmap and hand each one off
+	// to the provider.
 	for (zoneid, chgvec) in by_zone {
-		match r53::bulk_put(&prov, chgvec, &zoneid) {
-			Ok(x) => { println!("Change IDs: {}", x); },
-			Err(e) => { println!("Error! {}", e); retval = false; }
+		match prov.apply_changes(&zoneid, &chgvec) {
+			Ok(mut x) => {
+				println!("Change IDs: {}", x.join(", "));
+				if let Some(ref mut ids) = retval {
+					ids.append(&mut x);
+				}
+			},
+			Err(e) => { println!("Error! {}", e); retval = None; }
 		};
 	}
     retval
 }
 
+// Type and name of a changed Resource, plus the set identifier so routed
+// record sets with the same name can be told apart
+fn change_label(res: &Resource) -> String {
+	match &res.set_identifier {
+		Some(x) => format!("{} {} ({})", res.rtype, res.name, x),
+		None => format!("{} {}", res.rtype, res.name)
+	}
+}
+
 // Iterate through the ResHashes of changes and print out what needs to
 // be done to bring Remote in line with Local.  Returns 'false' if there
-// are no changes to push.
-fn output_changes(ne: &ResHash, up: &ResHash, de: &ResHash, st: &ResHash) -> bool {
+// are no changes to push, health checks included.  Updated records that
+// the statefile didn't know about get their old values from the remote
+// records instead.
+fn output_changes(ne: &ResHash, up: &ResHash, de: &ResHash, st: &ResHash, re: &ResHash, hplan: &HealthPlan) -> bool {
 	for (_k, v) in &ne.0 {
-		println!("[ADD] {} {}\t [ ] -> {:?}", &v.rtype, &v.name, &v.records);
+		println!("[ADD] {}\t [ ] -> {:?}", change_label(v), &v.records);
 	}
 	for (k, v) in &up.0 {
-		let oldres = match st.0.get(k) {
+		let oldres = match st.0.get(k).or(re.0.get(k)) {
 			Some(x) => x,
 			None => {
 				println!("Failed to get value for key {} in state", k);
 				continue;
 			}
 		};
-		println!("[UPD] {} {}\t {:?} -> {:?}", &v.rtype, &v.name, &oldres.records, &v.records);
+		println!("[UPD] {}\t {:?} -> {:?}", change_label(v), &oldres.records, &v.records);
 	}
 	for (_k, v) in &de.0 {
-		println!("[DEL] {} {}\t {:?} -> [ ]", &v.rtype, &v.name, &v.records);
+		println!("[DEL] {}\t {:?} -> [ ]", change_label(v), &v.records);
 	}
-	if ne.0.len() < 1 && up.0.len() < 1 && de.0.len() < 1 {
+	if ne.0.len() < 1 && up.0.len() < 1 && de.0.len() < 1 && hplan.is_empty() {
 		println!("No changes detected.");
 		false
 	} else {
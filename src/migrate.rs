@@ -0,0 +1,137 @@
+// Module for upgrading statefiles written by older versions of Macrotis.
+// Migrations work on the raw JSON so they can cope with layouts the
+// current structs can't read.
+
+use std::collections::HashSet;
+use serde_json::{Value, Map};
+use resource::{self, Resource};
+use tinydns::TinyDNSRecord;
+
+// The statefile layout this version of Macrotis writes
+//  1 - Original layout: TXT values stored without quotes (the last
+//      releases to write it quote them already)
+//  2 - TXT values quoted the way Route53 has them, keys include the
+//      set identifier of routed records
+//  3 - Health checks Macrotis has made are kept alongside the records
+pub const STATE_VERSION: u32 = 3;
+
+// A migration takes a statefile from one version to the next
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+// MIGRATIONS[n] upgrades version n+1 to version n+2
+const MIGRATIONS: [Migration; 2] = [v1_to_v2, v2_to_v3];
+
+// Bring a parsed statefile up to STATE_VERSION one step at a time.
+// Returns the version it started at if anything had to change, and
+// refuses statefiles from a newer Macrotis.
+pub fn upgrade(state: &mut Value) -> Result<Option<u32>, String> {
+    let version = match state.get("version").and_then(|x| x.as_u64()) {
+        Some(x) => x as u32,
+        None => { return Err("statefile has no version".to_string()); }
+    };
+    if version > STATE_VERSION {
+        return Err(format!("statefile is version {}, but this Macrotis only understands up to version {}; upgrade Macrotis",
+                           version, STATE_VERSION));
+    }
+    if version == STATE_VERSION {
+        return Ok(None);
+    }
+    if version == 0 {
+        return Err("statefile has invalid version 0".to_string());
+    }
+    for v in version..STATE_VERSION {
+        MIGRATIONS[(v - 1) as usize](state)?;
+        state["version"] = Value::from(v + 1);
+    }
+    Ok(Some(version))
+}
+
+// Version 1 -> 2: quote TXT values, and re-key every resource so the keys
+// match what build_reshash makes now.
+fn v1_to_v2(state: &mut Value) -> Result<(), String> {
+    let records = match state.get("records").and_then(|x| x.as_object()) {
+        Some(x) => x.clone(),
+        None => { return Err("statefile has no records".to_string()); }
+    };
+    let mut upgraded = Map::new();
+    let mut seen = HashSet::new();
+    for (k, v) in records {
+        let mut res: Resource = match serde_json::from_value(v) {
+            Ok(x) => x,
+            Err(e) => { return Err(format!("can't read resource {}: {}", k, e)); }
+        };
+        if res.rtype == "TXT" {
+            res.records = res.records.iter().map(|x| quote_txt(x)).collect();
+        }
+        let key = resource::resource_key(&res);
+        if !seen.insert(key.to_string()) {
+            return Err(format!("two resources would both become {}", key));
+        }
+        let value = match serde_json::to_value(&res) {
+            Ok(x) => x,
+            Err(e) => { return Err(e.to_string()); }
+        };
+        upgraded.insert(key, value);
+    }
+    state["records"] = Value::Object(upgraded);
+    Ok(())
+}
+
+// Version 2 -> 3: no health checks yet.  Older versions of Macrotis would
+// drop the ones a newer version made, so they mustn't read these.
+fn v2_to_v3(state: &mut Value) -> Result<(), String> {
+    match state.as_object_mut() {
+        Some(x) => {
+            x.insert("health_checks".to_string(), Value::Object(Map::new()));
+            Ok(())
+        },
+        None => Err("statefile isn't a JSON object".to_string())
+    }
+}
+
+// Quote an old-style TXT value, unless it's already been done
+fn quote_txt(value: &str) -> String {
+    if value.starts_with('"') {
+        return value.to_string();
+    }
+    let tdr = TinyDNSRecord {
+        rtype: "TXT".to_string(),
+        target: value.to_string(),
+        ..Default::default()
+    };
+    resource::record_value(&tdr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_v1() {
+        let mut state: Value = serde_json::from_str(r#"{
+            "version": 1, "appversion": "0.6.22", "serial": 5,
+            "records": {
+                "TXT-Test-com": { "zone_id": "Z1", "name": "test.com", "rtype": "TXT",
+                                  "records": ["v=spf1 -all"], "ttl": 300 },
+                "a-foo-test-com": { "zone_id": "Z1", "name": "foo.test.com", "rtype": "A",
+                                    "records": ["1.2.3.4"], "ttl": 300 }
+            }
+        }"#).unwrap();
+        assert_eq!(upgrade(&mut state).unwrap(), Some(1));
+        assert_eq!(state["version"], 3);
+        assert!(state["health_checks"].as_object().unwrap().is_empty());
+        assert_eq!(state["records"]["txt-test-com"]["records"][0], "\"v=spf1 -all\"");
+        assert_eq!(state["records"]["a-foo-test-com"]["records"][0], "1.2.3.4");
+
+        // Nothing left to do the second time around
+        assert_eq!(upgrade(&mut state).unwrap(), None);
+    }
+
+    #[test]
+    fn test_upgrade_refuses_newer() {
+        let mut state: Value = serde_json::from_str(r#"{ "version": 99, "records": {} }"#).unwrap();
+        assert!(upgrade(&mut state).unwrap_err().contains("version 99"));
+        let mut state: Value = serde_json::from_str(r#"{ "records": {} }"#).unwrap();
+        assert!(upgrade(&mut state).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+// In-memory stand-ins for a DNS provider and a state backend, for running
+// the whole fetch/compare/push/save cycle without talking to AWS.  Either
+// one can optionally be backed by a JSON fixture file so that changes
+// survive between runs (handy for testing the binary end-to-end).
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use {MacrotisProviderConfig, MacrotisStateConfig, Zone};
+use resource::Resource;
+use health::HealthCheck;
+use zones::RemoteZone;
+use provider::DnsProvider;
+use state::{self, MacrotisState, MacrotisLock};
+
+// Fake remote zones, keyed by zone_id.  If 'fixture' is set, the zones are
+// loaded from that file and written back out after every change.
+// Submitted changes report PENDING for 'pending_polls' status checks
+// before going INSYNC, so waiting on them can be tested too.  Health
+// checks, keyed by ID, go in a second file alongside the fixture, and
+// hosted zones made with create_zone go in a third.  Zones that are only
+// in the fixture can be used by ID but not looked up by name.
+pub struct MockProvider {
+	fixture: Option<String>,
+	zones: RefCell<HashMap<String, Vec<Resource>>>,
+	pending_polls: u32,
+	changes: RefCell<HashMap<String, u32>>,
+	checks: RefCell<HashMap<String, MockCheck>>,
+	hosted: RefCell<HashMap<String, MockZone>>
+}
+
+// A fake health check, with the reference and name it was created with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MockCheck {
+	pub reference: String,
+	pub name: String,
+	pub check: HealthCheck
+}
+
+// A fake hosted zone
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MockZone {
+	pub domain: String,
+	pub private: bool,
+	pub nameservers: Vec<String>
+}
+
+// Read a JSON fixture file, if it's there
+fn load_fixture<T: serde::de::DeserializeOwned + Default>(fname: &str) -> Option<T> {
+	match File::open(fname) {
+		Ok(f) => {
+			match serde_json::from_reader(BufReader::new(f)) {
+				Ok(x) => Some(x),
+				Err(e) => {
+					println!("Error parsing mock fixture {}: {}", fname, e);
+					None
+				}
+			}
+		},
+		Err(_) => Some(T::default())
+	}
+}
+
+// Write a JSON fixture file out
+fn save_fixture<T: serde::Serialize>(fname: &str, contents: &T) -> Result<bool, String> {
+	let outstring = match serde_json::to_string_pretty(contents) {
+		Ok(x) => x,
+		Err(e) => { return Err(e.to_string()); }
+	};
+	let f = match File::create(fname) {
+		Ok(x) => x,
+		Err(e) => { return Err(e.to_string()); }
+	};
+	let mut writer = BufWriter::new(f);
+	match writer.write_all(outstring.as_bytes()) {
+		Ok(_) => Ok(true),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+impl MockProvider {
+	// Build a MockProvider, seeding it from the fixture file if the
+	// provider config names one.  A missing fixture file just means empty
+	// zones; an unreadable one is an error.
+	pub fn new(conf: &MacrotisProviderConfig) -> Option<MockProvider> {
+		let (zones, checks, hosted) = match &conf.fixture {
+			Some(fname) => (load_fixture(fname)?, load_fixture(&format!("{}.health", fname))?,
+			                load_fixture(&format!("{}.zones", fname))?),
+			None => (HashMap::new(), HashMap::new(), HashMap::new())
+		};
+		Some(MockProvider {
+			fixture: conf.fixture.clone(),
+			zones: RefCell::new(zones),
+			pending_polls: conf.pending_polls.unwrap_or(0),
+			changes: RefCell::new(HashMap::new()),
+			checks: RefCell::new(checks),
+			hosted: RefCell::new(hosted)
+		})
+	}
+
+	// Build a MockProvider straight from a set of zones, no fixture file
+	pub fn from_zones(zones: HashMap<String, Vec<Resource>>) -> MockProvider {
+		MockProvider {
+			fixture: None,
+			zones: RefCell::new(zones),
+			pending_polls: 0,
+			changes: RefCell::new(HashMap::new()),
+			checks: RefCell::new(HashMap::new()),
+			hosted: RefCell::new(HashMap::new())
+		}
+	}
+
+	// Hand back a copy of everything currently in the fake remote
+	pub fn zones(&self) -> HashMap<String, Vec<Resource>> {
+		self.zones.borrow().clone()
+	}
+
+	// Hand back a copy of every health check, with its name
+	pub fn checks(&self) -> HashMap<String, MockCheck> {
+		self.checks.borrow().clone()
+	}
+
+	// Write the zones, health checks, and hosted zones back out to the
+	// fixture files, if there are any
+	fn save(&self) -> Result<bool, String> {
+		let fname = match &self.fixture {
+			Some(x) => x,
+			None => { return Ok(true); }
+		};
+		save_fixture(fname, &*self.zones.borrow())?;
+		save_fixture(&format!("{}.health", fname), &*self.checks.borrow())?;
+		save_fixture(&format!("{}.zones", fname), &*self.hosted.borrow())
+	}
+}
+
+// Two resources share a name and type, ignoring case and any trailing dot
+fn same_name(a: &Resource, b: &Resource) -> bool {
+	a.rtype == b.rtype &&
+	a.name.trim_end_matches('.').to_lowercase() == b.name.trim_end_matches('.').to_lowercase()
+}
+
+// Two resources refer to the same record set if their name, type, and set
+// identifier match
+fn same_set(a: &Resource, b: &Resource) -> bool {
+	same_name(a, b) && a.set_identifier == b.set_identifier
+}
+
+impl DnsProvider for MockProvider {
+	fn fetch_zone(&self, zone_id: &str) -> Option<Vec<Resource>> {
+		match self.zones.borrow().get(zone_id) {
+			Some(x) => Some(x.clone()),
+			None => Some(Vec::new())
+		}
+	}
+
+	// Behave like Route53 does: CREATE fails if the set exists, DELETE
+	// fails if it doesn't, and UPSERT doesn't care.  Neither CREATE nor
+	// UPSERT can leave sets with different routing policies sharing a name
+	// and type.  Nothing is applied unless every change in the batch is
+	// valid.
+	fn apply_changes(&self, zone_id: &str, changes: &Vec<(String, Resource)>) -> Result<Vec<String>, String> {
+		let mut zones = self.zones.borrow_mut();
+		let mut recs = zones.get(zone_id).cloned().unwrap_or(Vec::new());
+		for (action, res) in changes {
+			if action != "DELETE" {
+				let clash = recs.iter().find(|r| same_name(r, res) && !same_set(r, res) &&
+				                                 r.routing_policy() != res.routing_policy());
+				if let Some(x) = clash {
+					return Err(format!("Record set {} {} conflicts with existing {} routing",
+					                   res.rtype, res.name, x.routing_policy()));
+				}
+			}
+			let pos = recs.iter().position(|r| same_set(r, res));
+			match (action.as_str(), pos) {
+				("CREATE", None) => { recs.push(res.clone()); },
+				("CREATE", Some(_)) => {
+					return Err(format!("Record set {} {} already exists", res.rtype, res.name));
+				},
+				("UPSERT", None) => { recs.push(res.clone()); },
+				("UPSERT", Some(i)) => { recs[i] = res.clone(); },
+				("DELETE", Some(i)) => { recs.remove(i); },
+				("DELETE", None) => {
+					return Err(format!("Record set {} {} not found", res.rtype, res.name));
+				},
+				_ => { return Err(format!("Unknown action {}", action)); }
+			};
+		}
+		zones.insert(zone_id.to_string(), recs);
+		drop(zones);
+		self.save()?;
+		let mut submitted = self.changes.borrow_mut();
+		let id = format!("mock-{}-{}", zone_id, submitted.len() + 1);
+		submitted.insert(id.to_string(), self.pending_polls);
+		Ok(vec![id])
+	}
+
+	fn change_status(&self, change_id: &str) -> Result<String, String> {
+		match self.changes.borrow_mut().get_mut(change_id) {
+			Some(0) => Ok("INSYNC".to_string()),
+			Some(x) => {
+				*x -= 1;
+				Ok("PENDING".to_string())
+			},
+			None => Err(format!("No such change {}", change_id))
+		}
+	}
+
+	fn fetch_health_checks(&self) -> Option<HashMap<String, HealthCheck>> {
+		Some(self.checks.borrow().iter().map(|(k, v)| (k.to_string(), v.check.clone())).collect())
+	}
+
+	// Like Route53, a reference that's been used before gets back the
+	// check it made
+	fn create_health_check(&self, reference: &str, name: &str, check: &HealthCheck) -> Result<String, String> {
+		let id = format!("mock-{}", reference);
+		let mut checks = self.checks.borrow_mut();
+		if !checks.contains_key(&id) {
+			checks.insert(id.to_string(), MockCheck {
+				reference: reference.to_string(),
+				name: name.to_string(),
+				check: check.clone()
+			});
+		}
+		drop(checks);
+		self.save()?;
+		Ok(id)
+	}
+
+	fn update_health_check(&self, id: &str, check: &HealthCheck) -> Result<(), String> {
+		match self.checks.borrow_mut().get_mut(id) {
+			Some(x) if x.check.check_type != check.check_type || x.check.request_interval != check.request_interval => {
+				return Err(format!("Health check {} can't change type or request_interval", id));
+			},
+			Some(x) => { x.check = check.clone(); },
+			None => { return Err(format!("No such health check {}", id)); }
+		};
+		self.save()?;
+		Ok(())
+	}
+
+	// Checks still used by a record or a calculated check can't be
+	// deleted, which catches deleting things in the wrong order
+	fn delete_health_check(&self, id: &str) -> Result<(), String> {
+		let in_use = self.zones.borrow().values().flatten().any(|r| r.health_check_id.as_ref().map(|x| x.as_str()) == Some(id)) ||
+			self.checks.borrow().values().any(|c| c.check.children.clone().unwrap_or_default().iter().any(|x| x == id));
+		if in_use {
+			return Err(format!("Health check {} is still in use", id));
+		}
+		if self.checks.borrow_mut().remove(id).is_none() {
+			return Err(format!("No such health check {}", id));
+		}
+		self.save()?;
+		Ok(())
+	}
+
+	fn find_zones(&self, domain: &str) -> Result<Vec<RemoteZone>, String> {
+		let domain = domain.trim_end_matches('.').to_lowercase();
+		let mut retval: Vec<RemoteZone> = self.hosted.borrow().iter()
+			.filter(|(_, z)| z.domain == domain)
+			.map(|(id, z)| RemoteZone { id: id.to_string(), private: z.private })
+			.collect();
+		retval.sort_by(|a, b| a.id.cmp(&b.id));
+		Ok(retval)
+	}
+
+	// New zones come with SOA and NS records, like they do in Route53.
+	// Zones using a delegation set get nameservers named after it.
+	fn create_zone(&self, zone: &Zone, _reference: &str) -> Result<String, String> {
+		let domain = zone.domain.trim_end_matches('.').to_lowercase();
+		let mut id = format!("ZMOCK{}", self.hosted.borrow().len() + 1);
+		while self.zones.borrow().contains_key(&id) || self.hosted.borrow().contains_key(&id) {
+			id.push('X');
+		}
+		let nameservers = match (&zone.delegation_set_id, zone.is_private()) {
+			(_, true) => Vec::new(),
+			(Some(x), false) => vec![format!("ns1.{}.mock.test", x.to_lowercase()), format!("ns2.{}.mock.test", x.to_lowercase())],
+			(None, false) => vec!["ns1.mock.test".to_string(), "ns2.mock.test".to_string()]
+		};
+		let apex = |rtype: &str, records: Vec<String>| Resource {
+			zone_id: id.to_string(),
+			name: domain.to_string(),
+			rtype: rtype.to_string(),
+			records: records,
+			ttl: 900,
+			..Default::default()
+		};
+		let ns = if nameservers.is_empty() { vec!["ns1.private.mock.test".to_string()] } else { nameservers.clone() };
+		let soa = format!("{} hostmaster.mock.test 1 7200 900 1209600 86400", ns[0]);
+		self.zones.borrow_mut().insert(id.to_string(), vec![apex("SOA", vec![soa]), apex("NS", ns)]);
+		self.hosted.borrow_mut().insert(id.to_string(), MockZone {
+			domain: domain,
+			private: zone.is_private(),
+			nameservers: nameservers
+		});
+		self.save()?;
+		Ok(id)
+	}
+
+	fn zone_nameservers(&self, zone_id: &str) -> Result<Vec<String>, String> {
+		match self.hosted.borrow().get(zone_id) {
+			Some(x) => Ok(x.nameservers.clone()),
+			None => Err(format!("No such hosted zone {}", zone_id))
+		}
+	}
+
+	// Like Route53, only a zone with nothing but its SOA and NS records in
+	// it can be deleted
+	fn delete_zone(&self, zone_id: &str) -> Result<(), String> {
+		let domain = match self.hosted.borrow().get(zone_id) {
+			Some(x) => x.domain.to_string(),
+			None => { return Err(format!("No such hosted zone {}", zone_id)); }
+		};
+		let recs = self.zones.borrow().get(zone_id).cloned().unwrap_or_default();
+		if recs.iter().any(|r| r.name != domain || (r.rtype != "SOA" && r.rtype != "NS")) {
+			return Err(format!("Hosted zone {} isn't empty", zone_id));
+		}
+		self.zones.borrow_mut().remove(zone_id);
+		self.hosted.borrow_mut().remove(zone_id);
+		self.save()?;
+		Ok(())
+	}
+}
+
+// The in-memory mock statefile, used when the mock backend has no filename
+thread_local! {
+	static MOCK_STATE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+// Fetch the mock statefile, either from memory or from 'filename' if set.
+// A state that was never saved comes back empty, like a missing S3 key.
+pub fn fetch_state_file(conf: &MacrotisStateConfig) -> Option<MacrotisState> {
+	let contents = match &conf.filename {
+		Some(fname) => {
+			match std::fs::read_to_string(fname) {
+				Ok(x) => Some(x),
+				Err(_) => None
+			}
+		},
+		None => MOCK_STATE.with(|s| s.borrow().clone())
+	};
+	match contents {
+		Some(x) => {
+			match state::parse_state(&conf, &x) {
+				Ok(st) => Some(st),
+				Err(e) => {
+					println!("{}", e);
+					None
+				}
+			}
+		},
+		None => Some(MacrotisState::new_missing())
+	}
+}
+
+// Numbered copies of the in-memory mock statefile, by serial
+thread_local! {
+	static MOCK_HISTORY: RefCell<HashMap<u64, String>> = RefCell::new(HashMap::new());
+}
+
+// Keep a numbered copy of the in-memory mock statefile
+pub fn put_history(serial: u64, state: &str) -> Result<bool, String> {
+	MOCK_HISTORY.with(|h| h.borrow_mut().insert(serial, state.to_string()));
+	Ok(true)
+}
+
+// List the serials of the in-memory copies, oldest first
+pub fn list_history() -> Vec<u64> {
+	let mut retval: Vec<u64> = MOCK_HISTORY.with(|h| h.borrow().keys().cloned().collect());
+	retval.sort();
+	retval
+}
+
+// Fetch one of the in-memory copies
+pub fn fetch_history(conf: &MacrotisStateConfig, serial: u64) -> Option<MacrotisState> {
+	let contents = MOCK_HISTORY.with(|h| h.borrow().get(&serial).cloned())?;
+	match state::parse_state(&conf, &contents) {
+		Ok(st) => Some(st),
+		Err(e) => {
+			println!("{}", e);
+			None
+		}
+	}
+}
+
+// Save the mock statefile to memory or to 'filename' if set
+pub fn put_state_file(conf: &MacrotisStateConfig, state: &str) -> Result<bool, String> {
+	match &conf.filename {
+		Some(fname) => {
+			match std::fs::write(fname, state) {
+				Ok(_) => Ok(true),
+				Err(e) => Err(e.to_string())
+			}
+		},
+		None => {
+			MOCK_STATE.with(|s| *s.borrow_mut() = Some(state.to_string()));
+			Ok(true)
+		}
+	}
+}
+
+// The in-memory mock lock, used when the mock backend has no filename
+thread_local! {
+	static MOCK_LOCK: RefCell<Option<MacrotisLock>> = RefCell::new(None);
+}
+
+// Take the mock lock, either in memory or as 'filename'.lock like the
+// local backend
+pub fn put_lock(conf: &MacrotisStateConfig, lock: &MacrotisLock) -> Result<Option<MacrotisLock>, String> {
+	match &conf.filename {
+		Some(fname) => state::lock_local(&format!("{}.lock", fname), lock),
+		None => MOCK_LOCK.with(|l| {
+			let mut held = l.borrow_mut();
+			match held.clone() {
+				Some(x) => Ok(Some(x)),
+				None => {
+					*held = Some(lock.clone());
+					Ok(None)
+				}
+			}
+		})
+	}
+}
+
+// Fetch the mock lock, if there is one
+pub fn fetch_lock(conf: &MacrotisStateConfig) -> Result<Option<MacrotisLock>, String> {
+	match &conf.filename {
+		Some(fname) => state::fetch_local_lock(&format!("{}.lock", fname)),
+		None => Ok(MOCK_LOCK.with(|l| l.borrow().clone()))
+	}
+}
+
+// Remove the mock lock
+pub fn delete_lock(conf: &MacrotisStateConfig) -> Result<bool, String> {
+	match &conf.filename {
+		Some(fname) => state::delete_local_lock(&format!("{}.lock", fname)),
+		None => Ok(MOCK_LOCK.with(|l| l.borrow_mut().take().is_some()))
+	}
+}
@@ -0,0 +1,341 @@
+// Module for deciding which records Macrotis owns.  A zone can have rules
+// saying which names and types are Macrotis's to manage; anything else in
+// the zone (ACME challenges, records another tool writes) is never
+// changed, only reported.  Zones without rules are Macrotis's entirely.
+
+use std::collections::{HashMap, HashSet};
+use Zone;
+use resource::{self, Resource, ResHash};
+
+// Ownership markers are TXT records at this prefix plus the name they mark
+pub const MARKER_PREFIX: &str = "_macrotis.";
+
+// What Macrotis owns in a zone.  Names have to match one of the include
+// globs (any name, if there are none) and none of the exclude globs, and
+// types have to be in the types list (any type, if there isn't one).  With
+// an owner_id, every name Macrotis manages is marked with a TXT record
+// saying so, and records at names that aren't marked as its own are left
+// alone unless the statefile already has them.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Ownership {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub types: Option<Vec<String>>,
+    pub owner_id: Option<String>
+}
+
+impl Ownership {
+    // Whether the name and type rules allow a record
+    pub fn owns(&self, res: &Resource) -> bool {
+        let name = res.name.trim_end_matches('.');
+        let included = match &self.include {
+            Some(x) if !x.is_empty() => x.iter().any(|g| glob_match(g, name)),
+            _ => true
+        };
+        let excluded = match &self.exclude {
+            Some(x) => x.iter().any(|g| glob_match(g, name)),
+            None => false
+        };
+        let typed = match &self.types {
+            Some(x) => x.iter().any(|t| t.eq_ignore_ascii_case(&res.rtype)),
+            None => true
+        };
+        included && !excluded && typed
+    }
+
+    // The value of this owner's marker records, if it uses them
+    fn marker_value(&self) -> Option<String> {
+        self.owner_id.as_ref().map(|x| format!("\"heritage=macrotis,macrotis/owner={}\"", x))
+    }
+}
+
+// Match a name against a glob where '*' stands for any run of characters
+// (dots included), ignoring case
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_lowercase();
+    let name = name.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for p in &parts[1..parts.len() - 1] {
+        match rest.find(p) {
+            Some(i) => { rest = &rest[i + p.len()..]; },
+            None => { return false; }
+        };
+    }
+    true
+}
+
+// The configured zone a record is in: the one with its zone ID, or among
+// zones still to be created (which all have an empty ID), the one whose
+// domain is the longest match for its name
+fn zone_for<'a>(zones: &'a [Zone], res: &Resource) -> Option<&'a Zone> {
+    let name = res.name.trim_end_matches('.').to_lowercase();
+    zones.iter()
+        .filter(|z| z.id == res.zone_id)
+        .filter(|z| name.ends_with(&z.domain.trim_end_matches('.').to_lowercase()))
+        .max_by_key(|z| z.domain.len())
+}
+
+// The rules for a record's zone.  None means Macrotis owns everything.
+fn rules_for<'a>(zones: &'a [Zone], res: &Resource) -> Option<&'a Ownership> {
+    zone_for(zones, res).and_then(|z| z.ownership.as_ref())
+}
+
+pub fn is_marker(res: &Resource) -> bool {
+    res.rtype == "TXT" && res.name.to_lowercase().starts_with(MARKER_PREFIX)
+}
+
+// Type and name of a record, plus its set identifier if it has one
+fn label(res: &Resource) -> String {
+    match &res.set_identifier {
+        Some(x) => format!("{} {} ({})", res.rtype, res.name, x),
+        None => format!("{} {}", res.rtype, res.name)
+    }
+}
+
+fn sorted_keys(recs: &ResHash) -> Vec<&String> {
+    let mut keys: Vec<&String> = recs.0.keys().collect();
+    keys.sort();
+    keys
+}
+
+// Check the input only has records its zones let Macrotis own, printing
+// any that aren't.  Returns false if there were any.
+pub fn check_local(zones: &[Zone], local: &ResHash) -> bool {
+    let mut retval = true;
+    for key in sorted_keys(local) {
+        let res = &local.0[key];
+        if is_marker(res) {
+            continue;
+        }
+        if let Some(z) = zone_for(zones, res) {
+            if z.ownership.as_ref().map_or(false, |o| !o.owns(res)) {
+                println!("Error: {} is outside what zone {} owns", label(res), z.name);
+                retval = false;
+            }
+        }
+    }
+    retval
+}
+
+// Forget statefile records the rules no longer give to Macrotis, so they
+// aren't deleted when they drop out of the input.  The remote records are
+// left as they are.
+pub fn filter_state(zones: &[Zone], st: &mut ResHash) {
+    let gone: Vec<String> = sorted_keys(st).into_iter()
+        .filter(|k| {
+            let res = &st.0[*k];
+            !is_marker(res) && rules_for(zones, res).map_or(false, |o| !o.owns(res))
+        })
+        .cloned()
+        .collect();
+    for k in gone {
+        if let Some(x) = st.0.remove(&k) {
+            println!("[WARNING] {} is no longer owned, forgetting it without deleting it", label(&x));
+        }
+    }
+}
+
+// Take the records Macrotis doesn't own out of the remote ones, returning
+// them.  A record is owned if its zone's rules allow it and, where the zone
+// has an owner_id, the statefile has it or its name is marked with ours.
+// Markers themselves belong to whichever owner they name.
+pub fn split_remote(zones: &[Zone], remote: &mut ResHash, st: &ResHash) -> ResHash {
+    let mut marked = HashSet::new();
+    for res in remote.0.values() {
+        let ours = match rules_for(zones, res).and_then(|o| o.marker_value()) {
+            Some(x) => x,
+            None => { continue; }
+        };
+        if is_marker(res) && res.records == vec![ours] {
+            let name = res.name.trim_end_matches('.').to_lowercase();
+            marked.insert((res.zone_id.to_string(), name[MARKER_PREFIX.len()..].to_string()));
+        }
+    }
+
+    let mut unowned = HashMap::new();
+    for key in sorted_keys(remote) {
+        let res = &remote.0[key];
+        let rules = match rules_for(zones, res) {
+            Some(x) => x,
+            None => { continue; }
+        };
+        let name = res.name.trim_end_matches('.').to_lowercase();
+        let owned = match rules.marker_value() {
+            Some(x) if is_marker(res) => res.records == vec![x],
+            Some(_) => rules.owns(res) && (st.0.contains_key(key) || marked.contains(&(res.zone_id.to_string(), name))),
+            None => rules.owns(res)
+        };
+        if !owned {
+            unowned.insert(key.to_string(), res.clone());
+        }
+    }
+    for k in unowned.keys() {
+        remote.0.remove(k);
+    }
+    ResHash(unowned)
+}
+
+// Leave local records out if they'd overwrite one Macrotis doesn't own.
+// They're left out of the statefile too, so they don't become Macrotis's
+// by accident.
+pub fn drop_conflicts(local: &mut ResHash, unowned: &ResHash) {
+    for key in sorted_keys(unowned) {
+        if local.0.remove(key).is_some() {
+            println!("[SKIP] {} exists remotely but isn't owned by Macrotis", label(&unowned.0[key]));
+        }
+    }
+}
+
+// Add an ownership marker for every name with records in a zone that has
+// an owner_id.  Any markers already there (from a statefile being rolled
+// back to, say) are replaced.
+pub fn add_markers(zones: &[Zone], local: &mut ResHash) {
+    local.0.retain(|_, r| !is_marker(r));
+    let mut markers = Vec::new();
+    let mut seen = HashSet::new();
+    for key in sorted_keys(local) {
+        let res = &local.0[key];
+        let value = match rules_for(zones, res).and_then(|o| o.marker_value()) {
+            Some(x) => x,
+            None => { continue; }
+        };
+        let name = res.name.trim_end_matches('.').to_lowercase();
+        if !seen.insert((res.zone_id.to_string(), name.to_string())) {
+            continue;
+        }
+        markers.push(Resource {
+            zone_id: res.zone_id.to_string(),
+            name: format!("{}{}", MARKER_PREFIX, name),
+            rtype: "TXT".to_string(),
+            records: vec![value],
+            ttl: 300,
+            ..Default::default()
+        });
+    }
+    for m in markers {
+        local.0.insert(resource::resource_key(&m), m);
+    }
+}
+
+// Print the remote records being left alone
+pub fn report(unowned: &ResHash) {
+    if unowned.0.is_empty() {
+        return;
+    }
+    for key in sorted_keys(unowned) {
+        println!("[UNOWNED] {}", label(&unowned.0[key]));
+    }
+    println!("Leaving {} remote record(s) Macrotis doesn't own alone", unowned.0.len());
+}
+
+// Whether 'import' should take a remote record: not a marker, and owned
+// as far as the rules go
+pub fn importable(zones: &[Zone], res: &Resource) -> bool {
+    !is_marker(res) && rules_for(zones, res).map_or(true, |o| o.owns(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn res(name: &str, rtype: &str) -> Resource {
+        Resource {
+            zone_id: "Z1".to_string(),
+            name: name.to_string(),
+            rtype: rtype.to_string(),
+            records: vec!["x".to_string()],
+            ttl: 300,
+            ..Default::default()
+        }
+    }
+
+    fn zone(ownership: Ownership) -> Vec<Zone> {
+        vec![Zone {
+            name: "test".to_string(),
+            domain: "test.com".to_string(),
+            id: "Z1".to_string(),
+            create: None,
+            vpcs: None,
+            delegation_set_id: None,
+            ownership: Some(ownership)
+        }]
+    }
+
+    fn hash(recs: Vec<Resource>) -> ResHash {
+        resource::build_reshash(recs).unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.app.test.com", "www.app.test.com"));
+        assert!(glob_match("*.app.test.com", "a.b.app.test.com"));
+        assert!(!glob_match("*.app.test.com", "app.test.com"));
+        assert!(glob_match("_acme-challenge.*", "_ACME-challenge.www.test.com"));
+        assert!(glob_match("www*.test.com.", "www2.test.com"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("a*b*c", "acb"));
+        assert!(!glob_match("test.com", "www.test.com"));
+    }
+
+    #[test]
+    fn test_rules() {
+        let zones = zone(Ownership {
+            include: Some(vec!["*.test.com".to_string()]),
+            exclude: Some(vec!["_acme-challenge.*".to_string()]),
+            types: Some(vec!["a".to_string(), "CNAME".to_string()]),
+            owner_id: None
+        });
+        let local = hash(vec![res("www.test.com", "A"), res("mail.test.com", "CNAME")]);
+        assert!(check_local(&zones, &local));
+        assert!(!check_local(&zones, &hash(vec![res("www.test.com", "TXT")])));
+
+        let mut remote = hash(vec![res("www.test.com", "A"), res("_acme-challenge.www.test.com", "CNAME"), res("test.com", "A")]);
+        let unowned = split_remote(&zones, &mut remote, &ResHash(HashMap::new()));
+        assert_eq!(remote.0.len(), 1);
+        assert_eq!(unowned.0.len(), 2);
+
+        let mut st = hash(vec![res("www.test.com", "A"), res("test.com", "A")]);
+        filter_state(&zones, &mut st);
+        assert_eq!(st.0.len(), 1);
+    }
+
+    #[test]
+    fn test_markers() {
+        let zones = zone(Ownership { owner_id: Some("prod".to_string()), ..Default::default() });
+        let mut local = hash(vec![res("www.test.com", "A"), res("www.test.com", "AAAA"), res("new.test.com", "A")]);
+        add_markers(&zones, &mut local);
+        assert_eq!(local.0.len(), 5);
+        let marker = &local.0["txt-_macrotis-www-test-com"];
+        assert_eq!(marker.records, vec!["\"heritage=macrotis,macrotis/owner=prod\"".to_string()]);
+
+        // Remote records are only ours if they're marked, or in state
+        let mut other = marker.clone();
+        other.name = "_macrotis.old.test.com".to_string();
+        other.records = vec!["\"heritage=macrotis,macrotis/owner=dev\"".to_string()];
+        let mut remote = hash(vec![marker.clone(), other, res("www.test.com", "A"), res("old.test.com", "A"),
+                                   res("new.test.com", "A"), res("st.test.com", "A")]);
+        let unowned = split_remote(&zones, &mut remote, &hash(vec![res("st.test.com", "A")]));
+        let mut keys: Vec<&String> = unowned.0.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a-new-test-com", "a-old-test-com", "txt-_macrotis-old-test-com"]);
+        assert!(remote.0.contains_key("a-www-test-com"));
+        assert!(remote.0.contains_key("a-st-test-com"));
+
+        // A local record that would clobber someone else's is dropped,
+        // marker and all
+        drop_conflicts(&mut local, &unowned);
+        add_markers(&zones, &mut local);
+        assert!(!local.0.contains_key("a-new-test-com"));
+        assert!(!local.0.contains_key("txt-_macrotis-new-test-com"));
+        assert_eq!(local.0.len(), 3);
+    }
+}
@@ -0,0 +1,212 @@
+// Module for saving a set of changes to a plan file and loading it back,
+// so 'apply' pushes exactly what 'noop' showed.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use resource::{Resource, ResHash};
+use health::{self, HealthCheck, HealthPlan};
+
+// A plan holds the changes to push along with what they were worked out
+// from: the serial of the statefile, and hashes of the local input and of
+// the remote records at the time.  If any of those don't match when it's
+// applied, the plan is stale.  Changes to health checks ride along too;
+// plans from before there were any just have none.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MacrotisPlan {
+    pub version: u32,
+    pub appversion: String,
+    pub state_serial: u64,
+    pub input_hash: String,
+    pub remote_hash: String,
+    pub create: Vec<Resource>,
+    pub upsert: Vec<Resource>,
+    pub delete: Vec<Resource>,
+    #[serde(default)]
+    pub health: HealthPlan
+}
+
+impl std::fmt::Display for MacrotisPlan {
+	// Pretty print metadata about the plan
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "Plan for state serial {} (Macrotis v{}), {} create, {} upsert, {} delete",
+				self.state_serial, self.appversion, self.create.len(),
+				self.upsert.len(), self.delete.len())
+    }
+}
+
+impl MacrotisPlan {
+    // Build a plan from the three change sets
+    pub fn new(state_serial: u64, local: &ResHash, remote: &ResHash,
+               new_recs: ResHash, upd_recs: ResHash, del_recs: ResHash) -> MacrotisPlan {
+        MacrotisPlan {
+            version: 1,
+            appversion: env!("CARGO_PKG_VERSION").to_string(),
+            state_serial: state_serial,
+            input_hash: hash_reshash(local),
+            remote_hash: hash_reshash(remote),
+            create: sorted(new_recs),
+            upsert: sorted(upd_recs),
+            delete: sorted(del_recs),
+            health: HealthPlan::default()
+        }
+    }
+
+    // Add the planned health check changes, counting the declared checks
+    // as part of the local input
+    pub fn with_health(mut self, local: &ResHash, checks: &HashMap<String, HealthCheck>, hplan: &HealthPlan) -> MacrotisPlan {
+        self.input_hash = hash_input(local, checks);
+        self.health = hplan.clone();
+        self
+    }
+
+    // Hand the changes back keyed by action, the way push_remote wants them
+    pub fn changes(&self) -> HashMap<&'static str, Vec<Resource>> {
+        let mut retval = HashMap::new();
+        retval.insert("CREATE", self.create.clone());
+        retval.insert("UPSERT", self.upsert.clone());
+        retval.insert("DELETE", self.delete.clone());
+        retval
+    }
+
+    // Make sure the plan still describes the world as it is now.  Returns
+    // a list of everything that's changed since it was made.
+    pub fn check(&self, state_serial: u64, local: &ResHash, remote: &ResHash,
+                 checks: &HashMap<String, HealthCheck>, hplan: &HealthPlan) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.state_serial != state_serial {
+            problems.push(format!("statefile serial is {}, plan was made against {}",
+                                  state_serial, self.state_serial));
+        }
+        if self.input_hash != hash_input(local, checks) {
+            problems.push("local input has changed".to_string());
+        }
+        if self.remote_hash != hash_reshash(remote) {
+            problems.push("remote records have changed".to_string());
+        }
+        if self.health != *hplan {
+            problems.push("health checks have changed".to_string());
+        }
+        problems
+    }
+}
+
+// Pull the Resources out of a ResHash in key order so plan files come
+// out the same every time
+fn sorted(hsh: ResHash) -> Vec<Resource> {
+    let mut keyed: Vec<(String, Resource)> = hsh.0.into_iter().collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|x| x.1).collect()
+}
+
+// 64-bit FNV-1a.  std's DefaultHasher isn't guaranteed to give the same
+// answer across Rust releases, and plan files need to outlive the binary
+// that wrote them.
+pub fn fnv1a(data: &[u8], mut hash: u64) -> u64 {
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Hash a ResHash in a way that doesn't depend on HashMap ordering or the
+// order of values within a Resource
+pub fn hash_reshash(hsh: &ResHash) -> String {
+    let mut keys: Vec<&String> = hsh.0.keys().collect();
+    keys.sort();
+    let mut hash = 0xcbf29ce484222325;
+    for k in keys {
+        let mut res = hsh.0[k].clone();
+        res.records.sort();
+        let json = serde_json::to_string(&res).unwrap_or_default();
+        hash = fnv1a(k.as_bytes(), hash);
+        hash = fnv1a(b"\0", hash);
+        hash = fnv1a(json.as_bytes(), hash);
+        hash = fnv1a(b"\0", hash);
+    }
+    format!("{:016x}", hash)
+}
+
+// The local input is the records plus any declared health checks.  With
+// no checks it's just the records, as it was before there were any.
+fn hash_input(local: &ResHash, checks: &HashMap<String, HealthCheck>) -> String {
+    if checks.is_empty() {
+        return hash_reshash(local);
+    }
+    let hash = u64::from_str_radix(&hash_reshash(local), 16).unwrap_or_default();
+    format!("{:016x}", health::hash_checks(checks, hash))
+}
+
+// Write a plan out to a file as JSON
+pub fn save_plan(fname: &str, plan: &MacrotisPlan) -> bool {
+    let f = match File::create(fname) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Error opening plan file {}: {}", fname, e);
+            return false;
+        }
+    };
+    let writer = BufWriter::new(f);
+    match serde_json::to_writer_pretty(writer, plan) {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Error writing plan file {}: {}", fname, e);
+            false
+        }
+    }
+}
+
+// Read a plan back in from a file
+pub fn load_plan(fname: &str) -> Option<MacrotisPlan> {
+    let f = match File::open(fname) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Error opening plan file {}: {}", fname, e);
+            return None;
+        }
+    };
+    let reader = BufReader::new(f);
+    let plan: MacrotisPlan = match serde_json::from_reader(reader) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error parsing plan file JSON: {}", e);
+            return None;
+        }
+    };
+    if plan.version != 1 {
+        println!("Unsupported plan file version {}", plan.version);
+        return None;
+    }
+    Some(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use resource;
+
+    fn res(name: &str, records: Vec<&str>) -> Resource {
+        Resource {
+            zone_id: "Z1".to_string(),
+            name: name.to_string(),
+            rtype: "A".to_string(),
+            records: records.iter().map(|x| x.to_string()).collect(),
+            ttl: 300,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hash_reshash() {
+        let a = resource::build_reshash(vec![res("a.test.com", vec!["1.1.1.1", "2.2.2.2"]),
+                                             res("b.test.com", vec!["3.3.3.3"])]).unwrap();
+        let b = resource::build_reshash(vec![res("b.test.com", vec!["3.3.3.3"]),
+                                             res("a.test.com", vec!["2.2.2.2", "1.1.1.1"])]).unwrap();
+        let c = resource::build_reshash(vec![res("a.test.com", vec!["1.1.1.1"]),
+                                             res("b.test.com", vec!["3.3.3.3"])]).unwrap();
+        assert_eq!(hash_reshash(&a), hash_reshash(&b));
+        assert!(hash_reshash(&a) != hash_reshash(&c));
+        assert_eq!(hash_reshash(&ResHash(HashMap::new())), "cbf29ce484222325");
+    }
+}
@@ -0,0 +1,113 @@
+// Module defining the DnsProvider trait that every remote DNS backend
+// implements, plus a helper for picking a backend based on the config.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use {MacrotisProviderConfig, Zone};
+use resource::Resource;
+use health::HealthCheck;
+use zones::RemoteZone;
+use r53;
+use mock;
+
+// A DnsProvider knows how to download all the records in a zone and how to
+// push a set of changes back up to it.  Changes are (action, Resource)
+// tuples where the action is one of CREATE, UPSERT, or DELETE.
+pub trait DnsProvider {
+    // Retrieve all records for a given zone ID. Returns None on error.
+    fn fetch_zone(&self, zone_id: &str) -> Option<Vec<Resource>>;
+
+    // Send a batch of changes for a single zone to the remote end.  Returns
+    // the IDs of the changes submitted, for change_status.
+    fn apply_changes(&self, zone_id: &str, changes: &Vec<(String, Resource)>) -> Result<Vec<String>, String>;
+
+    // Look up whether a submitted change has gone out to every nameserver
+    // yet: "INSYNC" once it has, "PENDING" until then.
+    fn change_status(&self, change_id: &str) -> Result<String, String>;
+
+    // Retrieve every health check, keyed by ID.  Children of calculated
+    // checks are given by ID too.
+    fn fetch_health_checks(&self) -> Option<HashMap<String, HealthCheck>>;
+
+    // Make a new health check, with 'name' as its label, and return its
+    // ID.  Asking again with the same reference returns the same check.
+    fn create_health_check(&self, reference: &str, name: &str, check: &HealthCheck) -> Result<String, String>;
+
+    // Change an existing check's settings (other than its type and
+    // request_interval, which can't be changed)
+    fn update_health_check(&self, id: &str, check: &HealthCheck) -> Result<(), String>;
+
+    fn delete_health_check(&self, id: &str) -> Result<(), String>;
+
+    // Find every hosted zone (public or private) for a domain
+    fn find_zones(&self, domain: &str) -> Result<Vec<RemoteZone>, String>;
+
+    // Make a new hosted zone as the config describes it, returning its ID.
+    // The reference has to be one that's never been used before.
+    fn create_zone(&self, zone: &Zone, reference: &str) -> Result<String, String>;
+
+    // The nameservers a zone is served from.  Private zones have none.
+    fn zone_nameservers(&self, zone_id: &str) -> Result<Vec<String>, String>;
+
+    fn delete_zone(&self, zone_id: &str) -> Result<(), String>;
+}
+
+// Build the appropriate DnsProvider for the given provider config, using
+// its 'name' field to decide which backend to use.  Older configs used the
+// name as a free-form label, but a typo shouldn't quietly turn a test
+// config into one that changes live DNS, so unknown names are an error.
+pub fn build_provider(conf: &MacrotisProviderConfig) -> Option<Box<dyn DnsProvider>> {
+    match conf.name.to_lowercase().as_str() {
+        "route53" | "r53" => Some(Box::new(r53::Route53Provider::new(conf))),
+        "mock" => {
+            match mock::MockProvider::new(conf) {
+                Some(x) => Some(Box::new(x)),
+                None => None
+            }
+        },
+        _ => {
+            println!("Unknown provider name '{}'; use 'route53' or 'mock'", &conf.name);
+            None
+        }
+    }
+}
+
+// Default seconds to wait for changes to reach INSYNC, and between polls
+pub const DEFAULT_WAIT_TIMEOUT: u64 = 300;
+pub const DEFAULT_POLL_INTERVAL: u64 = 5;
+
+// Poll the provider until every change in 'ids' is INSYNC, printing
+// progress as it goes.  Returns false if any change errors out or is still
+// pending once 'timeout' seconds have passed.
+pub fn wait_for_changes(prov: &dyn DnsProvider, ids: &Vec<String>, timeout: u64, interval: u64) -> bool {
+    if ids.is_empty() {
+        return true;
+    }
+    let start = Instant::now();
+    let mut pending = ids.clone();
+    loop {
+        let mut still_pending = Vec::new();
+        for id in pending {
+            match prov.change_status(&id) {
+                Ok(ref x) if x == "INSYNC" => {},
+                Ok(_) => { still_pending.push(id); },
+                Err(e) => {
+                    println!("Error checking on change {}: {}", id, e);
+                    return false;
+                }
+            };
+        }
+        pending = still_pending;
+        let elapsed = start.elapsed().as_secs();
+        if pending.is_empty() {
+            println!("All {} changes are INSYNC after {}s", ids.len(), elapsed);
+            return true;
+        }
+        if elapsed >= timeout {
+            println!("Timed out after {}s with {} of {} changes still pending: {}",
+                     elapsed, pending.len(), ids.len(), pending.join(", "));
+            return false;
+        }
+        println!("Waiting for {} of {} changes to reach INSYNC ({}s elapsed)", pending.len(), ids.len(), elapsed);
+        std::thread::sleep(Duration::from_secs(std::cmp::min(interval, timeout - elapsed)));
+    }
+}
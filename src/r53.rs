@@ -1,21 +1,95 @@
 // Functions for talking to Route53
-use std::str::FromStr;
-use MacrotisProviderConfig;
-use resource::Resource;
-use rusoto_core::{Region, HttpClient};
+use std::collections::HashMap;
+use {MacrotisProviderConfig, Zone, ZoneVpc};
+use aws;
+use resource::{Resource, GeoLocation, AliasTarget};
+use health::HealthCheck;
+use zones::RemoteZone;
+use provider::DnsProvider;
+use retry::{RetryPolicy, with_retry, with_retry_unapplied};
+use rusoto_core::{HttpClient, RusotoError};
 use rusoto_route53::{Route53Client, Route53, ListResourceRecordSetsRequest};
 use rusoto_route53::{ResourceRecord, ResourceRecordSet, Change};
-use rusoto_route53::{ChangeBatch, ChangeResourceRecordSetsRequest};
+use rusoto_route53::GeoLocation as R53GeoLocation;
+use rusoto_route53::AliasTarget as R53AliasTarget;
+use rusoto_route53::{ChangeBatch, ChangeResourceRecordSetsRequest, GetChangeRequest};
+use rusoto_route53::{ChangeResourceRecordSetsError, ListResourceRecordSetsError, GetChangeError};
+use rusoto_route53::{HealthCheckConfig, CreateHealthCheckRequest, UpdateHealthCheckRequest};
+use rusoto_route53::{DeleteHealthCheckRequest, ListHealthChecksRequest, ChangeTagsForResourceRequest, Tag};
+use rusoto_route53::{UpdateHealthCheckError, DeleteHealthCheckError, ListHealthChecksError};
+use rusoto_route53::{CreateHealthCheckError, ChangeTagsForResourceError};
+use rusoto_route53::{ListHostedZonesByNameRequest, CreateHostedZoneRequest, HostedZoneConfig, VPC};
+use rusoto_route53::{AssociateVPCWithHostedZoneRequest, GetHostedZoneRequest, DeleteHostedZoneRequest};
+use rusoto_route53::{AssociateVPCWithHostedZoneError, DeleteHostedZoneError, HostedZone};
+use rusoto_route53::{ListHostedZonesByNameError, CreateHostedZoneError, GetHostedZoneError};
 use rusoto_sts::{StsClient, StsAssumeRoleSessionCredentialsProvider};
 
+// DnsProvider implementation for Route53.  Holds on to its own copy of the
+// provider config so clients can be built as needed.
+pub struct Route53Provider {
+	conf: MacrotisProviderConfig
+}
+
+impl Route53Provider {
+	pub fn new(conf: &MacrotisProviderConfig) -> Route53Provider {
+		Route53Provider { conf: conf.clone() }
+	}
+}
+
+impl DnsProvider for Route53Provider {
+	fn fetch_zone(&self, zone_id: &str) -> Option<Vec<Resource>> {
+		bulk_fetch(&self.conf, zone_id)
+	}
+
+	fn apply_changes(&self, zone_id: &str, changes: &Vec<(String, Resource)>) -> Result<Vec<String>, String> {
+		let mut chgvec = Vec::new();
+		for (action, res) in changes {
+			chgvec.push(resource_to_change(&action, &res));
+		}
+		bulk_put(&self.conf, chgvec, zone_id)
+	}
+
+	fn change_status(&self, change_id: &str) -> Result<String, String> {
+		get_change_status(&self.conf, change_id)
+	}
+
+	fn fetch_health_checks(&self) -> Option<HashMap<String, HealthCheck>> {
+		fetch_health_checks(&self.conf)
+	}
+
+	fn create_health_check(&self, reference: &str, name: &str, check: &HealthCheck) -> Result<String, String> {
+		create_health_check(&self.conf, reference, name, check)
+	}
+
+	fn update_health_check(&self, id: &str, check: &HealthCheck) -> Result<(), String> {
+		update_health_check(&self.conf, id, check)
+	}
+
+	fn delete_health_check(&self, id: &str) -> Result<(), String> {
+		delete_health_check(&self.conf, id)
+	}
+
+	fn find_zones(&self, domain: &str) -> Result<Vec<RemoteZone>, String> {
+		find_zones(&self.conf, domain)
+	}
+
+	fn create_zone(&self, zone: &Zone, reference: &str) -> Result<String, String> {
+		create_zone(&self.conf, zone, reference)
+	}
+
+	fn zone_nameservers(&self, zone_id: &str) -> Result<Vec<String>, String> {
+		zone_nameservers(&self.conf, zone_id)
+	}
+
+	fn delete_zone(&self, zone_id: &str) -> Result<(), String> {
+		delete_zone(&self.conf, zone_id)
+	}
+}
 
 // Build a Route53Client for Route53 operations
 pub fn build_client(conf: &MacrotisProviderConfig) -> Option<Route53Client> {
 	// Grab region from conf or use the default
-	let region = match &conf.region {
-		Some(x) => Region::from_str(&x).unwrap_or(Region::default()),
-		None => Region::default()
-	};
+	let region = aws::build_region(&conf.region, &conf.endpoint);
 	
 	let mut client = Route53Client::new(region.to_owned());
 	
@@ -32,7 +106,9 @@ pub fn build_client(conf: &MacrotisProviderConfig) -> Option<Route53Client> {
 			Some(x) => x.to_string(),
 			None => "default".to_string()
 		};
-		let sts = StsClient::new(region.to_owned());
+		// STS goes to the same place as Route53 unless told otherwise
+		let sts_endpoint = conf.sts_endpoint.clone().or(conf.endpoint.clone());
+		let sts = StsClient::new(aws::build_region(&conf.region, &sts_endpoint));
 		let provider = StsAssumeRoleSessionCredentialsProvider::new(
 		    sts,
 		    arn,
@@ -67,8 +143,15 @@ pub fn bulk_fetch(conf: &MacrotisProviderConfig, zone_id: &str) -> Option<Vec<Re
         hosted_zone_id: zone_id.to_string(),
         max_items: None, start_record_identifier: None,
         start_record_type: None, start_record_name: None };
+    let policy = RetryPolicy::from_config(&conf);
     loop {
-        match client.list_resource_record_sets(req.to_owned()).sync() {
+        let result = with_retry(&policy, "ListResourceRecordSets",
+                                || client.list_resource_record_sets(req.to_owned()).sync(),
+                                |e| match e {
+                                    ListResourceRecordSetsError::InvalidInput(_) |
+                                    ListResourceRecordSetsError::NoSuchHostedZone(_) => false
+                                });
+        match result {
             Err(e) => {
                 println!("Error fetching from Route53: {}", e);
                 return None;
@@ -93,7 +176,12 @@ pub fn bulk_fetch(conf: &MacrotisProviderConfig, zone_id: &str) -> Option<Vec<Re
   
 // Given Provider metadata, a zone_id, and a vector of changes to push,
 // generate a number of Route53 requests and push everything up there.
-pub fn bulk_put(conf: &MacrotisProviderConfig, mut records: Vec<Change>, zone_id: &str) -> Result<String, String> {
+// Returns the change ID of every batch sent.
+pub fn bulk_put(conf: &MacrotisProviderConfig, records: Vec<Change>, zone_id: &str) -> Result<Vec<String>, String> {
+    // Work out the batches first, so nothing is sent if a change is too
+    // big to ever go through
+    let batches = build_batches(records)?;
+
     // Build the client
     let client = match build_client(&conf) {
         Some(x) => x,
@@ -102,34 +190,476 @@ pub fn bulk_put(conf: &MacrotisProviderConfig, mut records: Vec<Change>, zone_id
         }
     };
         
-    // We can only send 100 items at a time, so use vec.split_off to 
-    // shift them into their own temp vec.  split_off panics if given
-    // a number larger than vec.len so do some checking there first.
-    loop {
-		let c = records.len();
-        let chunk = records.split_off(std::cmp::min(c, 99));
+    let mut retval = Vec::new();
+    let policy = RetryPolicy::from_config(&conf);
+
+    for chunk in batches {
 		let batch = ChangeBatch { changes: chunk, comment: None };
 		let req = ChangeResourceRecordSetsRequest {
 			change_batch: batch,
 			hosted_zone_id: zone_id.to_string()
 		};
-		match client.change_resource_record_sets(req.to_owned()).sync() {
+		// A batch that went through before an error would fail if sent
+		// again, so it's only retried when Route53 turned it away
+		let result = with_retry_unapplied(&policy, "ChangeResourceRecordSets",
+		                                  || client.change_resource_record_sets(req.to_owned()).sync(),
+		                                  |e| match e {
+		                                      ChangeResourceRecordSetsError::PriorRequestNotComplete(_) => true,
+		                                      _ => false
+		                                  });
+		match result {
 			Err(e) => {
 				println!("Error sending changes to Route53: {}", e);
 				return Err(e.to_string());
 			},
 			Ok(output) => {
-				let id = output.change_info.id;
-				println!("{}", id);
+				retval.push(output.change_info.id);
+			}
+		};
+	}
+	Ok(retval)
+}
+
+// Route53's limits on a single change batch: how many record values it can
+// hold, and how many characters those values can add up to
+const MAX_BATCH_VALUES: usize = 1000;
+const MAX_BATCH_CHARS: usize = 32000;
+
+// What a change counts for against the batch limits, as (values, chars).
+// UPSERTs count double, since Route53 treats them as a delete and a create.
+fn change_size(chg: &Change) -> (usize, usize) {
+	let recs = match &chg.resource_record_set.resource_records {
+		Some(x) => x,
+		None => { return (0, 0); }
+	};
+	let values = recs.len();
+	let chars = recs.iter().map(|r| r.value.chars().count()).sum();
+	match chg.action.as_str() {
+		"UPSERT" => (values * 2, chars * 2),
+		_ => (values, chars)
+	}
+}
+
+// Split changes up into batches Route53 will accept.  Order is kept, since
+// a change can depend on an earlier one (a CREATE on the DELETE that makes
+// room for it), and filling each batch as full as it goes before starting
+// the next makes the fewest batches possible without reordering.  Errors if
+// any one change is too big to send at all.
+pub fn build_batches(changes: Vec<Change>) -> Result<Vec<Vec<Change>>, String> {
+	let mut retval = Vec::new();
+	let mut batch = Vec::new();
+	let (mut values, mut chars) = (0, 0);
+	for chg in changes {
+		let (v, c) = change_size(&chg);
+		if v > MAX_BATCH_VALUES || c > MAX_BATCH_CHARS {
+			return Err(format!("{} {} {} is too big for one Route53 change batch ({} values, {} characters)",
+			                   chg.action, chg.resource_record_set.type_, chg.resource_record_set.name, v, c));
+		}
+		if !batch.is_empty() && (values + v > MAX_BATCH_VALUES || chars + c > MAX_BATCH_CHARS) {
+			retval.push(batch);
+			batch = Vec::new();
+			values = 0;
+			chars = 0;
+		}
+		values += v;
+		chars += c;
+		batch.push(chg);
+	}
+	if !batch.is_empty() {
+		retval.push(batch);
+	}
+	Ok(retval)
+}
+
+// Ask Route53 how a change is getting on: PENDING or INSYNC
+pub fn get_change_status(conf: &MacrotisProviderConfig, change_id: &str) -> Result<String, String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	// Change IDs come back as /change/<id>, but GetChange wants the bare ID
+	let req = GetChangeRequest {
+		id: change_id.trim_start_matches("/change/").to_string()
+	};
+	let policy = RetryPolicy::from_config(&conf);
+	match with_retry(&policy, "GetChange",
+	                 || client.get_change(req.to_owned()).sync(),
+	                 |e| match e {
+	                     GetChangeError::InvalidInput(_) | GetChangeError::NoSuchChange(_) => false
+	                 }) {
+		Ok(output) => Ok(output.change_info.status),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+// Retrieve every health check in the account, keyed by ID
+pub fn fetch_health_checks(conf: &MacrotisProviderConfig) -> Option<HashMap<String, HealthCheck>> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			println!("Error creating Route53 Client");
+			return None;
+		}
+	};
+
+	let mut retval = HashMap::new();
+	let mut req = ListHealthChecksRequest { marker: None, max_items: None };
+	let policy = RetryPolicy::from_config(&conf);
+	loop {
+		let result = with_retry(&policy, "ListHealthChecks",
+		                        || client.list_health_checks(req.to_owned()).sync(),
+		                        |e| match e {
+		                            ListHealthChecksError::IncompatibleVersion(_) |
+		                            ListHealthChecksError::InvalidInput(_) => false
+		                        });
+		match result {
+			Err(e) => {
+				println!("Error fetching health checks from Route53: {}", e);
+				return None;
+			},
+			Ok(output) => {
+				for hc in output.health_checks {
+					retval.insert(hc.id, check_from_r53(hc.health_check_config));
+				}
+				if output.is_truncated {
+					req.marker = output.next_marker;
+				} else {
+					break;
+				}
 			}
+		}
+	}
+	Some(retval)
+}
+
+// Create a health check and label it with a Name tag, which is what the
+// console shows.  The check is already made if tagging fails, so that only
+// gets a warning.
+pub fn create_health_check(conf: &MacrotisProviderConfig, reference: &str, name: &str, check: &HealthCheck) -> Result<String, String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let req = CreateHealthCheckRequest {
+		caller_reference: reference.to_string(),
+		health_check_config: check_to_r53(check)
+	};
+	let policy = RetryPolicy::from_config(&conf);
+	let result = with_retry(&policy, "CreateHealthCheck",
+	                        || client.create_health_check(req.to_owned()).sync(),
+	                        |e| match e {
+	                            CreateHealthCheckError::HealthCheckAlreadyExists(_) |
+	                            CreateHealthCheckError::InvalidInput(_) |
+	                            CreateHealthCheckError::TooManyHealthChecks(_) => false
+	                        });
+	let id = match result {
+		Ok(output) => output.health_check.id,
+		Err(e) => { return Err(e.to_string()); }
+	};
+
+	let tag = ChangeTagsForResourceRequest {
+		add_tags: Some(vec![Tag { key: Some("Name".to_string()), value: Some(name.to_string()) }]),
+		remove_tag_keys: None,
+		resource_id: id.to_string(),
+		resource_type: "healthcheck".to_string()
+	};
+	let result = with_retry(&policy, "ChangeTagsForResource",
+	                        || client.change_tags_for_resource(tag.to_owned()).sync(),
+	                        |e| match e {
+	                            ChangeTagsForResourceError::PriorRequestNotComplete(_) |
+	                            ChangeTagsForResourceError::Throttling(_) => true,
+	                            ChangeTagsForResourceError::InvalidInput(_) |
+	                            ChangeTagsForResourceError::NoSuchHealthCheck(_) |
+	                            ChangeTagsForResourceError::NoSuchHostedZone(_) => false
+	                        });
+	if let Err(e) = result {
+		println!("[WARNING] Couldn't tag health check {} with its name: {}", id, e);
+	}
+	Ok(id)
+}
+
+// Bring an existing health check's settings in line with 'check'.  Fields
+// that have been dropped have to be reset by name, since leaving them out
+// of the request leaves them as they were.
+pub fn update_health_check(conf: &MacrotisProviderConfig, id: &str, check: &HealthCheck) -> Result<(), String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let mut reset = Vec::new();
+	if check.fqdn.is_none() && !check.is_calculated() {
+		reset.push("FullyQualifiedDomainName".to_string());
+	}
+	if check.resource_path.is_none() && !check.is_calculated() {
+		reset.push("ResourcePath".to_string());
+	}
+	let cfg = check_to_r53(check);
+	let req = UpdateHealthCheckRequest {
+		health_check_id: id.to_string(),
+		child_health_checks: cfg.child_health_checks,
+		enable_sni: cfg.enable_sni,
+		failure_threshold: cfg.failure_threshold,
+		fully_qualified_domain_name: cfg.fully_qualified_domain_name,
+		health_threshold: cfg.health_threshold,
+		ip_address: cfg.ip_address,
+		inverted: Some(cfg.inverted.unwrap_or(false)),
+		port: cfg.port,
+		resource_path: cfg.resource_path,
+		reset_elements: if reset.is_empty() { None } else { Some(reset) },
+		..Default::default()
+	};
+	let policy = RetryPolicy::from_config(&conf);
+	match with_retry(&policy, "UpdateHealthCheck",
+	                 || client.update_health_check(req.to_owned()).sync(),
+	                 |e| match e {
+	                     UpdateHealthCheckError::HealthCheckVersionMismatch(_) => true,
+	                     _ => false
+	                 }) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+// Delete a health check.  Route53 refuses while a record still uses it,
+// which can take a moment to clear after the record changes go through.
+pub fn delete_health_check(conf: &MacrotisProviderConfig, id: &str) -> Result<(), String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let req = DeleteHealthCheckRequest { health_check_id: id.to_string() };
+	let policy = RetryPolicy::from_config(&conf);
+	match with_retry(&policy, "DeleteHealthCheck",
+	                 || client.delete_health_check(req.to_owned()).sync(),
+	                 |e| match e {
+	                     DeleteHealthCheckError::HealthCheckInUse(_) => true,
+	                     _ => false
+	                 }) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+// List every hosted zone named 'domain'.  Zones are listed in name order
+// starting from the one asked for, so this stops at the first other name.
+fn list_zones(conf: &MacrotisProviderConfig, domain: &str) -> Result<Vec<HostedZone>, String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let wanted = format!("{}.", domain.trim_end_matches('.').to_lowercase());
+	let mut retval = Vec::new();
+	let mut req = ListHostedZonesByNameRequest { dns_name: Some(wanted.to_string()), hosted_zone_id: None, max_items: None };
+	let policy = RetryPolicy::from_config(&conf);
+	loop {
+		let result = with_retry(&policy, "ListHostedZonesByName",
+		                        || client.list_hosted_zones_by_name(req.to_owned()).sync(),
+		                        |e| match e {
+		                            ListHostedZonesByNameError::InvalidDomainName(_) |
+		                            ListHostedZonesByNameError::InvalidInput(_) => false
+		                        });
+		let output = match result {
+			Ok(x) => x,
+			Err(e) => { return Err(e.to_string()); }
 		};
-		if records.is_empty() {
+		let mut past = false;
+		for hz in output.hosted_zones {
+			if hz.name.to_lowercase() != wanted {
+				past = true;
+				break;
+			}
+			retval.push(hz);
+		}
+		if past || !output.is_truncated {
 			break;
 		}
+		req.dns_name = output.next_dns_name;
+		req.hosted_zone_id = output.next_hosted_zone_id;
+	}
+	Ok(retval)
+}
+
+fn zone_id(hz: &HostedZone) -> String {
+	hz.id.trim_start_matches("/hostedzone/").to_string()
+}
+
+// Find every hosted zone named 'domain'
+pub fn find_zones(conf: &MacrotisProviderConfig, domain: &str) -> Result<Vec<RemoteZone>, String> {
+	Ok(list_zones(&conf, domain)?.iter().map(|hz| RemoteZone {
+		id: zone_id(hz),
+		private: hz.config.as_ref().and_then(|x| x.private_zone).unwrap_or(false)
+	}).collect())
+}
+
+// The zone that was made with a given caller reference, if any
+fn zone_with_reference(zones: &Vec<HostedZone>, reference: &str) -> Option<String> {
+	zones.iter().find(|hz| hz.caller_reference == reference).map(zone_id)
+}
+
+fn zone_vpc(vpc: &ZoneVpc) -> VPC {
+	VPC { vpc_id: Some(vpc.vpc_id.to_string()), vpc_region: Some(vpc.region.to_string()) }
+}
+
+// Create a hosted zone.  A private zone can only be made with one VPC, so
+// any others are associated with it afterwards.  If a retry finds the
+// caller reference already used, an earlier attempt made the zone before
+// failing, so that's the zone to carry on with.
+pub fn create_zone(conf: &MacrotisProviderConfig, zone: &Zone, reference: &str) -> Result<String, String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let vpcs = zone.vpcs.clone().unwrap_or_default();
+	let req = CreateHostedZoneRequest {
+		caller_reference: reference.to_string(),
+		name: zone.domain.to_string(),
+		delegation_set_id: zone.delegation_set_id.clone(),
+		hosted_zone_config: Some(HostedZoneConfig {
+			comment: Some(format!("Managed by Macrotis ({})", zone.name)),
+			private_zone: Some(zone.is_private())
+		}),
+		vpc: vpcs.first().map(zone_vpc)
+	};
+	let policy = RetryPolicy::from_config(&conf);
+	let result = with_retry(&policy, "CreateHostedZone",
+	                        || client.create_hosted_zone(req.to_owned()).sync(),
+	                        |e| match e {
+	                            CreateHostedZoneError::ConflictingDomainExists(_) |
+	                            CreateHostedZoneError::DelegationSetNotAvailable(_) |
+	                            CreateHostedZoneError::DelegationSetNotReusable(_) |
+	                            CreateHostedZoneError::HostedZoneAlreadyExists(_) |
+	                            CreateHostedZoneError::InvalidDomainName(_) |
+	                            CreateHostedZoneError::InvalidInput(_) |
+	                            CreateHostedZoneError::InvalidVPCId(_) |
+	                            CreateHostedZoneError::NoSuchDelegationSet(_) |
+	                            CreateHostedZoneError::TooManyHostedZones(_) => false
+	                        });
+	let id = match result {
+		Ok(output) => zone_id(&output.hosted_zone),
+		Err(RusotoError::Service(CreateHostedZoneError::HostedZoneAlreadyExists(e))) => {
+			match zone_with_reference(&list_zones(&conf, &zone.domain)?, reference) {
+				Some(x) => x,
+				None => { return Err(e); }
+			}
+		},
+		Err(e) => { return Err(e.to_string()); }
+	};
+
+	for v in vpcs.iter().skip(1) {
+		let assoc = AssociateVPCWithHostedZoneRequest {
+			comment: None,
+			hosted_zone_id: id.to_string(),
+			vpc: zone_vpc(v)
+		};
+		let result = with_retry(&policy, "AssociateVPCWithHostedZone",
+		                        || client.associate_vpc_with_hosted_zone(assoc.to_owned()).sync(),
+		                        |e| match e {
+		                            AssociateVPCWithHostedZoneError::PriorRequestNotComplete(_) => true,
+		                            _ => false
+		                        });
+		if let Err(e) = result {
+			return Err(format!("created zone {} but couldn't associate {} with it: {}", id, v.vpc_id, e));
+		}
+	}
+	Ok(id)
+}
+
+// The nameservers from a zone's delegation set
+pub fn zone_nameservers(conf: &MacrotisProviderConfig, zone_id: &str) -> Result<Vec<String>, String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let req = GetHostedZoneRequest { id: zone_id.to_string() };
+	let policy = RetryPolicy::from_config(&conf);
+	match with_retry(&policy, "GetHostedZone",
+	                 || client.get_hosted_zone(req.to_owned()).sync(),
+	                 |e| match e {
+	                     GetHostedZoneError::InvalidInput(_) | GetHostedZoneError::NoSuchHostedZone(_) => false
+	                 }) {
+		Ok(output) => Ok(output.delegation_set.map(|x| x.name_servers).unwrap_or_default()),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+pub fn delete_zone(conf: &MacrotisProviderConfig, zone_id: &str) -> Result<(), String> {
+	let client = match build_client(&conf) {
+		Some(x) => x,
+		None => {
+			return Err("Error creating Route53 Client".to_string());
+		}
+	};
+
+	let req = DeleteHostedZoneRequest { id: zone_id.to_string() };
+	let policy = RetryPolicy::from_config(&conf);
+	match with_retry(&policy, "DeleteHostedZone",
+	                 || client.delete_hosted_zone(req.to_owned()).sync(),
+	                 |e| match e {
+	                     DeleteHostedZoneError::PriorRequestNotComplete(_) => true,
+	                     _ => false
+	                 }) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(e.to_string())
 	}
-	Ok("Success".to_string())		
 }
-              
+
+// Convert between our HealthCheck and Route53's config for one.  HTTPS
+// checks always send SNI, so name-based hosting works.
+fn check_to_r53(check: &HealthCheck) -> HealthCheckConfig {
+	let check_type = check.check_type.to_uppercase();
+	HealthCheckConfig {
+		enable_sni: if check_type == "HTTPS" { Some(true) } else { None },
+		type_: check_type,
+		ip_address: check.ip_address.clone(),
+		fully_qualified_domain_name: check.fqdn.clone(),
+		port: check.port,
+		resource_path: check.resource_path.clone(),
+		request_interval: check.request_interval,
+		failure_threshold: check.failure_threshold,
+		child_health_checks: check.children.clone(),
+		health_threshold: check.health_threshold,
+		inverted: check.inverted,
+		..Default::default()
+	}
+}
+
+fn check_from_r53(cfg: HealthCheckConfig) -> HealthCheck {
+	let calculated = cfg.type_ == "CALCULATED";
+	HealthCheck {
+		check_type: cfg.type_,
+		ip_address: cfg.ip_address,
+		fqdn: cfg.fully_qualified_domain_name,
+		port: cfg.port,
+		resource_path: cfg.resource_path,
+		request_interval: if calculated { None } else { cfg.request_interval },
+		failure_threshold: if calculated { None } else { cfg.failure_threshold },
+		children: cfg.child_health_checks,
+		health_threshold: cfg.health_threshold,
+		inverted: cfg.inverted
+	}
+}
+
 // Take a Vec of Route53 ResourceRecordSet structs, convert to a Vec of
 // MacrotisRecord structs
 fn parse_records(records: Vec<ResourceRecordSet>, zone: &str) -> Vec<Resource> {
@@ -150,12 +680,37 @@ fn parse_records(records: Vec<ResourceRecordSet>, zone: &str) -> Vec<Resource> {
             },
             None => {}
         };
+        let geo = match rec.geo_location {
+            Some(x) => Some(GeoLocation {
+                continent_code: x.continent_code,
+                country_code: x.country_code,
+                subdivision_code: x.subdivision_code
+            }),
+            None => None
+        };
+        // Aliases have no TTL or values of their own
+        let (ttl, alias) = match rec.alias_target {
+            Some(x) => (0, Some(AliasTarget {
+                hosted_zone_id: x.hosted_zone_id,
+                dns_name: x.dns_name.trim_end_matches('.').to_lowercase(),
+                evaluate_target_health: x.evaluate_target_health
+            })),
+            None => (ttl, None)
+        };
         let mac_rec = Resource {
             zone_id: zone.to_string(),
             name: name.trim_end_matches('.').to_string(),
             rtype: rtype.to_string(),
             records: values,
-            ttl: ttl
+            ttl: ttl,
+            set_identifier: rec.set_identifier,
+            geolocation: geo,
+            alias_target: alias,
+            weight: rec.weight,
+            region: rec.region,
+            failover: rec.failover,
+            multivalue: rec.multi_value_answer,
+            health_check_id: rec.health_check_id
         };
         retval.push(mac_rec);
     }
@@ -168,44 +723,165 @@ fn parse_records(records: Vec<ResourceRecordSet>, zone: &str) -> Vec<Resource> {
 pub fn macrotis_to_r53(resources: &Vec<Resource>, action: &str) -> Vec<Change> {
 	let mut retval = Vec::new();
 	for res in resources {
-		// Turn the records part into an array of hashes for some
-		// godforsaken reason
-		let mut rrvec: Vec<ResourceRecord> = Vec::new();
-		for rec in &res.records {
-			let rr = ResourceRecord { value: rec.to_string() };
-			rrvec.push(rr);
-		}
-		let rrs = ResourceRecordSet {
-			name: res.name.to_string(),
-			type_: res.rtype.to_string(),
-			ttl: Some(res.ttl),
-			resource_records: Some(rrvec),
-			..Default::default()
-		};
-		let change = Change {
-			action: action.to_string(),
-			resource_record_set: rrs
-		};
-		retval.push(change);
+		retval.push(resource_to_change(action, res));
 	}
 	retval
 }
 
 pub fn resource_to_change(action: &str, res: &Resource) -> Change {
+	// Turn the records part into an array of hashes for some
+	// godforsaken reason
 	let mut rrvec: Vec<ResourceRecord> = Vec::new();
 	for rec in &res.records {
 		let rr = ResourceRecord { value: rec.to_string() };
 		rrvec.push(rr);
 	}
-	let rrs = ResourceRecordSet {
+	let geo = match &res.geolocation {
+		Some(x) => Some(R53GeoLocation {
+			continent_code: x.continent_code.clone(),
+			country_code: x.country_code.clone(),
+			subdivision_code: x.subdivision_code.clone()
+		}),
+		None => None
+	};
+	let mut rrs = ResourceRecordSet {
 		name: res.name.to_string(),
 		type_: res.rtype.to_string(),
 		ttl: Some(res.ttl),
 		resource_records: Some(rrvec),
+		set_identifier: res.set_identifier.clone(),
+		geo_location: geo,
+		weight: res.weight,
+		region: res.region.clone(),
+		failover: res.failover.clone(),
+		multi_value_answer: res.multivalue,
+		health_check_id: res.health_check_id.clone(),
 		..Default::default()
 	};
+	// An alias replaces the TTL and values, Route53 refuses them together
+	if let Some(x) = &res.alias_target {
+		rrs.ttl = None;
+		rrs.resource_records = None;
+		rrs.alias_target = Some(R53AliasTarget {
+			hosted_zone_id: x.hosted_zone_id.to_string(),
+			dns_name: x.dns_name.to_string(),
+			evaluate_target_health: x.evaluate_target_health
+		});
+	}
 	Change {
 		action: action.to_string(),
 		resource_record_set: rrs
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A change to a TXT record with the given values
+	fn change(action: &str, name: &str, values: Vec<String>) -> Change {
+		Change {
+			action: action.to_string(),
+			resource_record_set: ResourceRecordSet {
+				name: name.to_string(),
+				type_: "TXT".to_string(),
+				resource_records: Some(values.into_iter().map(|v| ResourceRecord { value: v }).collect()),
+				..Default::default()
+			}
+		}
+	}
+
+	fn sizes(batches: &Vec<Vec<Change>>) -> Vec<usize> {
+		batches.iter().map(|b| b.len()).collect()
+	}
+
+	#[test]
+	fn test_build_batches() {
+		// No more splitting at 99 changes
+		let changes: Vec<Change> = (0..150).map(|i| change("CREATE", &format!("r{}", i), vec!["v".to_string()])).collect();
+		assert_eq!(sizes(&build_batches(changes).unwrap()), vec![150]);
+
+		// Exactly 1000 values fit; one more starts a new batch
+		let changes: Vec<Change> = (0..1001).map(|i| change("DELETE", &format!("r{}", i), vec!["v".to_string()])).collect();
+		let batches = build_batches(changes).unwrap();
+		assert_eq!(sizes(&batches), vec![1000, 1]);
+		assert_eq!(batches[1][0].resource_record_set.name, "r1000");
+
+		// UPSERTs count twice
+		let changes: Vec<Change> = (0..501).map(|i| change("UPSERT", &format!("r{}", i), vec!["v".to_string()])).collect();
+		assert_eq!(sizes(&build_batches(changes).unwrap()), vec![500, 1]);
+
+		// 160 values of 200 characters is exactly 32000
+		let long = "x".repeat(200);
+		let changes: Vec<Change> = (0..200).map(|i| change("CREATE", &format!("r{}", i), vec![long.to_string()])).collect();
+		assert_eq!(sizes(&build_batches(changes).unwrap()), vec![160, 40]);
+
+		// Order is kept even when a later, smaller change would fit
+		let changes = vec![
+			change("CREATE", "big1", vec!["v".to_string(); 600]),
+			change("CREATE", "big2", vec!["v".to_string(); 600]),
+			change("DELETE", "small", vec!["v".to_string()])
+		];
+		let batches = build_batches(changes).unwrap();
+		assert_eq!(sizes(&batches), vec![1, 2]);
+		assert_eq!(batches[1][1].resource_record_set.name, "small");
+		assert!(build_batches(Vec::new()).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_oversized_change() {
+		// 600 values is fine to create, but too many to upsert
+		assert!(build_batches(vec![change("CREATE", "big", vec!["v".to_string(); 600])]).is_ok());
+		assert!(build_batches(vec![change("UPSERT", "big", vec!["v".to_string(); 600])]).is_err());
+		assert!(build_batches(vec![change("CREATE", "long", vec!["x".repeat(32001)])]).is_err());
+	}
+
+	#[test]
+	fn test_routing_round_trip() {
+		let res = Resource {
+			zone_id: "Z1".to_string(),
+			name: "www.test.com".to_string(),
+			rtype: "A".to_string(),
+			records: vec!["1.2.3.4".to_string()],
+			ttl: 60,
+			set_identifier: Some("a".to_string()),
+			weight: Some(70),
+			health_check_id: Some("hc-1".to_string()),
+			..Default::default()
+		};
+		let chg = resource_to_change("CREATE", &res);
+		assert_eq!(chg.resource_record_set.weight, Some(70));
+		assert_eq!(chg.resource_record_set.set_identifier, Some("a".to_string()));
+		assert_eq!(chg.resource_record_set.health_check_id, Some("hc-1".to_string()));
+		assert_eq!(chg.resource_record_set.region, None);
+		let back = parse_records(vec![chg.resource_record_set], "Z1");
+		assert_eq!(back, vec![res]);
+	}
+
+	#[test]
+	fn test_check_round_trip() {
+		let check = HealthCheck {
+			check_type: "HTTPS".to_string(),
+			fqdn: Some("www.test.com".to_string()),
+			resource_path: Some("/health".to_string()),
+			..Default::default()
+		}.normalized();
+		let cfg = check_to_r53(&check);
+		assert_eq!(cfg.port, Some(443));
+		assert_eq!(cfg.enable_sni, Some(true));
+		assert_eq!(check_from_r53(cfg), check);
+	}
+
+	#[test]
+	fn test_zone_with_reference() {
+		let zone = |id: &str, reference: &str| HostedZone {
+			id: format!("/hostedzone/{}", id),
+			name: "test.com.".to_string(),
+			caller_reference: reference.to_string(),
+			..Default::default()
+		};
+		let zones = vec![zone("Z1", "macrotis-test.com-1"), zone("Z2", "macrotis-test.com-2")];
+		assert_eq!(zone_with_reference(&zones, "macrotis-test.com-2"), Some("Z2".to_string()));
+		assert_eq!(zone_with_reference(&zones, "macrotis-test.com-3"), None);
+	}
+}
@@ -3,20 +3,85 @@
 use std::collections::HashMap;
 use tinydns::TinyDNSRecord;
 use tinydns;
-use Zone;
+use MacrotisConfig;
 
-// What is a resource?  Dns data with a zone_id attached.
-#[derive(Serialize, Deserialize, Debug, Hash, Clone)]
+// What is a resource?  Dns data with a zone_id attached.  Resources that
+// are only served to some clients carry a set_identifier and either a
+// geolocation to say which ones, or else one of the other Route53 routing
+// policies (weight, latency region, failover, or multivalue).  Alias
+// resources point at an alias_target instead of having records, and have a
+// TTL of 0.
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, Default)]
 pub struct Resource {
     pub zone_id: String,
     pub name: String,
     pub rtype: String,
     pub records: Vec<String>,
-    pub ttl: i64
+    pub ttl: i64,
+    pub set_identifier: Option<String>,
+    pub geolocation: Option<GeoLocation>,
+    pub alias_target: Option<AliasTarget>,
+    pub weight: Option<i64>,
+    pub region: Option<String>,
+    pub failover: Option<String>,
+    pub multivalue: Option<bool>,
+    pub health_check_id: Option<String>
 }
 
-// A collection of Resources uses the type+name to generate a unique
-// 'key' for easy comparison and iteration
+// The routing policy given to a set of records by a '!' line.  Only one
+// of weight, region, failover, and multivalue is set; the health check
+// can go with any of them.
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct RoutingPolicy {
+    pub weight: Option<i64>,
+    pub region: Option<String>,
+    pub failover: Option<String>,
+    pub multivalue: Option<bool>,
+    pub health_check_id: Option<String>
+}
+
+impl RoutingPolicy {
+    // The policy's name, as written in a '!' line
+    pub fn kind(&self) -> &'static str {
+        if self.weight.is_some() {
+            "weight"
+        } else if self.region.is_some() {
+            "latency"
+        } else if self.failover.is_some() {
+            "failover"
+        } else if self.multivalue.is_some() {
+            "multivalue"
+        } else {
+            ""
+        }
+    }
+}
+
+// A Route53 alias: the DNS name of what's being pointed at (a load
+// balancer, CloudFront distribution, S3 website, or another record) and
+// the hosted zone that name lives in.
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct AliasTarget {
+    pub hosted_zone_id: String,
+    pub dns_name: String,
+    pub evaluate_target_health: bool
+}
+
+// CloudFront distributions all live in this hosted zone
+pub const CLOUDFRONT_ZONE_ID: &str = "Z2FDTNDATAQYW2";
+
+// A Route53-style geolocation.  A country_code of "*" is the default for
+// clients that don't match any other location.
+#[derive(Serialize, Deserialize, Debug, Hash, Clone, PartialEq, Eq, Default)]
+pub struct GeoLocation {
+    pub continent_code: Option<String>,
+    pub country_code: Option<String>,
+    pub subdivision_code: Option<String>
+}
+
+// A collection of Resources uses the type+name (plus the set identifier,
+// if there is one) to generate a unique 'key' for easy comparison and
+// iteration
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ResHash(pub HashMap<String, Resource>);
 
@@ -33,23 +98,62 @@ impl PartialEq for Resource {
         self.name    == other.name &&
         self.rtype   == other.rtype &&
         my_records   == other_records &&
-        self.ttl     == other.ttl
+        self.ttl     == other.ttl &&
+        self.set_identifier == other.set_identifier &&
+        self.geolocation    == other.geolocation &&
+        self.alias_target   == other.alias_target &&
+        self.weight         == other.weight &&
+        self.region         == other.region &&
+        self.failover       == other.failover &&
+        self.multivalue     == other.multivalue &&
+        self.health_check_id == other.health_check_id
     }
 }
 
 // And why not implement display?
 impl std::fmt::Display for Resource {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}\t{}\tIN\t{}\t{:?}", self.name, self.ttl, self.rtype, self.records)
+        match &self.alias_target {
+            Some(x) => write!(f, "{}\t{}\tIN\t{}\tALIAS {} ({})", self.name, self.ttl, self.rtype,
+                              x.dns_name, x.hosted_zone_id),
+            None => write!(f, "{}\t{}\tIN\t{}\t{:?}", self.name, self.ttl, self.rtype, self.records)
+        }
     }
 }
 
 impl Resource {
+    // Which routing policy the resource uses, for telling apart the ones
+    // Route53 won't allow to share a name and type
+    pub fn routing_policy(&self) -> &'static str {
+        if self.geolocation.is_some() {
+            "geolocation"
+        } else if self.set_identifier.is_none() {
+            "simple"
+        } else {
+            match self.routing().kind() {
+                "" => "unknown",
+                x => x
+            }
+        }
+    }
+
+    // Just the fields a '!' line sets
+    pub fn routing(&self) -> RoutingPolicy {
+        RoutingPolicy {
+            weight: self.weight,
+            region: self.region.clone(),
+            failover: self.failover.clone(),
+            multivalue: self.multivalue,
+            health_check_id: self.health_check_id.clone()
+        }
+    }
+
     // Merge the records vectors of this and another struct
     // Return false if the record types are mismatched or there's
     // any other sorts of issues with the merge
     pub fn merge(&mut self, other: &Self) -> bool {
-        if self.rtype != other.rtype {
+        // An alias is the whole record set, so there's nothing to merge
+        if self.rtype != other.rtype || self.alias_target.is_some() || other.alias_target.is_some() {
             return false;
         }
         let mut newvec = other.records.clone();
@@ -59,6 +163,19 @@ impl Resource {
     }
 }
 
+// Generate a string from the resource type and name (plus the set
+// identifier, if any) to serve as a unique identifier/hashmap key.
+// Clean up any trailing dots.
+pub fn resource_key(rec: &Resource) -> String {
+	let mut record_name = format!("{}-{}", &rec.rtype, &rec.name);
+	record_name = record_name.trim_end_matches('.').to_string();
+	record_name = record_name.replace(".", "-").to_ascii_lowercase();
+	if let Some(x) = &rec.set_identifier {
+		record_name = format!("{}-{}", record_name, x);
+	}
+	record_name
+}
+
 // Build a ResHash from a Vec of Resources.  Combine the records Vecs
 // of any duplicate names, unless they are PTRs, then complain.
 pub fn build_reshash(records: Vec<Resource>) -> Option<ResHash> {
@@ -66,11 +183,7 @@ pub fn build_reshash(records: Vec<Resource>) -> Option<ResHash> {
 	let mut error_flag = false;
 	
 	for mut rec in records {
-		// Generate a string from the resource type and name to serve as
-		// a unique identifier/hashmap key.  Clean up any trailing dots.
-		let mut record_name = format!("{}-{}", &rec.rtype, &rec.name);
-		record_name = record_name.trim_end_matches('.').to_string();
-		record_name = record_name.replace(".", "-").to_ascii_lowercase();
+		let record_name = resource_key(&rec);
 		
 		// Check for an existing resource in the hashmap.  Merge the
 		// 'records' arrays (unless it's a PTR, then complain).
@@ -102,16 +215,49 @@ pub fn build_reshash(records: Vec<Resource>) -> Option<ResHash> {
   }
 }
 
+// Turn a TDR's target into a value the way Route53 wants it.  That's
+// just the target, except for TXT records from ' lines, whose text has to
+// be split into quoted strings of no more than 255 bytes each.  Those
+// never start with a quote (parse_txt strips them), while generic TXT
+// records come out of decode_rdata already quoted.
+pub fn record_value(rec: &TinyDNSRecord) -> String {
+	if rec.rtype != "TXT" || rec.target.starts_with('"') {
+		return rec.target.to_string();
+	}
+	let mut chunks = Vec::new();
+	let mut current = String::new();
+	for c in rec.target.chars() {
+		if current.len() + c.len_utf8() > 255 {
+			chunks.push(tinydns::quote(&current));
+			current.clear();
+		}
+		current.push(c);
+	}
+	chunks.push(tinydns::quote(&current));
+	chunks.join(" ")
+}
+
 // Build a Vec of Resources from a Vec of TinyDNSRecords when supplied
-// with Zone metadata
-pub fn vec_from_tiny(records: &Vec<TinyDNSRecord>, zones: &Vec<Zone>) -> Option<Vec<Resource>> {
+// with the config (for zone and location metadata).  Routing policies from
+// '!' lines are used here and don't become Resources themselves.
+pub fn vec_from_tiny(records: &Vec<TinyDNSRecord>, config: &MacrotisConfig) -> Option<Vec<Resource>> {
 	let mut retval = Vec::new();
 	// A flag in case any problems are encountered
 	let mut error_flag = false;
+	let policies = match tinydns::policies(&records) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("Error: {}", e);
+			return None;
+		}
+	};
 	
 	// For each TDR, find its zone_id and build a Resource struct
 	for rec in records {
-		let zone_id = match tinydns::find_zone_id(&rec, &zones) {
+		if rec.rtype == "!" {
+			continue;
+		}
+		let zone_id = match tinydns::find_zone_id(&rec, &config.zones) {
 			Some(x) => x,
 			None => {
 				println!("Warning: Unable to find zone_id for {}", rec.fqdn);
@@ -121,15 +267,66 @@ pub fn vec_from_tiny(records: &Vec<TinyDNSRecord>, zones: &Vec<Zone>) -> Option<
 		};
 		
 		// Create a Resource struct
-		let res = Resource {
+		let mut res = Resource {
 			zone_id: zone_id.to_string(),
 			name:    rec.fqdn.to_string(),
 			rtype:   rec.rtype.to_string(),
-			records:  vec![rec.target.to_string()],
-			ttl:     rec.ttl as i64
+			records:  vec![record_value(&rec)],
+			ttl:     rec.ttl as i64,
+			set_identifier: None,
+			geolocation: None,
+			alias_target: None,
+			weight: None,
+			region: None,
+			failover: None,
+			multivalue: None,
+			health_check_id: None
 		};
+
+		// Aliases have a target instead of records
+		if let Some(alias) = &rec.alias {
+			match resolve_alias(&alias, &config) {
+				Some(x) => {
+					res.records = Vec::new();
+					res.alias_target = Some(x);
+				},
+				None => {
+					println!("Error: alias {} -> {} needs the hosted zone ID of its target", rec.fqdn, alias.dns_name);
+					error_flag = true;
+					continue;
+				}
+			};
+		}
+
+		// Work out where located records should be served.  A location
+		// with a '!' line for this name is a set of records with that
+		// routing policy.  Anything else has to be mapped to a geolocation
+		// in the config.
+		if let Some(lo) = &rec.location {
+			if let Some(p) = policies.get(&(rec.fqdn.to_lowercase(), lo.to_string())) {
+				res.weight = p.weight;
+				res.region = p.region.clone();
+				res.failover = p.failover.clone();
+				res.multivalue = p.multivalue;
+				res.health_check_id = p.health_check_id.clone();
+			} else {
+				match config.locations.as_ref().and_then(|l| l.get(lo)) {
+					Some(x) => { res.geolocation = Some(x.clone()); },
+					None => {
+						println!("Error: {} uses unknown location '{}'", rec.fqdn, lo);
+						error_flag = true;
+						continue;
+					}
+				};
+			}
+			res.set_identifier = Some(lo.to_string());
+		}
 		retval.push(res);
 	}
+	set_location_defaults(&mut retval);
+	if !check_routing(&retval) {
+		error_flag = true;
+	}
 	if error_flag {
 		None
 	} else {
@@ -137,6 +334,77 @@ pub fn vec_from_tiny(records: &Vec<TinyDNSRecord>, zones: &Vec<Zone>) -> Option<
 	}
 }
 
+// Fill in the hosted zone of an alias's target if the line left it out.
+// CloudFront's is always the same, and a target in one of the configured
+// zones (including the alias's own) is in that zone.  Anything else (load
+// balancers, S3 websites) depends on the region, so it has to be given.
+fn resolve_alias(alias: &AliasTarget, config: &MacrotisConfig) -> Option<AliasTarget> {
+	if !alias.hosted_zone_id.is_empty() {
+		return Some(alias.clone());
+	}
+	let zone_id = if alias.dns_name.ends_with(".cloudfront.net") {
+		CLOUDFRONT_ZONE_ID.to_string()
+	} else {
+		let target = TinyDNSRecord { fqdn: alias.dns_name.to_string(), ..Default::default() };
+		tinydns::find_zone_id(&target, &config.zones)?
+	};
+	Some(AliasTarget { hosted_zone_id: zone_id, ..alias.clone() })
+}
+
+// Route53 won't mix located and unlocated records with the same name and
+// type, so any unlocated ones that share a name with located ones become
+// the default ('*') location.
+fn set_location_defaults(resources: &mut Vec<Resource>) {
+	let mut located = Vec::new();
+	for res in resources.iter() {
+		if res.geolocation.is_some() {
+			located.push((res.rtype.to_string(), res.name.to_lowercase()));
+		}
+	}
+	for res in resources.iter_mut() {
+		if res.set_identifier.is_some() || !located.contains(&(res.rtype.to_string(), res.name.to_lowercase())) {
+			continue;
+		}
+		res.set_identifier = Some("default".to_string());
+		res.geolocation = Some(GeoLocation {
+			country_code: Some("*".to_string()),
+			..Default::default()
+		});
+	}
+}
+
+// Route53 wants every record set with the same name and type to use the
+// same routing policy, and only allows one primary and one secondary for
+// failover.  Complain about any that don't, rather than have the whole
+// change batch rejected.
+fn check_routing(resources: &Vec<Resource>) -> bool {
+	let mut retval = true;
+	let mut seen: HashMap<(String, String), &Resource> = HashMap::new();
+	let mut failover: HashMap<(String, String, String), &Resource> = HashMap::new();
+	for res in resources {
+		let key = (res.rtype.to_string(), res.name.to_lowercase());
+		match seen.get(&key) {
+			Some(x) if x.routing_policy() != res.routing_policy() => {
+				println!("Error: {} {} mixes {} and {} routing", res.rtype, res.name,
+				         x.routing_policy(), res.routing_policy());
+				retval = false;
+			},
+			Some(_) => {},
+			None => { seen.insert(key.clone(), res); }
+		};
+		if let Some(x) = &res.failover {
+			let fkey = (key.0, key.1, x.to_string());
+			if let Some(other) = failover.insert(fkey, res) {
+				if other.set_identifier != res.set_identifier {
+					println!("Error: {} {} has more than one {} failover set", res.rtype, res.name, x);
+					retval = false;
+				}
+			}
+		}
+	}
+	retval
+}
+
 // Turn a ResHash into just a Vec of Resources. Because I need to do
 // that for some reason.  Consumes the ResHash, returns a
 // Vec<Resource>.
@@ -0,0 +1,206 @@
+// Module for retrying provider calls that fail for reasons that go away by
+// themselves: throttling, a previous change still being applied, network
+// trouble, or a server error.  Waits between attempts grow exponentially,
+// with 'full jitter' (a random wait up to the limit) so a crowd of runners
+// that got throttled together don't all come back at once.
+
+use std::fmt::Display;
+use std::time::Duration;
+use ring::rand::{SecureRandom, SystemRandom};
+use rusoto_core::RusotoError;
+use MacrotisProviderConfig;
+
+// Defaults for the retry settings in the provider config
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_MAX_DELAY_MS: u64 = 20000;
+
+// Error codes that only mean 'slow down'
+const RETRYABLE_CODES: [&str; 4] = ["Throttling", "ThrottlingException", "PriorRequestNotComplete",
+                                    "RequestLimitExceeded"];
+
+// How many times to try a call, and how long to wait between tries
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64
+}
+
+impl RetryPolicy {
+    // Take the limits from the provider config, or the defaults
+    pub fn from_config(conf: &MacrotisProviderConfig) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: std::cmp::max(conf.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS), 1),
+            base_delay_ms: conf.retry_base_delay_ms.unwrap_or(DEFAULT_BASE_DELAY_MS),
+            max_delay_ms: conf.retry_max_delay_ms.unwrap_or(DEFAULT_MAX_DELAY_MS)
+        }
+    }
+
+    // The longest wait after the given (1-based) failed attempt: base
+    // doubled for each attempt after the first, up to max
+    pub fn delay_limit(&self, attempt: u32) -> u64 {
+        let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::max_value());
+        std::cmp::min(self.base_delay_ms.saturating_mul(factor), self.max_delay_ms)
+    }
+
+    // A random wait between zero and delay_limit
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let limit = self.delay_limit(attempt);
+        let mut bytes = [0u8; 8];
+        let random = match SystemRandom::new().fill(&mut bytes) {
+            Ok(_) => u64::from_le_bytes(bytes),
+            Err(_) => u64::max_value()
+        };
+        Duration::from_millis(random % limit.saturating_add(1))
+    }
+}
+
+// Whether an error is worth another try.  'service' decides for the errors
+// a particular call defines, since those are different for every call;
+// callers match every variant rather than using a catch-all, so nothing
+// gets left unclassified.  Throttling a call doesn't define comes back as
+// an unknown error, and is caught by its error code.
+pub fn is_retryable<E, F>(err: &RusotoError<E>, service: F) -> bool
+    where F: Fn(&E) -> bool {
+    match err {
+        RusotoError::Service(e) => service(e),
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(resp) => resp.status.as_u16() >= 500 || is_throttled(resp.body_as_str()),
+        _ => false
+    }
+}
+
+// Whether an error says for sure that the request wasn't carried out, so
+// even a call that can't safely be made twice can be tried again.  A
+// network or server error might have come after the request was applied.
+pub fn is_rejected<E, F>(err: &RusotoError<E>, service: F) -> bool
+    where F: Fn(&E) -> bool {
+    match err {
+        RusotoError::Service(e) => service(e),
+        RusotoError::Unknown(resp) => is_throttled(resp.body_as_str()),
+        _ => false
+    }
+}
+
+fn is_throttled(body: &str) -> bool {
+    RETRYABLE_CODES.iter().any(|c| body.contains(&format!("<Code>{}</Code>", c)))
+}
+
+// Make a call, trying again after a wait if it fails with a retryable error,
+// up to the policy's attempt limit.  'what' names the call in messages.
+pub fn with_retry<T, E, C, F>(policy: &RetryPolicy, what: &str, call: C, service: F) -> Result<T, RusotoError<E>>
+    where C: FnMut() -> Result<T, RusotoError<E>>, F: Fn(&E) -> bool, RusotoError<E>: Display {
+    retry_while(policy, what, call, |e| is_retryable(e, &service))
+}
+
+// Like with_retry, for calls that mustn't be applied twice (a change batch
+// would fail its second time through).  Only errors that say the request
+// was turned away are retried; 'service' must only pass errors like that.
+pub fn with_retry_unapplied<T, E, C, F>(policy: &RetryPolicy, what: &str, call: C, service: F) -> Result<T, RusotoError<E>>
+    where C: FnMut() -> Result<T, RusotoError<E>>, F: Fn(&E) -> bool, RusotoError<E>: Display {
+    retry_while(policy, what, call, |e| is_rejected(e, &service))
+}
+
+fn retry_while<T, E, C, R>(policy: &RetryPolicy, what: &str, mut call: C, retryable: R) -> Result<T, RusotoError<E>>
+    where C: FnMut() -> Result<T, RusotoError<E>>, R: Fn(&RusotoError<E>) -> bool, RusotoError<E>: Display {
+    let mut attempt = 1;
+    loop {
+        match call() {
+            Ok(x) => { return Ok(x); },
+            Err(e) => {
+                if attempt >= policy.max_attempts || !retryable(&e) {
+                    return Err(e);
+                }
+                let wait = policy.delay(attempt);
+                println!("{} failed (attempt {} of {}), retrying in {}ms: {}",
+                         what, attempt, policy.max_attempts, wait.as_millis(), e);
+                std::thread::sleep(wait);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::HttpDispatchError;
+
+    #[derive(Debug, PartialEq)]
+    enum TestError { Busy, Broken }
+
+    impl std::fmt::Display for TestError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    fn busy(e: &TestError) -> bool {
+        *e == TestError::Busy
+    }
+
+    #[test]
+    fn test_delay_limit() {
+        let policy = RetryPolicy { max_attempts: 5, base_delay_ms: 100, max_delay_ms: 1000 };
+        assert_eq!(policy.delay_limit(1), 100);
+        assert_eq!(policy.delay_limit(3), 400);
+        assert_eq!(policy.delay_limit(5), 1000);
+        assert_eq!(policy.delay_limit(200), 1000);
+        for attempt in 1..6 {
+            assert!(policy.delay(attempt) <= Duration::from_millis(policy.delay_limit(attempt)));
+        }
+    }
+
+    #[test]
+    fn test_with_retry() {
+        let policy = RetryPolicy { max_attempts: 3, base_delay_ms: 0, max_delay_ms: 0 };
+
+        // Retryable errors get retried until the call works...
+        let mut calls = 0;
+        let result = with_retry(&policy, "test", || {
+            calls += 1;
+            if calls < 3 { Err(RusotoError::Service(TestError::Busy)) } else { Ok(calls) }
+        }, busy);
+        assert_eq!(result.unwrap(), 3);
+
+        // ...or the attempts run out
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry(&policy, "test", || {
+            calls += 1;
+            Err(RusotoError::Service(TestError::Busy))
+        }, busy);
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+
+        // Anything else fails straight away
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry(&policy, "test", || {
+            calls += 1;
+            Err(RusotoError::Service(TestError::Broken))
+        }, busy);
+        assert_eq!(result.unwrap_err(), RusotoError::Service(TestError::Broken));
+        assert_eq!(calls, 1);
+        assert!(!is_retryable(&RusotoError::Validation("bad".to_string()), busy));
+    }
+
+    #[test]
+    fn test_with_retry_unapplied() {
+        let policy = RetryPolicy { max_attempts: 3, base_delay_ms: 0, max_delay_ms: 0 };
+        let mut calls = 0;
+        let result = with_retry_unapplied(&policy, "test", || {
+            calls += 1;
+            if calls < 2 { Err(RusotoError::Service(TestError::Busy)) } else { Ok(calls) }
+        }, busy);
+        assert_eq!(result.unwrap(), 2);
+
+        // The request might have gone through before the connection broke
+        let mut calls = 0;
+        let result: Result<(), _> = with_retry_unapplied(&policy, "test", || {
+            calls += 1;
+            Err(RusotoError::HttpDispatch(HttpDispatchError::new("reset".to_string())))
+        }, busy);
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -1,44 +1,60 @@
 // Functions for talking to S3
-use std::str::FromStr;
 use MacrotisStateConfig;
-use state::MacrotisState;
-use rusoto_core::{Region, HttpClient, RusotoError};
+use aws;
+use std::io::Read;
+use state::{self, MacrotisState, MacrotisLock};
+use rusoto_core::{HttpClient, RusotoError};
 use rusoto_sts::{StsClient, StsAssumeRoleSessionCredentialsProvider};
-use rusoto_s3::{S3Client, S3, GetObjectRequest, PutObjectRequest, GetObjectError};
+use rusoto_s3::{S3Client, S3, GetObjectRequest, PutObjectRequest, GetObjectError, DeleteObjectRequest,
+                ListObjectsV2Request};
 
-// Build an S3Client for S3 operations
-fn build_client(conf: &MacrotisStateConfig) -> Option<S3Client> {
-    // Grab region from conf or use the default
-    let region = match &conf.region {
-        Some(x) => Region::from_str(&x).unwrap_or(Region::default()),
-        None => Region::default()
+// Credentials for the role in role_arn, if the state config assumes one.
+// The DynamoDB lock table uses these too.
+pub fn role_provider(conf: &MacrotisStateConfig) -> Option<StsAssumeRoleSessionCredentialsProvider> {
+    let arn = conf.role_arn.as_ref()?;
+    let session = match &conf.session_name {
+        Some(x) => x.to_string(),
+        None => "default".to_string()
     };
+    let sts_endpoint = conf.sts_endpoint.clone().or(conf.endpoint.clone());
+    let sts = StsClient::new(aws::build_region(&conf.region, &sts_endpoint));
+    Some(StsAssumeRoleSessionCredentialsProvider::new(
+        sts,
+        arn.to_string(),
+        session.to_string(),
+        None, None, None, None
+        ))
+}
 
-    let mut client = S3Client::new(region.to_owned());
+// Build an S3Client for S3 operations
+fn build_client(conf: &MacrotisStateConfig) -> Option<S3Client> {
+    let region = aws::build_region(&conf.region, &conf.endpoint);
 
     // See if we're assuming a role
-    if let Some(arn) = &conf.role_arn {
-        let session = match &conf.session_name {
-            Some(x) => x.to_string(),
-            None => "default".to_string()
-        };
-        let sts = StsClient::new(region.to_owned());
-        let provider = StsAssumeRoleSessionCredentialsProvider::new(
-            sts,
-            arn.to_string(),
-            session.to_string(),
-            None, None, None, None
-            );
-        client = S3Client::new_with(
-            HttpClient::new().unwrap(),
-            provider,
-            region);
+    match role_provider(&conf) {
+        Some(provider) => Some(S3Client::new_with(HttpClient::new().unwrap(), provider, region)),
+        None => Some(S3Client::new(region))
     }
-    Some(client)
 }
 
 // Attempt to retrieve state file from S3
 pub fn fetch_state_file(conf: &MacrotisStateConfig) -> Option<MacrotisState> {
+    match &conf.key {
+        Some(x) => fetch_state_at(&conf, &x),
+        None => None
+    }
+}
+
+// Attempt to retrieve a saved copy of the state file from S3
+pub fn fetch_history(conf: &MacrotisStateConfig, serial: u64) -> Option<MacrotisState> {
+    match &conf.key {
+        Some(x) => fetch_state_at(&conf, &format!("{}.{}", x, serial)),
+        None => None
+    }
+}
+
+// Retrieve the state stored under 'key'
+fn fetch_state_at(conf: &MacrotisStateConfig, key: &str) -> Option<MacrotisState> {
     // Build the client
     let client = match build_client(&conf) {
         Some(x) => x,
@@ -48,16 +64,12 @@ pub fn fetch_state_file(conf: &MacrotisStateConfig) -> Option<MacrotisState> {
         }
     };
 
-    // Shouldn't be able to get here without these being defined but double-
-    // check and return empty sadness if they're missing.
+    // Shouldn't be able to get here without this being defined but double-
+    // check and return empty sadness if it's missing.
     let bucket = match &conf.bucket {
         Some(x) => x.to_owned(),
         None => { return None; }
     };
-    let key = match &conf.key {
-        Some(x) => x.to_owned(),
-        None => { return None; }
-    };
 
     // Attempt to grab from S3
     let get_req = GetObjectRequest {
@@ -74,7 +86,7 @@ pub fn fetch_state_file(conf: &MacrotisStateConfig) -> Option<MacrotisState> {
         Ok(x) => x,
         Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => {
             println!("Remote statefile not found, creating a new one...");
-            let state = MacrotisState::new_empty();
+            let state = MacrotisState::new_missing();
             return Some(state);
         },
         Err(e) => {
@@ -84,24 +96,104 @@ pub fn fetch_state_file(conf: &MacrotisStateConfig) -> Option<MacrotisState> {
     };
 
     let stream = result.body.unwrap();
-    let body = stream.into_blocking_read();
+    let mut body = stream.into_blocking_read();
 
     // We use stream.into_blocking_read as that implements Read and we can
-    // hand it off to serde_json::from_reader at that point.
-    let retval: MacrotisState = match serde_json::from_reader(body) {
+    // hand it off to state::parse_state at that point.
+    let mut text = String::new();
+    if let Err(e) = body.read_to_string(&mut text) {
+        println!("Error reading S3 object: {}", e);
+        return None;
+    }
+    let mut retval = match state::parse_state(&conf, &text) {
         Ok(x) => x,
         Err(e) => {
-            println!("Error reading JSON: {}", e);
+            println!("{}", e);
             return None;
         }
     };
 
+    // Hang on to the ETag so save_state can tell if it's changed
+    retval.etag = result.e_tag;
+
     // retval should now contain the state
     Some(retval)
 }
 
 // Attempt to save a state file in S3
 pub fn put_state_file(conf: &MacrotisStateConfig, state: &str) -> Result<bool, String> {
+    match &conf.key {
+        Some(x) => put_state_at(&conf, &x, state),
+        None => Err("Missing config params".to_string())
+    }
+}
+
+// Save a numbered copy of the state file in S3 alongside the real one
+pub fn put_history(conf: &MacrotisStateConfig, serial: u64, state: &str) -> Result<bool, String> {
+    match &conf.key {
+        Some(x) => put_state_at(&conf, &format!("{}.{}", x, serial), state),
+        None => Err("Missing config params".to_string())
+    }
+}
+
+// Save a copy of a statefile from before it was upgraded
+pub fn put_backup(conf: &MacrotisStateConfig, version: u32, state: &str) -> Result<bool, String> {
+    match &conf.key {
+        Some(x) => put_state_at(&conf, &format!("{}.v{}.bak", x, version), state),
+        None => Err("Missing config params".to_string())
+    }
+}
+
+// List the serials of the numbered copies of the state file in S3
+pub fn list_history(conf: &MacrotisStateConfig) -> Result<Vec<u64>, String> {
+    let client = match build_client(&conf) {
+        Some(x) => x,
+        None => { return Err("Error creating S3 Client".to_string()); }
+    };
+    let (bucket, prefix) = match (&conf.bucket, &conf.key) {
+        (Some(b), Some(k)) => (b.to_string(), format!("{}.", k)),
+        _ => { return Err("Missing config params".to_string()); }
+    };
+
+    // Results come back a page at a time
+    let mut retval = Vec::new();
+    let mut token = None;
+    loop {
+        let req = ListObjectsV2Request {
+            bucket: bucket.to_string(),
+            prefix: Some(prefix.to_string()),
+            continuation_token: token,
+            ..Default::default()
+        };
+        let result = match client.list_objects_v2(req).sync() {
+            Ok(x) => x,
+            Err(e) => { return Err(format!("Error listing S3 objects: {}", e)); }
+        };
+        for obj in result.contents.unwrap_or(Vec::new()) {
+            let serial = obj.key.as_ref()
+                .and_then(|k| k[prefix.len()..].parse::<u64>().ok());
+            if let Some(x) = serial {
+                retval.push(x);
+            }
+        }
+        token = result.next_continuation_token;
+        if token.is_none() {
+            break;
+        }
+    }
+    retval.sort();
+    Ok(retval)
+}
+
+// Ask S3 to encrypt an object at rest, if the state config says to.
+// Check_bucket_params has already made sure these make sense.
+fn set_encryption(conf: &MacrotisStateConfig, req: &mut PutObjectRequest) {
+    req.server_side_encryption = conf.server_side_encryption.clone();
+    req.ssekms_key_id = conf.kms_key_id.clone();
+}
+
+// Store 'state' under 'key'
+fn put_state_at(conf: &MacrotisStateConfig, key: &str, state: &str) -> Result<bool, String> {
     // Starts the same as fetch - build client and check config params
     let sadness = "Missing config params".to_string();
     let client = match build_client(&conf) {
@@ -115,10 +207,6 @@ pub fn put_state_file(conf: &MacrotisStateConfig, state: &str) -> Result<bool, S
         Some(x) => x.to_owned(),
         None => { return Err(sadness); }
     };
-    let key = match &conf.key {
-        Some(x) => x.to_owned(),
-        None => { return Err(sadness); }
-    };
     
     // Add support for tagging the S3 resource
     let tagstring = match &conf.tags {
@@ -146,6 +234,7 @@ pub fn put_state_file(conf: &MacrotisStateConfig, state: &str) -> Result<bool, S
     if tagstring.len() > 1 {
 		req.tagging = Some(tagstring.to_string());
 	}
+    set_encryption(&conf, &mut req);
 
     let result = match client.put_object(req).sync() {
         Ok(_) => Ok(true),
@@ -153,3 +242,101 @@ pub fn put_state_file(conf: &MacrotisStateConfig, state: &str) -> Result<bool, S
     };
     result
 }
+
+// The lock object lives alongside the statefile, as <key>.lock
+fn lock_location(conf: &MacrotisStateConfig) -> Option<(String, String)> {
+    match (&conf.bucket, &conf.key) {
+        (Some(b), Some(k)) => Some((b.to_string(), format!("{}.lock", k))),
+        _ => None
+    }
+}
+
+// Fetch the current lock object, if there is one
+pub fn fetch_lock(conf: &MacrotisStateConfig) -> Result<Option<MacrotisLock>, String> {
+    let client = match build_client(&conf) {
+        Some(x) => x,
+        None => { return Err("Error creating S3 Client".to_string()); }
+    };
+    let (bucket, key) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let get_req = GetObjectRequest {
+        bucket: bucket,
+        key: key,
+        ..Default::default()
+    };
+    let result = match client.get_object(get_req).sync() {
+        Ok(x) => x,
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => { return Ok(None); },
+        Err(e) => { return Err(format!("Error retrieving S3 lock object: {}", e)); }
+    };
+    let body = match result.body {
+        Some(x) => x.into_blocking_read(),
+        None => { return Err("S3 lock object has no body".to_string()); }
+    };
+    match serde_json::from_reader(body) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Err(format!("Error reading lock JSON: {}", e))
+    }
+}
+
+// Try to take the lock.  Returns None if we got it, or whoever holds it.
+// rusoto can't send If-None-Match on a PutObject, so this checks for an
+// existing lock, writes ours, then reads it back.  That is NOT atomic: two
+// runners can both pass the check and both think they hold the lock.  It's
+// only used when the state config has no lock_table (see dynamodb.rs) and
+// unsafe_lock_object asks for it.
+pub fn put_lock(conf: &MacrotisStateConfig, lock: &MacrotisLock) -> Result<Option<MacrotisLock>, String> {
+    if let Some(x) = fetch_lock(&conf)? {
+        return Ok(Some(x));
+    }
+    let client = match build_client(&conf) {
+        Some(x) => x,
+        None => { return Err("Error creating S3 Client".to_string()); }
+    };
+    let (bucket, key) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let body = match serde_json::to_string_pretty(lock) {
+        Ok(x) => x.into_bytes(),
+        Err(e) => { return Err(e.to_string()); }
+    };
+    let mut req = PutObjectRequest {
+        bucket: bucket,
+        key: key,
+        body: Some(body.into()),
+        ..Default::default()
+    };
+    set_encryption(&conf, &mut req);
+    if let Err(e) = client.put_object(req).sync() {
+        return Err(format!("Error writing S3 lock object: {}", e));
+    }
+    match fetch_lock(&conf)? {
+        Some(ref x) if x.id == lock.id => Ok(None),
+        Some(x) => Ok(Some(x)),
+        None => Err("S3 lock object vanished after writing it".to_string())
+    }
+}
+
+// Remove the lock object
+pub fn delete_lock(conf: &MacrotisStateConfig) -> Result<bool, String> {
+    let client = match build_client(&conf) {
+        Some(x) => x,
+        None => { return Err("Error creating S3 Client".to_string()); }
+    };
+    let (bucket, key) = match lock_location(&conf) {
+        Some(x) => x,
+        None => { return Err("Missing config params".to_string()); }
+    };
+    let req = DeleteObjectRequest {
+        bucket: bucket,
+        key: key,
+        ..Default::default()
+    };
+    match client.delete_object(req).sync() {
+        Ok(_) => Ok(true),
+        Err(e) => Err(format!("Error deleting S3 lock object: {}", e))
+    }
+}
@@ -1,20 +1,35 @@
 // Module defining operations with local and/or remote statefiles
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::collections::HashMap;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write, ErrorKind};
 use std::time::SystemTime;
+use std::path::Path;
 use resource::{ResHash};
+use health::StoredCheck;
 use {MacrotisConfig, MacrotisStateConfig};
 use s3;
+use dynamodb;
+use mock;
+use migrate;
+use crypto;
 
 // What is a state?  We just don't know.
+// The serial goes up by one on every save.  Etag is the S3 ETag the state
+// was loaded with, if it came from S3, and original is the version and
+// text of the file if it had to be upgraded; neither is part of the file
+// itself.  Health_checks are the checks Macrotis has made, by name.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MacrotisState {
     pub version: u32,
     pub appversion: String,
     pub serial: u64,
-    pub records: ResHash
+    pub records: ResHash,
+    pub health_checks: HashMap<String, StoredCheck>,
+    #[serde(skip)]
+    pub etag: Option<String>,
+    #[serde(skip)]
+    pub original: Option<(u32, String)>
 }
 
 impl std::fmt::Display for MacrotisState {
@@ -37,12 +52,301 @@ impl MacrotisState {
             Err(_) => panic!("Can't get time since epoch?!")
         };
         MacrotisState {
-            version: 1,
+            version: migrate::STATE_VERSION,
             appversion: app_ver.to_string(),
             serial: right_now,
-            records: rh
+            records: rh,
+            health_checks: HashMap::new(),
+            etag: None,
+            original: None
         }
     }
+
+    // Stand-in for a statefile that doesn't exist yet.  Its serial is 0
+    // so it comes out the same on every run until something gets saved.
+    pub fn new_missing() -> MacrotisState {
+        let mut state = MacrotisState::new_empty();
+        state.serial = 0;
+        state
+    }
+}
+
+// Encrypt the text of a statefile with the configured key, if there is one
+pub fn seal_state(conf: &MacrotisStateConfig, text: String) -> Result<String, String> {
+    match &conf.encryption_key_file {
+        Some(x) => crypto::encrypt(&crypto::load_key(&x)?, &text),
+        None => Ok(text)
+    }
+}
+
+// Decrypt the text of a statefile if it's encrypted.  Plain statefiles still
+// load with a key set, so encryption can be turned on for an existing
+// statefile; it gets encrypted the next time it's saved.
+fn open_state(conf: &MacrotisStateConfig, text: &str) -> Result<String, String> {
+    if !crypto::is_encrypted(text) {
+        return Ok(text.to_string());
+    }
+    match &conf.encryption_key_file {
+        Some(x) => crypto::decrypt(&crypto::load_key(&x)?, text),
+        None => Err("Statefile is encrypted but no encryption_key_file is set in state config".to_string())
+    }
+}
+
+// Turn the text of a statefile into a MacrotisState, decrypting and
+// upgrading it first if need be.  Every backend loads through here.
+pub fn parse_state(conf: &MacrotisStateConfig, text: &str) -> Result<MacrotisState, String> {
+    let plain = open_state(&conf, text)?;
+    let mut value: serde_json::Value = match serde_json::from_str(&plain) {
+        Ok(x) => x,
+        Err(e) => { return Err(format!("Error parsing statefile JSON: {}", e)); }
+    };
+    let from = migrate::upgrade(&mut value)?;
+    let mut state: MacrotisState = match serde_json::from_value(value) {
+        Ok(x) => x,
+        Err(e) => { return Err(format!("Error parsing statefile JSON: {}", e)); }
+    };
+    if let Some(v) = from {
+        println!("Upgraded statefile from version {} to {}", v, migrate::STATE_VERSION);
+        state.original = Some((v, text.to_string()));
+    }
+    Ok(state)
+}
+
+// A lock on the statefile, held for the whole load -> push -> save run so
+// two runners can't interleave.  Times are unix seconds; a lock past its
+// expiry is considered stale and can be cleared with 'force-unlock'.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MacrotisLock {
+    pub id: String,
+    pub owner: String,
+    pub operation: String,
+    pub created: u64,
+    pub expires: u64
+}
+
+impl std::fmt::Display for MacrotisLock {
+	// Pretty print who holds the lock and until when
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "Lock {} held by {} for '{}' since {}, expires {}",
+				self.id, self.owner, self.operation, self.created, self.expires)
+    }
+}
+
+// Default lock lifetime if the state config doesn't set lock_timeout
+const DEFAULT_LOCK_TIMEOUT: u64 = 3600;
+
+impl MacrotisLock {
+    // Make a new lock for this process, good for 'timeout' seconds.  The ID
+    // just needs to be unique, so the current time and PID will do.
+    pub fn new(operation: &str, timeout: u64) -> MacrotisLock {
+        let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => n,
+            Err(_) => panic!("Can't get time since epoch?!")
+        };
+        let user = std::env::var("USER").or(std::env::var("USERNAME"))
+                        .unwrap_or("unknown".to_string());
+        let host = std::env::var("HOSTNAME").unwrap_or("unknown".to_string());
+        MacrotisLock {
+            id: format!("{:x}{:08x}-{:x}", now.as_secs(), now.subsec_nanos(), std::process::id()),
+            owner: format!("{}@{}", user, host),
+            operation: operation.to_string(),
+            created: now.as_secs(),
+            expires: now.as_secs() + timeout
+        }
+    }
+
+    // Whether the lock has outlived its expiry
+    pub fn expired(&self) -> bool {
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => n.as_secs() > self.expires,
+            Err(_) => false
+        }
+    }
+}
+
+// Take the statefile lock for 'operation'.  Returns the lock on success so
+// it can be handed back to unlock_state; prints who holds it and returns
+// None if somebody else got there first.
+pub fn lock_state(config: &MacrotisConfig, operation: &str) -> Option<MacrotisLock> {
+    let stateconf = &config.statefile;
+    let lock = MacrotisLock::new(operation, stateconf.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT));
+    let result = match stateconf.backend.as_str() {
+        "local" => {
+            match &stateconf.filename {
+                Some(x) => lock_local(&format!("{}.lock", x), &lock),
+                None => Err("Statefile backend set to 'local' but filename unset".to_string())
+            }
+        },
+        "s3" => {
+            if !check_bucket_params(&stateconf) {
+                return None;
+            }
+            match (&stateconf.lock_table, stateconf.unsafe_lock_object) {
+                (Some(_), _) => dynamodb::put_lock(&stateconf, &lock),
+                (None, Some(true)) => {
+                    println!("Warning: unsafe_lock_object is set, so the S3 statefile lock isn't atomic and two runs can both take it");
+                    s3::put_lock(&stateconf, &lock)
+                },
+                (None, _) => Err("Statefile backend set to 's3' but lock_table unset (set unsafe_lock_object to lock with an object in the bucket, which isn't atomic)".to_string())
+            }
+        },
+        "mock" => mock::put_lock(&stateconf, &lock),
+        _ => Err(format!("Unknown backend: {}", &stateconf.backend))
+    };
+    match result {
+        Ok(None) => Some(lock),
+        Ok(Some(other)) => {
+            println!("Statefile is locked: {}", other);
+            if other.expired() {
+                println!("That lock has expired; clear it with 'force-unlock'");
+            }
+            None
+        },
+        Err(e) => {
+            println!("Error locking statefile: {}", e);
+            None
+        }
+    }
+}
+
+// Fetch whatever lock is currently held on the statefile, if any
+pub fn fetch_lock(config: &MacrotisConfig) -> Result<Option<MacrotisLock>, String> {
+    let stateconf = &config.statefile;
+    match stateconf.backend.as_str() {
+        "local" => {
+            match &stateconf.filename {
+                Some(x) => fetch_local_lock(&format!("{}.lock", x)),
+                None => Err("Statefile backend set to 'local' but filename unset".to_string())
+            }
+        },
+        "s3" if stateconf.lock_table.is_some() => dynamodb::fetch_lock(&stateconf),
+        "s3" => s3::fetch_lock(&stateconf),
+        "mock" => mock::fetch_lock(&stateconf),
+        _ => Err(format!("Unknown backend: {}", &stateconf.backend))
+    }
+}
+
+// Remove the lock without any checks
+fn delete_lock(config: &MacrotisConfig) -> Result<bool, String> {
+    let stateconf = &config.statefile;
+    match stateconf.backend.as_str() {
+        "local" => {
+            match &stateconf.filename {
+                Some(x) => delete_local_lock(&format!("{}.lock", x)),
+                None => Err("Statefile backend set to 'local' but filename unset".to_string())
+            }
+        },
+        "s3" if stateconf.lock_table.is_some() => dynamodb::delete_lock(&stateconf),
+        "s3" => s3::delete_lock(&stateconf),
+        "mock" => mock::delete_lock(&stateconf),
+        _ => Err(format!("Unknown backend: {}", &stateconf.backend))
+    }
+}
+
+// Release a lock taken by lock_state.  Leaves it alone if it's been
+// replaced by somebody else's (say, after a force-unlock).
+pub fn unlock_state(config: &MacrotisConfig, lock: &MacrotisLock) -> bool {
+    match fetch_lock(&config) {
+        Ok(Some(ref x)) if x.id == lock.id => {},
+        Ok(Some(x)) => {
+            println!("Not releasing statefile lock, it now belongs to someone else: {}", x);
+            return false;
+        },
+        Ok(None) => {
+            println!("Statefile lock {} was already released", lock.id);
+            return true;
+        },
+        Err(e) => {
+            println!("Error checking statefile lock: {}", e);
+            return false;
+        }
+    };
+    match delete_lock(&config) {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Error releasing statefile lock: {}", e);
+            false
+        }
+    }
+}
+
+// Clear a lock left behind by a runner that died.  With an ID only that
+// lock is removed; without one the lock is only removed if it's expired.
+pub fn force_unlock(config: &MacrotisConfig, id: Option<&str>) -> bool {
+    let lock = match fetch_lock(&config) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            println!("Statefile is not locked");
+            return true;
+        },
+        Err(e) => {
+            println!("Error checking statefile lock: {}", e);
+            return false;
+        }
+    };
+    println!("{}", lock);
+    match id {
+        Some(x) if x != lock.id => {
+            println!("Lock ID doesn't match {}, leaving it alone", x);
+            return false;
+        },
+        None if !lock.expired() => {
+            println!("Lock hasn't expired; pass its ID to remove it anyway");
+            return false;
+        },
+        _ => {}
+    };
+    match delete_lock(&config) {
+        Ok(_) => {
+            println!("Removed lock {}", lock.id);
+            true
+        },
+        Err(e) => {
+            println!("Error removing lock: {}", e);
+            false
+        }
+    }
+}
+
+// Take a lock file.  create_new makes this atomic: only one process can
+// create the file, everybody else gets told who has it.
+pub fn lock_local(fname: &str, lock: &MacrotisLock) -> Result<Option<MacrotisLock>, String> {
+    let f = match OpenOptions::new().write(true).create_new(true).open(fname) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
+            return match fetch_local_lock(fname)? {
+                Some(x) => Ok(Some(x)),
+                None => Err(format!("Lock file {} vanished, try again", fname))
+            };
+        },
+        Err(e) => { return Err(format!("Error creating lock file {}: {}", fname, e)); }
+    };
+    match serde_json::to_writer_pretty(BufWriter::new(f), lock) {
+        Ok(_) => Ok(None),
+        Err(e) => Err(format!("Error writing lock file {}: {}", fname, e))
+    }
+}
+
+// Read a lock file, if there is one
+pub fn fetch_local_lock(fname: &str) -> Result<Option<MacrotisLock>, String> {
+    let f = match File::open(fname) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => { return Ok(None); },
+        Err(e) => { return Err(format!("Error opening lock file {}: {}", fname, e)); }
+    };
+    match serde_json::from_reader(BufReader::new(f)) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Err(format!("Error parsing lock file {}: {}", fname, e))
+    }
+}
+
+// Remove a lock file
+pub fn delete_local_lock(fname: &str) -> Result<bool, String> {
+    match std::fs::remove_file(fname) {
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(format!("Error removing lock file {}: {}", fname, e))
+    }
 }
 
 // Genericized state loading function; takes a MacrotisConfig struct and calls
@@ -62,7 +366,7 @@ pub fn load_state(config: &MacrotisConfig) -> Option<MacrotisState> {
                     return None;
                 }
             };
-            load_local_state(&fname)
+            load_local_state(&stateconf, &fname)
         },
         "s3" => {
             if check_bucket_params(&stateconf) {
@@ -72,7 +376,8 @@ pub fn load_state(config: &MacrotisConfig) -> Option<MacrotisState> {
                 // just return None here.
                 return None;
             }
-        }
+        },
+        "mock" => mock::fetch_state_file(&stateconf),
         _ => {
             println!("Unknown backend: {}", &stateconf.backend);
             return None;
@@ -82,8 +387,17 @@ pub fn load_state(config: &MacrotisConfig) -> Option<MacrotisState> {
 
 // Attempt to load state from a local file.  Returns None if unable to load,
 // MacrotisState with empty RecordHash if file does not exist.
-pub fn load_local_state(fname: &str) -> Option<MacrotisState> {
-    // Attempt to open and read file
+pub fn load_local_state(conf: &MacrotisStateConfig, fname: &str) -> Option<MacrotisState> {
+    if !Path::new(fname).exists() {
+        println!("Local statefile not found, creating a new one...");
+        return Some(MacrotisState::new_missing());
+    }
+    read_state_file(&conf, &fname)
+}
+
+// Load state from a file that has to exist, like one given to 'state push'.
+// Returns None if it's missing or can't be loaded.
+pub fn read_state_file(conf: &MacrotisStateConfig, fname: &str) -> Option<MacrotisState> {
     let f = match File::open(fname) {
         Ok(file) => file,
         Err(e) => {
@@ -91,24 +405,57 @@ pub fn load_local_state(fname: &str) -> Option<MacrotisState> {
             return None;
         }
     };
-    let reader = BufReader::new(f);
-    let state: MacrotisState = match serde_json::from_reader(reader) {
-        Ok(x) => x,
+    let mut text = String::new();
+    if let Err(e) = BufReader::new(f).read_to_string(&mut text) {
+        println!("Error reading statefile {}: {}", fname, e);
+        return None;
+    }
+    match parse_state(&conf, &text) {
+        Ok(x) => Some(x),
         Err(e) => {
-            println!("Error parsing statefile JSON: {}", e);
-            return None;
+            println!("{}", e);
+            None
         }
+    }
+}
+
+// Make sure the stored statefile is still the one that was loaded, so a
+// runner working from an old copy can't roll it back.  Compares serials,
+// and ETags too when both sides have one.  This is check-then-write, not
+// a conditional write (rusoto can't send If-Match on a PutObject), so it
+// only helps while the statefile lock is held; every caller of save_state
+// holds it.
+fn check_serial(config: &MacrotisConfig, loaded: &MacrotisState) -> Result<(), String> {
+    let current = match load_state(&config) {
+        Some(x) => x,
+        None => { return Err("couldn't re-read statefile to check its serial".to_string()); }
     };
-    Some(state)
+    if current.serial != loaded.serial {
+        return Err(format!("statefile serial is now {}, expected {}", current.serial, loaded.serial));
+    }
+    match (&current.etag, &loaded.etag) {
+        (Some(a), Some(b)) if a != b => Err(format!("statefile ETag is now {}, expected {}", a, b)),
+        _ => Ok(())
+    }
 }
 
-// Genericized state saving function, operates same as load_state.  Returns
-// true on success, false on failure.
-pub fn save_state(config: &MacrotisConfig, recs: ResHash) -> bool {
+// Genericized state saving function, operates same as load_state.  Loaded
+// is the state this run started from; the new state gets the next serial,
+// and nothing is written if the stored one has moved on since.  The health
+// checks saved are loaded's, so callers that change them update loaded's
+// copy first.  Returns true on success, false on failure.
+pub fn save_state(config: &MacrotisConfig, recs: ResHash, loaded: &MacrotisState) -> bool {
+    if let Err(e) = check_serial(&config, &loaded) {
+        println!("Refusing to save statefile: {}", e);
+        return false;
+    }
+
     // Make an empty macrotis state and replace its innards with the received
     // RecordHash and serial, then turn it into a string of JSON with Serde
     let mut state = MacrotisState::new_empty();
     state.records = recs;
+    state.health_checks = loaded.health_checks.clone();
+    state.serial = loaded.serial + 1;
 
     let outstring = match serde_json::to_string_pretty(&state) {
         Ok(x) => x,
@@ -117,31 +464,160 @@ pub fn save_state(config: &MacrotisConfig, recs: ResHash) -> bool {
             return false;
         }
     };
-
     let stateconf = &config.statefile;
+    let outstring = match seal_state(&stateconf, outstring) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error encrypting statefile: {}", e);
+            return false;
+        }
+    };
+
+    // If the loaded state had to be upgraded, keep the old file around
+    // before it gets replaced
+    if let Some((v, ref text)) = loaded.original {
+        let saved = match stateconf.backend.as_str() {
+            "local" | "mock" if stateconf.filename.is_some() => {
+                let fname = format!("{}.v{}.bak", stateconf.filename.as_ref().unwrap(), v);
+                save_local_state(&fname, text)
+            },
+            "s3" => s3::put_backup(&stateconf, v, text),
+            _ => Ok(true)
+        };
+        if let Err(e) = saved {
+            println!("Refusing to save statefile without a backup of the version {} one: {}", v, e);
+            return false;
+        }
+    }
+
     match stateconf.backend.as_str() {
         "local" => {
-            match save_local_state("foo", &outstring) {
-                Ok(_) => true,
+            let fname = match &stateconf.filename {
+                Some(x) => x,
+                None => {
+                    println!("Statefile backend set to 'local' but filename unset");
+                    return false;
+                }
+            };
+            match save_local_state(&fname, &outstring) {
+                Ok(_) => {},
                 Err(e) => {
                     println!("Error: {}", e);
-                    false
+                    return false;
                 }
             }
         },
         "s3" => {
             match s3::put_state_file(&stateconf, &outstring) {
-                Ok(_) => true,
+                Ok(_) => {},
                 Err(e) => {
                     println!("Error: {}", e);
-                    false
+                    return false;
+                }
+            }
+        },
+        "mock" => {
+            match mock::put_state_file(&stateconf, &outstring) {
+                Ok(_) => {},
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return false;
                 }
             }
         },
         _ => {
             println!("Unknown backend: {}", &stateconf.backend);
-            false
+            return false;
         }
+    };
+
+    // Keep a numbered copy for 'state history' and 'state rollback'.  The
+    // real statefile is already saved, so failing here is just a warning.
+    let saved = match stateconf.backend.as_str() {
+        "local" | "mock" if stateconf.filename.is_some() => {
+            let fname = stateconf.filename.as_ref().unwrap();
+            save_local_state(&history_name(&fname, state.serial), &outstring)
+        },
+        "s3" => s3::put_history(&stateconf, state.serial, &outstring),
+        _ => mock::put_history(state.serial, &outstring)
+    };
+    if let Err(e) = saved {
+        println!("Warning, couldn't save statefile history: {}", e);
+    }
+    true
+}
+
+// Name of the numbered copy of a local statefile
+fn history_name(fname: &str, serial: u64) -> String {
+    format!("{}.{}", fname, serial)
+}
+
+// List the serials of the saved copies of a local statefile, which sit
+// next to it as <filename>.<serial>
+fn list_local_history(fname: &str) -> Result<Vec<u64>, String> {
+    let path = std::path::Path::new(fname);
+    let base = match path.file_name().and_then(|x| x.to_str()) {
+        Some(x) => format!("{}.", x),
+        None => { return Err(format!("Bad statefile name {}", fname)); }
+    };
+    let dir = match path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => std::path::Path::new(".")
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => { return Err(format!("Error reading {}: {}", dir.display(), e)); }
+    };
+    let mut retval = Vec::new();
+    for entry in entries {
+        if let Ok(f) = entry {
+            let name = f.file_name().to_string_lossy().to_string();
+            if name.starts_with(&base) {
+                if let Ok(x) = name[base.len()..].parse::<u64>() {
+                    retval.push(x);
+                }
+            }
+        }
+    }
+    retval.sort();
+    Ok(retval)
+}
+
+// List the serials of every saved copy of the statefile, oldest first
+pub fn list_history(config: &MacrotisConfig) -> Result<Vec<u64>, String> {
+    let stateconf = &config.statefile;
+    match stateconf.backend.as_str() {
+        "local" | "mock" if stateconf.filename.is_some() => {
+            list_local_history(stateconf.filename.as_ref().unwrap())
+        },
+        "local" => Err("Statefile backend set to 'local' but filename unset".to_string()),
+        "s3" => s3::list_history(&stateconf),
+        "mock" => Ok(mock::list_history()),
+        _ => Err(format!("Unknown backend: {}", &stateconf.backend))
+    }
+}
+
+// Load the saved copy of the statefile with the given serial
+pub fn load_history(config: &MacrotisConfig, serial: u64) -> Option<MacrotisState> {
+    match list_history(&config) {
+        Ok(ref x) if x.contains(&serial) => {},
+        Ok(_) => {
+            println!("No saved statefile with serial {}", serial);
+            return None;
+        },
+        Err(e) => {
+            println!("Error listing statefile history: {}", e);
+            return None;
+        }
+    };
+    let stateconf = &config.statefile;
+    match stateconf.backend.as_str() {
+        "local" | "mock" if stateconf.filename.is_some() => {
+            load_local_state(&stateconf, &history_name(stateconf.filename.as_ref().unwrap(), serial))
+        },
+        "s3" => s3::fetch_history(&stateconf, serial),
+        "mock" => mock::fetch_history(&stateconf, serial),
+        _ => None
     }
 }
 
@@ -195,6 +671,20 @@ pub fn check_bucket_params(conf: &MacrotisStateConfig) -> bool {
         }
     };
 
+    // S3 only knows these two kinds of server-side encryption, and only
+    // KMS takes a key
+    match conf.server_side_encryption.as_ref().map(|x| x.as_str()) {
+        None | Some("AES256") | Some("aws:kms") => { },
+        Some(x) => {
+            println!("Unknown server_side_encryption {} in state config; use AES256 or aws:kms", x);
+            retval = false;
+        }
+    };
+    if conf.kms_key_id.is_some() && conf.server_side_encryption != Some("aws:kms".to_string()) {
+        println!("kms_key_id is set in state config, but server_side_encryption isn't aws:kms");
+        retval = false;
+    }
+
     match &conf.role_arn {
         Some(_) => {
             match &conf.session_name {
@@ -210,3 +700,107 @@ pub fn check_bucket_params(conf: &MacrotisStateConfig) -> bool {
     };
     retval
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_config(name: &str) -> (MacrotisConfig, String) {
+        let fname = std::env::temp_dir().join(format!("macrotis-state-{}-{}.json", name, std::process::id()));
+        let fname = fname.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&fname);
+        let conf = format!(r#"{{
+            "provider": {{ "name": "mock", "assume_role": false }},
+            "statefile": {{ "backend": "local", "filename": "{}" }},
+            "zones": []
+        }}"#, fname);
+        (serde_json::from_str(&conf).unwrap(), fname)
+    }
+
+    #[test]
+    fn test_serial_increments() {
+        let (config, fname) = local_config("serial");
+        let st = load_state(&config).unwrap();
+        assert_eq!(st.serial, 0);
+        assert!(save_state(&config, ResHash(HashMap::new()), &st));
+        let st = load_state(&config).unwrap();
+        assert_eq!(st.serial, 1);
+        assert!(save_state(&config, ResHash(HashMap::new()), &st));
+        assert_eq!(load_state(&config).unwrap().serial, 2);
+        assert_eq!(list_history(&config).unwrap(), vec![1, 2]);
+        for f in &[fname.to_string(), format!("{}.1", fname), format!("{}.2", fname)] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stale_save_refused() {
+        let (config, fname) = local_config("stale");
+        let first = load_state(&config).unwrap();
+        let second = load_state(&config).unwrap();
+        assert!(save_state(&config, ResHash(HashMap::new()), &second));
+
+        // 'first' was loaded before 'second' saved, so it's out of date
+        assert!(!save_state(&config, ResHash(HashMap::new()), &first));
+        assert_eq!(load_state(&config).unwrap().serial, 1);
+        std::fs::remove_file(&fname).unwrap();
+        std::fs::remove_file(format!("{}.1", fname)).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_keeps_backup() {
+        let (config, fname) = local_config("upgrade");
+        let old = r#"{ "version": 1, "appversion": "0.6.22", "serial": 3, "records": {} }"#;
+        std::fs::write(&fname, old).unwrap();
+        let st = load_state(&config).unwrap();
+        assert_eq!(st.version, migrate::STATE_VERSION);
+        assert!(save_state(&config, ResHash(HashMap::new()), &st));
+        let backup = format!("{}.v1.bak", fname);
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), old);
+        assert_eq!(load_state(&config).unwrap().serial, 4);
+
+        // A statefile from the future is left alone
+        std::fs::write(&fname, r#"{ "version": 99, "appversion": "9", "serial": 1, "records": {} }"#).unwrap();
+        assert!(load_state(&config).is_none());
+        for f in &[fname.to_string(), backup, format!("{}.4", fname)] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_encrypted_statefile() {
+        let (mut config, fname) = local_config("encrypted");
+        let keyfile = format!("{}.key", fname);
+        std::fs::write(&keyfile, "0123456789abcdef".repeat(4)).unwrap();
+        config.statefile.encryption_key_file = Some(keyfile.to_string());
+
+        // A plain statefile still loads, and gets encrypted when saved
+        std::fs::write(&fname, r#"{ "version": 3, "appversion": "0.6.22", "serial": 3, "records": {}, "health_checks": {} }"#).unwrap();
+        let st = load_state(&config).unwrap();
+        assert!(save_state(&config, ResHash(HashMap::new()), &st));
+        for f in &[fname.to_string(), format!("{}.4", fname)] {
+            let text = std::fs::read_to_string(f).unwrap();
+            assert!(crypto::is_encrypted(&text));
+            assert!(!text.contains("appversion"));
+        }
+        assert_eq!(load_state(&config).unwrap().serial, 4);
+
+        // Without the key it can't be read at all
+        config.statefile.encryption_key_file = None;
+        assert!(load_state(&config).is_none());
+        for f in &[fname.to_string(), format!("{}.4", fname), keyfile] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_s3_lock_needs_table() {
+        let (mut config, _) = local_config("s3lock");
+        config.statefile.backend = "s3".to_string();
+        config.statefile.bucket = Some("bucket".to_string());
+        config.statefile.key = Some("state.json".to_string());
+
+        // Refused before anything is sent to S3
+        assert!(lock_state(&config, "execute").is_none());
+    }
+}
@@ -1,18 +1,28 @@
 use Zone;
+use resource::{AliasTarget, RoutingPolicy};
 use std::collections::HashMap;
 use std::cmp::Ordering;
 use std::fs::File;
 use std::io::{BufReader, BufRead};
 use std::time::SystemTime;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 // Define the TinyDNSRecord struct and implement some Traits on it
-#[derive(Debug, Hash)]
+// Timestamp is in unix seconds; with a nonzero TTL it's when the record
+// starts being served, and with a zero TTL it's when it stops.  Location
+// is the 'lo' code of the clients that should see the record.  Alias is
+// only set for '~' lines, whose target is the alias's DNS name, and routing
+// only for '!' lines.
+#[derive(Debug, Hash, Clone, Default)]
 pub struct TinyDNSRecord {
     pub rtype: String,
     pub fqdn: String,
     pub target: String,
     pub ttl: i32,
+    pub timestamp: Option<u64>,
+    pub location: Option<String>,
+    pub alias: Option<AliasTarget>,
+    pub routing: Option<RoutingPolicy>,
 }
 
 impl Eq for TinyDNSRecord {}
@@ -20,9 +30,13 @@ impl Eq for TinyDNSRecord {}
 impl PartialEq for TinyDNSRecord {
     // Consider a TDR to equal another one even if their TTLs differ
     fn eq(&self, other: &Self) -> bool {
-        self.rtype  == other.rtype &&
-        self.fqdn   == other.fqdn &&
-        self.target == other.target
+        self.rtype     == other.rtype &&
+        self.fqdn      == other.fqdn &&
+        self.target    == other.target &&
+        self.timestamp == other.timestamp &&
+        self.location  == other.location &&
+        self.alias     == other.alias &&
+        self.routing   == other.routing
     }
 }
 
@@ -63,18 +77,59 @@ pub fn find_zone_id(tdr: &TinyDNSRecord, zones: &Vec<Zone>) -> Option<String> {
 	match_tuple.1
 }
 
+// Everything we know about a line that failed to parse.  The parse_X
+// functions only know the field and reason; from_string fills in the text
+// of the line and from_file adds the file name and line number.  Fields
+// are counted from 1, starting with the one right after the prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub file: String,
+    pub line: usize,
+    pub field: Option<usize>,
+    pub text: String,
+    pub reason: String
+}
+
+impl ParseError {
+    pub fn new(field: Option<usize>, reason: &str) -> ParseError {
+        ParseError {
+            file: String::new(),
+            line: 0,
+            field: field,
+            text: String::new(),
+            reason: reason.to_string()
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    // file:line: reason (field N), followed by the offending line
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.reason)?;
+        if let Some(x) = self.field {
+            write!(f, " (field {})", x)?;
+        }
+        if !self.text.is_empty() {
+            write!(f, "\n\t{}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
 // Given a filename, read in the contents, sending each line to the
 // 'from_string' function to be turned into one or more TDRs.  Returns
-// a Vec of TDRs on success or None on error.
-pub fn from_file(fname: &str) -> Option<Vec<TinyDNSRecord>> {
+// a Vec of TDRs on success, or a Vec of every ParseError in the file.
+pub fn from_file(fname: &str) -> Result<Vec<TinyDNSRecord>, Vec<ParseError>> {
     let mut retval = Vec::new();
+    let mut errors = Vec::new();
 
     // Attempt to open and read file
     let f = match File::open(fname) {
         Ok(file) => file,
         Err(e) => {
-            println!("Error opening file {}: {}", fname, e);
-            return None;
+            let mut err = ParseError::new(None, &format!("error opening file: {}", e));
+            err.file = fname.to_string();
+            return Err(vec![err]);
         }
     };
     let reader = BufReader::new(&f);
@@ -82,14 +137,25 @@ pub fn from_file(fname: &str) -> Option<Vec<TinyDNSRecord>> {
     // Process each line in the file and call the appropriate parsing
     // function.  Remember that some prefixes generate more than one!
     // Because of that, all the parse_X functions return a Vec that
-    // can be simply append()-ed to retval, even if that Vec is empty.
-    for line in reader.lines() {
-        let l = line.expect("Couldn't get line?");
-        match from_string(&l) {
-            Some(mut x) => { retval.append(&mut x); }
-            None => { return None; }
+    // can be simply append()-ed to retval.  Keep going after errors so
+    // they can all be reported at once.
+    for (i, line) in reader.lines().enumerate() {
+        let parsed = match line {
+            Ok(l) => from_string(&l),
+            Err(e) => Err(ParseError::new(None, &format!("error reading line: {}", e)))
+        };
+        match parsed {
+            Ok(mut x) => { retval.append(&mut x); },
+            Err(mut e) => {
+                e.file = fname.to_string();
+                e.line = i + 1;
+                errors.push(e);
+            }
         }
     }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
     // Check for duplicates in the Vec and warn about them
     check_dups(&retval);
@@ -99,38 +165,50 @@ pub fn from_file(fname: &str) -> Option<Vec<TinyDNSRecord>> {
     retval.dedup();
 
     // Return the parsed records
-    Some(retval)
+    Ok(retval)
 }
 
 // Process a string representing one line in a TinyDNS-formatted file.
-pub fn from_string(line: &str) -> Option<Vec<TinyDNSRecord>> {
+pub fn from_string(line: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Since half of these need to return more than 1 struct, they're all set
-    // to return a Vec of TDRs. If that Vec is empty, there was an issue, and
-    // we should return None so the upstream calling function can deal with it.
-    // Comments and excluded records should still return 'successful' but empty.
-    let (prefix, data) = line.split_at(1);
+    // to return a Vec of TDRs. Comments, blank lines, and excluded records
+    // return 'successful' but empty.
+    let prefix = match line.chars().next() {
+        Some(x) => x,
+        None => { return Ok(Vec::new()); }
+    };
+    let data = &line[prefix.len_utf8()..];
     let parsed = match prefix {
-        "+" => { parse("A", data) },
-        "^" => { parse("PTR", data) },
-        "C" => { parse("CNAME", data) },
-        "'" => { parse_txt(data) },
-        "@" => { parse_mx(data) },
-        "Z" => { parse_soa(data) },
-        "." => { parse_anssoa(data) },
-        "&" => { parse_ans(data) },
-        "=" => { parse_aptr(data) },
-        "-" => { return Some(Vec::new()); }, // Excluded record, ignore
-        "#" => { return Some(Vec::new()); }, // Comment line, ignore
+        '+' => { parse("A", data) },
+        '^' => { parse("PTR", data) },
+        'C' => { parse("CNAME", data) },
+        '\'' => { parse_txt(data) },
+        '@' => { parse_mx(data) },
+        'Z' => { parse_soa(data) },
+        '.' => { parse_anssoa(data) },
+        '&' => { parse_ans(data) },
+        '=' => { parse_aptr(data) },
+        '3' => { parse_aaaa(data) },
+        '6' => { parse_aaaaptr(data) },
+        ':' => { parse_generic(data) },
+        '%' => { parse_location(data) },
+        '~' => { parse_alias(data) },
+        '!' => { parse_policy(data) },
+        '-' => { return Ok(Vec::new()); }, // Excluded record, ignore
+        '#' => { return Ok(Vec::new()); }, // Comment line, ignore
+        _ if line.trim().is_empty() => { return Ok(Vec::new()); },
         _   => {
-            println!("Unsuported prefix: {}", prefix);
-            Vec::new()
+            Err(ParseError::new(None, &format!("unsupported prefix: {}", prefix)))
         }
     };
 
-    // Return parsed if there's anything in it.
-    match parsed.is_empty() {
-        true => None,
-        false => Some(parsed)
+    // Tack the original line onto any error before handing it back
+    match parsed {
+        Ok(x) => Ok(x),
+        Err(mut e) => {
+            e.text = line.to_string();
+            Err(e)
+        }
     }
 }
 
@@ -159,18 +237,17 @@ fn check_dups(records: &Vec<TinyDNSRecord>) {
 // +fqdn:rec:ttl:timestamp:lo - A
 // ^fqdn:rec:ttl:timestamp:lo - PTR
 // Cfqdn:rec:ttl:timestamp:lo - CNAME
-pub fn parse(rtype: &str, data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse(rtype: &str, data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create our return Vec
     let mut retval = Vec::new();
 
     // Split up the data by colon.
     let mut parts: Vec<&str> = data.split(':').collect();
 
-    // The FQDN and Target are mandatory. Print an error and return an
-    // empty Vec if there aren't at least 2 items in 'parts'
+    // The FQDN and Target are mandatory. Return an error if there
+    // aren't at least 2 items in 'parts'
     if parts.len() < 2 {
-        println!("Error parsing line: {} of type {}", data, rtype);
-        return retval;
+        return Err(ParseError::new(None, &format!("expected at least 2 fields for type {}", rtype)));
     }
 
     // Pull those parts out
@@ -184,9 +261,7 @@ pub fn parse(rtype: &str, data: &str) -> Vec<TinyDNSRecord> {
         match rec.parse::<Ipv4Addr>() {
             Ok(_) => {},
             Err(e) => {
-                println!("Error processing record: {}", data);
-                println!("{}", e);
-                return retval;
+                return Err(ParseError::new(Some(2), &format!("bad IPv4 address '{}': {}", rec, e)));
             }
         }
     }
@@ -201,23 +276,30 @@ pub fn parse(rtype: &str, data: &str) -> Vec<TinyDNSRecord> {
         }
     };
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 4)?;
+
     // Any data that may be left in 'parts' is extraneous and unneeded,
     // so proceed on to making a TDR, put it in retval, and return.
     let tdr = TinyDNSRecord {
         rtype: rtype.to_string(),
         fqdn:  fqdn.to_string(),
         target: target,
-        ttl: ttl
+        ttl: ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr);
 
-    retval
+    Ok(retval)
 }
 
 // Parse a TXT record - gets its own function because strings can be dumb
 // 'fqdn:rec:ttl:timestamp:lo
 // Type=TXT, fqdn=fqdn, target=string with extraneous quotes removed
-pub fn parse_txt(data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse_txt(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -226,8 +308,7 @@ pub fn parse_txt(data: &str) -> Vec<TinyDNSRecord> {
 
     // There still need to be at least two things in there
     if parts.len() < 2 {
-        println!("Error parsing line: {} of type TXT", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 2 fields for type TXT"));
     }
 
     // And the first part is just fqdn as normal
@@ -239,14 +320,12 @@ pub fn parse_txt(data: &str) -> Vec<TinyDNSRecord> {
     // find the end quotes.
     let mut rec = parts.remove(0).to_string();
     if !rec.starts_with('"') {
-        println!("TXT record missing double-quotes: {}", data);
-        return retval;
+        return Err(ParseError::new(Some(2), "TXT record missing double-quotes"));
     }
     while !rec.ends_with('"') {
         // Make sure there's another piece to remove
         if parts.len() == 0 {
-            println!("TXT record missing end quotes: {}", data);
-            return retval;
+            return Err(ParseError::new(Some(2), "TXT record missing end quotes"));
         }
         // Extract and add on to rec, then finish loop and test again.
         let rec2 = parts.remove(0);
@@ -266,25 +345,32 @@ pub fn parse_txt(data: &str) -> Vec<TinyDNSRecord> {
         }
     };
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 4)?;
+
     // Any data that may be left in 'parts' is extraneous and unneeded,
     // so proceed on to making a TDR, put it in retval, and return.
     let tdr = TinyDNSRecord {
         rtype: "TXT".to_string(),
         fqdn:  fqdn.to_string(),
         target: target.to_string(),
-        ttl: ttl
+        ttl: ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr);
 
     // Return retval
-    retval
+    Ok(retval)
 }
 
 // Parse an MX record into two TinyDNSRecords
 // @fqdn:ip:x:dist:ttl:timestamp:lo
 // (1) type=MX, fqdn=fqdn, target="dist x(.mx.fqdn)"
-// (2) type=A,  fqdn=x(.mx.fqdn), target=ip
-pub fn parse_mx(data: &str) -> Vec<TinyDNSRecord> {
+// (2) type=A,  fqdn=x(.mx.fqdn), target=ip (only if ip isn't empty)
+pub fn parse_mx(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -293,22 +379,20 @@ pub fn parse_mx(data: &str) -> Vec<TinyDNSRecord> {
 
     // FQDN, target, mx_fqdn required; error and return on parts < 3
     if parts.len() < 3 {
-        println!("Error parsing line: {} of type MX", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 3 fields for type MX"));
     }
 
     // Pull out required parts
     let fqdn = parts.remove(0);
-    let ip = parts.remove(0);
+    let ip = parts.remove(0); // This can be empty
     let x = parts.remove(0);
 
-    // Make sure IP is an IP
+    // Make sure IP is an IP, if there is one
     match ip.parse::<Ipv4Addr>() {
         Ok(_) => {},
+        Err(_) if ip.is_empty() => {},
         Err(e) => {
-            println!("Error processing record: {}", data);
-            println!("{}", e);
-            return retval;
+            return Err(ParseError::new(Some(2), &format!("bad IPv4 address '{}': {}", ip, e)));
         }
     }
 
@@ -329,33 +413,46 @@ pub fn parse_mx(data: &str) -> Vec<TinyDNSRecord> {
               parts.remove(0).parse::<i32>().unwrap_or(300))
     };
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 6)?;
+
     // Generate MX TDR
     let tdr1 = TinyDNSRecord {
         rtype:   "MX".to_string(),
         fqdn:    fqdn.to_string(),
         target:  format!("{} {}", mx_dist, mx_fqdn),
-        ttl:     ttl
+        ttl:     ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr1);
 
-    // Generate A TDR
-    let tdr2 = TinyDNSRecord {
-        rtype:  "A".to_string(),
-        fqdn:   mx_fqdn,
-        target: ip.to_string(),
-        ttl:    ttl
-    };
-    retval.push(tdr2);
+    // Generate A TDR, unless there's no IP
+    if !ip.is_empty() {
+        let tdr2 = TinyDNSRecord {
+            rtype:  "A".to_string(),
+            fqdn:   mx_fqdn,
+            target: ip.to_string(),
+            ttl:    ttl,
+            timestamp: timestamp,
+            location: location.clone(),
+            alias: None,
+            routing: None
+        };
+        retval.push(tdr2);
+    }
 
     // Return Vec
-    retval
+    Ok(retval)
 }
 
 // Parse an SOA record 
 // Zfqdn:ns:contact:serial:refresh:retry:expire:min:ttl:timestamp:lo
 // serial, refresh, retry, expire, and min are optional and default to
 // epoch, 16384, 2048, 1048576, and 2560.
-pub fn parse_soa(data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse_soa(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -364,8 +461,7 @@ pub fn parse_soa(data: &str) -> Vec<TinyDNSRecord> {
 
     // Error and return if we don't have at least 3 items
     if parts.len() < 3 {
-        println!("Error parsing line: {} of type SOA", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 3 fields for type SOA"));
     }
 
     // Pull the required 3 off
@@ -417,17 +513,24 @@ pub fn parse_soa(data: &str) -> Vec<TinyDNSRecord> {
     let target = format!("{} {} {} {} {} {} {}", ns, contact, ser, refr, 
                          retr, exp, min);
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 10)?;
+
     // Generate TDR, push, return
     let tdr = TinyDNSRecord {
         rtype:  "SOA".to_string(),
         fqdn:   fqdn.to_string(),
         target: target,
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr);
 
     // Return
-    retval
+    Ok(retval)
 }
 
 // Parse a combination A/NS/SOA record into 3 TinyDNSRecords
@@ -435,7 +538,7 @@ pub fn parse_soa(data: &str) -> Vec<TinyDNSRecord> {
 // (1) type=NS, fqdn=x(.ns.fqdn), target=fqdn
 // (2) type=A,  fqdn=x(.ns.fqdn), target=ip
 // (3) type=SOA fqdn=fqdn, target="x hostmaster.fqdn default-values"
-pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse_anssoa(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -444,8 +547,7 @@ pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
 
     // Make sure there's enough pieces
     if parts.len() < 3 {
-        println!("Error parsing line: {} of type A/NS/SOA", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 3 fields for type A/NS/SOA"));
     }
 
     // Get 'em
@@ -453,13 +555,12 @@ pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
     let ip = parts.remove(0); // This can be empty
     let x = parts.remove(0);
 
-    // Make sure IP is an IP
+    // Make sure IP is an IP, if there is one
     match ip.parse::<Ipv4Addr>() {
         Ok(_) => {},
+        Err(_) if ip.is_empty() => {},
         Err(e) => {
-            println!("Error processing record: {}", data);
-            println!("{}", e);
-            return retval;
+            return Err(ParseError::new(Some(2), &format!("bad IPv4 address '{}': {}", ip, e)));
         }
     }
 
@@ -476,12 +577,19 @@ pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
         false => format!("{}.ns.{}", x, fqdn)
     };
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 5)?;
+
     // Start building TDRs. If ip is empty, don't create (2).
     let tdr1 = TinyDNSRecord {
         rtype:  "NS".to_string(),
         fqdn:   ns_fqdn.to_string(),
         target: fqdn.to_string(),
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr1);
 
@@ -490,7 +598,11 @@ pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
             rtype:  "A".to_string(),
             fqdn:   ns_fqdn.to_string(),
             target: ip.to_string(),
-            ttl:    ttl
+            ttl:    ttl,
+            timestamp: timestamp,
+            location: location.clone(),
+            alias: None,
+            routing: None
         };
         retval.push(tdr2);
     }
@@ -500,19 +612,23 @@ pub fn parse_anssoa(data: &str) -> Vec<TinyDNSRecord> {
         rtype:  "SOA".to_string(),
         fqdn:   fqdn.to_string(),
         target: target,
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr3);
 
     // Return
-    retval
+    Ok(retval)
 }
 
 // Parse a combination A/NS record into 2 TinyDNSRecords
 // &fqdn:ip:x:ttl:timestamp:lo
 // (1) type=NS, fqdn=x(.ns.fqdn), target=fqdn
 // (2) type=A,  fqdn=x(.ns.fqdn), target=ip
-pub fn parse_ans(data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse_ans(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -521,8 +637,7 @@ pub fn parse_ans(data: &str) -> Vec<TinyDNSRecord> {
 
     // 3 shall be the number of the counting
     if parts.len() < 3 {
-        println!("Error parsing line: {} of type A/NS", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 3 fields for type A/NS"));
     }
 
     // You're gonna extract HIM?
@@ -534,9 +649,7 @@ pub fn parse_ans(data: &str) -> Vec<TinyDNSRecord> {
     match ip.parse::<Ipv4Addr>() {
         Ok(_) => {},
         Err(e) => {
-            println!("Error processing record: {}", data);
-            println!("{}", e);
-            return retval;
+            return Err(ParseError::new(Some(2), &format!("bad IPv4 address '{}': {}", ip, e)));
         }
     }
 
@@ -552,12 +665,19 @@ pub fn parse_ans(data: &str) -> Vec<TinyDNSRecord> {
         false => format!("{}.ns.{}", x, fqdn)
     };
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 5)?;
+
     // Build TDRs
     let tdr1 = TinyDNSRecord {
         rtype:  "NS".to_string(),
         fqdn:   ns_fqdn.to_string(),
         target: fqdn.to_string(),
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr1);
 
@@ -565,19 +685,23 @@ pub fn parse_ans(data: &str) -> Vec<TinyDNSRecord> {
         rtype:  "A".to_string(),
         fqdn:   ns_fqdn.to_string(),
         target: ip.to_string(),
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr2);
 
     // Return
-    retval
+    Ok(retval)
 }
 
 // Parse a combination A/PTR record into 2 TinyDNSRecords
 // =fqdn:ip:ttl:timestamp:lo
 // (1) type=A, fqdn=fqdn, target=ip
 // (2) type=PTR, fqdn=arpaized-ip, target=fqdn
-pub fn parse_aptr(data: &str) -> Vec<TinyDNSRecord> {
+pub fn parse_aptr(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
     // Create return vec
     let mut retval = Vec::new();
 
@@ -586,8 +710,7 @@ pub fn parse_aptr(data: &str) -> Vec<TinyDNSRecord> {
 
     // It takes two to tango
     if parts.len() < 2 {
-        println!("Error parsing line: {} of type A/PTR", data);
-        return retval;
+        return Err(ParseError::new(None, "expected at least 2 fields for type A/PTR"));
     }
 
     // Front and back
@@ -598,9 +721,7 @@ pub fn parse_aptr(data: &str) -> Vec<TinyDNSRecord> {
     match ip.parse::<Ipv4Addr>() {
         Ok(_) => {},
         Err(e) => {
-            println!("Error processing record: {}", data);
-            println!("{}", e);
-            return retval;
+            return Err(ParseError::new(Some(2), &format!("bad IPv4 address '{}': {}", ip, e)));
         }
     };
 
@@ -616,12 +737,19 @@ pub fn parse_aptr(data: &str) -> Vec<TinyDNSRecord> {
     let backwards = ipbits.join(".");
     let ptr_fqdn = format!("{}.in-addr.arpa", backwards);
 
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 4)?;
+
     // Build TDRs
     let tdr1 = TinyDNSRecord {
         rtype:  "A".to_string(),
         fqdn:   fqdn.to_string(),
         target: ip.to_string(),
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr1);
 
@@ -629,14 +757,774 @@ pub fn parse_aptr(data: &str) -> Vec<TinyDNSRecord> {
         rtype:  "PTR".to_string(),
         fqdn:   ptr_fqdn,
         target: fqdn.to_string(),
-        ttl:    ttl
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
     };
     retval.push(tdr2);
 
     // Return
+    Ok(retval)
+}
+
+// Pull the optional timestamp and location fields off the front of
+// 'parts'.  'field' is the field number of the timestamp, for errors.
+// Timestamps are TAI64 labels (16 hex digits) and get turned into unix
+// seconds; an empty field means no timestamp.
+fn take_tslo(parts: &mut Vec<&str>, field: usize) -> Result<(Option<u64>, Option<String>), ParseError> {
+    let timestamp = match parts.is_empty() {
+        true => None,
+        false => {
+            let ts = parts.remove(0);
+            if ts.is_empty() {
+                None
+            } else {
+                match u64::from_str_radix(ts, 16) {
+                    Ok(x) if ts.len() == 16 && x >= TAI64_OFFSET => Some(x - TAI64_OFFSET),
+                    _ => {
+                        return Err(ParseError::new(Some(field), &format!("bad TAI64 timestamp '{}'", ts)));
+                    }
+                }
+            }
+        }
+    };
+    let location = match parts.is_empty() {
+        true => None,
+        false => {
+            let lo = parts.remove(0);
+            match lo.len() {
+                0 => None,
+                1 | 2 => Some(lo.to_string()),
+                _ => {
+                    return Err(ParseError::new(Some(field + 1), &format!("location '{}' longer than 2 characters", lo)));
+                }
+            }
+        }
+    };
+    Ok((timestamp, location))
+}
+
+// TAI64 labels count from 2^62, and TAI is 10 seconds ahead of UTC
+const TAI64_OFFSET: u64 = 0x4000000000000000 + 10;
+
+// Turn unix seconds back into a TAI64 label for writing out
+pub fn tai64_label(ts: u64) -> String {
+    format!("{:016x}", ts + TAI64_OFFSET)
+}
+
+// Client location definitions ('%lo:ipprefix') would need Route53 CIDR
+// routing, which the Route53 client Macrotis is built with doesn't have.
+// Refuse them outright rather than plan changes that can't be pushed.
+pub fn parse_location(_data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    Err(ParseError::new(None, "'%' location lines aren't supported (Route53 CIDR routing isn't available); map the location to a geolocation under 'locations' in the config instead"))
+}
+
+// Record types Route53 allows an alias for
+const ALIAS_TYPES: [&str; 10] = ["A", "AAAA", "CAA", "CNAME", "MX", "NAPTR", "PTR", "SPF", "SRV", "TXT"];
+
+// Parse a Route53 alias record (not something tinydns itself has)
+// ~fqdn:target:zone:type:health:timestamp:lo
+// Type=type (default A), fqdn=fqdn, target=the DNS name being aliased.
+// Zone is the hosted zone ID of the target; left empty, it gets worked
+// out from the config later.  Health is 1 to have Route53 check the
+// target's health.  Aliases have no TTL of their own, so the TTL is 0 and
+// a timestamp is when the alias stops being served.
+pub fn parse_alias(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    let mut parts: Vec<&str> = data.split(':').collect();
+    if parts.len() < 2 || parts[1].is_empty() {
+        return Err(ParseError::new(None, "expected at least 2 fields for an alias"));
+    }
+    let fqdn = parts.remove(0);
+    let target = parts.remove(0).trim_end_matches('.').to_lowercase();
+    let zone = match parts.is_empty() {
+        true => "",
+        false => parts.remove(0)
+    };
+    let rtype = match parts.is_empty() {
+        true => "A".to_string(),
+        false => match parts.remove(0) {
+            "" => "A".to_string(),
+            x => x.to_uppercase()
+        }
+    };
+    if !ALIAS_TYPES.contains(&rtype.as_str()) {
+        return Err(ParseError::new(Some(4), &format!("Route53 can't alias a {} record", rtype)));
+    }
+    let health = match parts.is_empty() {
+        true => false,
+        false => match parts.remove(0) {
+            "" | "0" => false,
+            "1" => true,
+            x => {
+                return Err(ParseError::new(Some(5), &format!("health should be 0 or 1, not '{}'", x)));
+            }
+        }
+    };
+    let (timestamp, location) = take_tslo(&mut parts, 6)?;
+
+    let tdr = TinyDNSRecord {
+        rtype: rtype,
+        fqdn: fqdn.to_string(),
+        target: target.to_string(),
+        ttl: 0,
+        timestamp: timestamp,
+        location: location,
+        alias: Some(AliasTarget {
+            hosted_zone_id: zone.to_string(),
+            dns_name: target,
+            evaluate_target_health: health
+        }),
+        routing: None
+    };
+    Ok(vec![tdr])
+}
+
+// Parse a routing policy for the records at a name in one location (not
+// something tinydns itself has)
+// !fqdn:lo:policy:value:health
+// Type=!, fqdn=fqdn, location=lo.  Records at fqdn with that 'lo' become a
+// Route53 record set using the policy, with lo as its set identifier,
+// instead of being located.  The policy is one of:
+//   weight      value is the weight, 0-255
+//   latency     value is the AWS region the records are served from
+//   failover    value is primary or secondary
+//   multivalue  no value
+// Health is the health check for the set, if any: the name of one declared
+// in the config, or the ID of an existing one.
+pub fn parse_policy(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    let mut parts: Vec<&str> = data.split(':').collect();
+    if parts.len() < 3 {
+        return Err(ParseError::new(None, "expected at least 3 fields for a routing policy"));
+    }
+    let fqdn = parts.remove(0);
+    let lo = parts.remove(0);
+    if lo.is_empty() || lo.len() > 2 {
+        return Err(ParseError::new(Some(2), "location must be 1 or 2 characters"));
+    }
+    let kind = parts.remove(0).to_lowercase();
+    let value = match parts.is_empty() {
+        true => "",
+        false => parts.remove(0)
+    };
+    let mut policy = RoutingPolicy::default();
+    match (kind.as_str(), value) {
+        ("weight", x) => match x.parse::<i64>() {
+            Ok(w) if (0..=255).contains(&w) => { policy.weight = Some(w); },
+            _ => {
+                return Err(ParseError::new(Some(4), &format!("weight should be 0-255, not '{}'", x)));
+            }
+        },
+        ("latency", "") => {
+            return Err(ParseError::new(Some(4), "latency needs a region"));
+        },
+        ("latency", x) => { policy.region = Some(x.to_lowercase()); },
+        ("failover", x) => match x.to_uppercase().as_str() {
+            "PRIMARY" | "SECONDARY" => { policy.failover = Some(x.to_uppercase()); },
+            _ => {
+                return Err(ParseError::new(Some(4), &format!("failover should be primary or secondary, not '{}'", x)));
+            }
+        },
+        ("multivalue", "") => { policy.multivalue = Some(true); },
+        ("multivalue", _) => {
+            return Err(ParseError::new(Some(4), "multivalue doesn't take a value"));
+        },
+        (x, _) => {
+            return Err(ParseError::new(Some(3), &format!("unknown routing policy '{}'", x)));
+        }
+    };
+    if !parts.is_empty() {
+        let health = parts.remove(0);
+        if !health.is_empty() {
+            policy.health_check_id = Some(health.to_string());
+        }
+    }
+    if !parts.is_empty() {
+        return Err(ParseError::new(Some(6), "too many fields for a routing policy"));
+    }
+
+    let tdr = TinyDNSRecord {
+        rtype: "!".to_string(),
+        fqdn: fqdn.to_string(),
+        location: Some(lo.to_string()),
+        routing: Some(policy),
+        ..Default::default()
+    };
+    Ok(vec![tdr])
+}
+
+// Collect the routing policies from '!' lines, by lowercased name and
+// location.  More than one for the same name and location is an error.
+pub fn policies(records: &Vec<TinyDNSRecord>) -> Result<HashMap<(String, String), RoutingPolicy>, String> {
+    let mut retval = HashMap::new();
+    for rec in records {
+        if let (Some(lo), Some(p)) = (&rec.location, &rec.routing) {
+            let key = (rec.fqdn.to_lowercase(), lo.to_string());
+            if retval.insert(key, p.clone()).is_some() {
+                return Err(format!("more than one routing policy for {} in location '{}'", rec.fqdn, lo));
+            }
+        }
+    }
+    Ok(retval)
+}
+
+// Check whether a record should be served at the given time (in unix
+// seconds).  A nonzero TTL makes the timestamp a start time; a zero TTL
+// makes it an end time.
+pub fn is_active(rec: &TinyDNSRecord, now: u64) -> bool {
+    match rec.timestamp {
+        None => true,
+        Some(ts) => match rec.ttl {
+            0 => now < ts,
+            _ => now >= ts
+        }
+    }
+}
+
+// Drop any records that shouldn't be served right now
+pub fn filter_active(records: Vec<TinyDNSRecord>) -> Vec<TinyDNSRecord> {
+    let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => panic!("Something is REALLY wrong, SystemTime < EPOCH??")
+    };
+    records.into_iter().filter(|r| is_active(r, now)).collect()
+}
+
+// Pull an IPv6 address off the front of 'parts'.  TinyDNS wants 32 hex
+// digits with no colons, but we also accept the usual colon form.  Since
+// that clashes with the field separator, the colon form can be wrapped in
+// square brackets; unbracketed, it's only accepted if exactly one run of
+// fields makes a valid address (otherwise a TTL could be mistaken for the
+// last group).
+fn take_ip6(parts: &mut Vec<&str>) -> Result<Ipv6Addr, String> {
+    if parts.is_empty() {
+        return Err("missing IPv6 address".to_string());
+    }
+
+    // The native 32-hex-digit form
+    let first = parts[0];
+    if first.len() == 32 && first.chars().all(|c| c.is_digit(16)) {
+        parts.remove(0);
+        return match u128::from_str_radix(first, 16) {
+            Ok(n) => Ok(Ipv6Addr::from(n)),
+            Err(e) => Err(e.to_string())
+        };
+    }
+
+    // Bracketed colon form: keep pulling pieces until the closing bracket
+    if first.starts_with('[') {
+        let mut ip = parts.remove(0).to_string();
+        while !ip.ends_with(']') {
+            if parts.is_empty() {
+                return Err(format!("missing closing bracket in {}", ip));
+            }
+            ip = format!("{}:{}", ip, parts.remove(0));
+        }
+        return match ip.trim_matches(|c| c == '[' || c == ']').parse::<Ipv6Addr>() {
+            Ok(x) => Ok(x),
+            Err(e) => Err(format!("{}: {}", ip, e))
+        };
+    }
+
+    // Bare colon form: an address has at most 8 groups, plus one more
+    // piece for a '::' at either end.
+    let mut found = Vec::new();
+    for n in 1..std::cmp::min(parts.len(), 10) + 1 {
+        let joined = parts[..n].join(":");
+        if let Ok(x) = joined.parse::<Ipv6Addr>() {
+            found.push((n, x));
+        }
+    }
+    match found.len() {
+        1 => {
+            let (n, ip) = found[0];
+            parts.drain(..n);
+            Ok(ip)
+        },
+        0 => Err(format!("{} is not a valid IPv6 address", parts.join(":"))),
+        _ => Err(format!("ambiguous IPv6 address in {}; use 32 hex digits or [brackets]",
+                         parts.join(":")))
+    }
+}
+
+// Build an ip6.arpa PTR name from an IPv6 address by reversing its 32
+// nibbles, same idea as the in-addr.arpa names in parse_aptr.
+pub fn ip6_arpa(ip: &Ipv6Addr) -> String {
+    let hex = format!("{:032x}", u128::from(*ip));
+    let mut nibbles: Vec<String> = hex.chars().map(|c| c.to_string()).collect();
+    nibbles.reverse();
+    format!("{}.ip6.arpa", nibbles.join("."))
+}
+
+// Parse an AAAA record into 1 TinyDNSRecord
+// 3fqdn:ip6:ttl:timestamp:lo
+// Type=AAAA, fqdn=fqdn, target=ip6 in its usual compressed colon form
+pub fn parse_aaaa(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    // Create return vec
+    let mut retval = Vec::new();
+
+    // Split on colon - the IPv6 address may span several pieces
+    let mut parts: Vec<&str> = data.split(':').collect();
+
+    // Need at least an fqdn and an address
+    if parts.len() < 2 {
+        return Err(ParseError::new(None, "expected at least 2 fields for type AAAA"));
+    }
+
+    let fqdn = parts.remove(0);
+    let ip = match take_ip6(&mut parts) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(ParseError::new(Some(2), &e));
+        }
+    };
+
+    // TTL check
+    let ttl = match parts.is_empty() {
+        true => 300,
+        false => parts.remove(0).parse::<i32>().unwrap_or(300)
+    };
+
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 4)?;
+
+    let tdr = TinyDNSRecord {
+        rtype:  "AAAA".to_string(),
+        fqdn:   fqdn.to_string(),
+        target: ip.to_string(),
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
+    };
+    retval.push(tdr);
+
+    // Return
+    Ok(retval)
+}
+
+// Parse a combination AAAA/PTR record into 2 TinyDNSRecords
+// 6fqdn:ip6:ttl:timestamp:lo
+// (1) type=AAAA, fqdn=fqdn, target=ip6
+// (2) type=PTR, fqdn=nibble-reversed ip6.arpa name, target=fqdn
+pub fn parse_aaaaptr(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    // The AAAA half is exactly what parse_aaaa does
+    let mut retval = parse_aaaa(data)?;
+
+    // Build a PTR from it
+    let ip = match retval[0].target.parse::<Ipv6Addr>() {
+        Ok(x) => x,
+        Err(e) => { return Err(ParseError::new(Some(2), &e.to_string())); }
+    };
+    let tdr = TinyDNSRecord {
+        rtype:  "PTR".to_string(),
+        fqdn:   ip6_arpa(&ip),
+        target: retval[0].fqdn.to_string(),
+        ttl:    retval[0].ttl,
+        timestamp: retval[0].timestamp,
+        location: retval[0].location.clone(),
+        alias: None,
+        routing: None
+    };
+    retval.push(tdr);
+
+    // Return
+    Ok(retval)
+}
+
+// Turn a string containing tinydns-style octal escapes (\NNN) into raw
+// bytes.  Anything not escaped is taken as-is.
+pub fn unescape_octal(data: &str) -> Result<Vec<u8>, String> {
+    let bytes = data.as_bytes();
+    let mut retval = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            retval.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        // Need three octal digits after the backslash
+        let digits = match data.get(i + 1..i + 4) {
+            Some(x) => x,
+            None => { return Err(format!("truncated escape at position {}", i)); }
+        };
+        match u8::from_str_radix(digits, 8) {
+            Ok(b) => { retval.push(b); },
+            Err(_) => { return Err(format!("bad octal escape \\{}", digits)); }
+        };
+        i += 4;
+    }
+    Ok(retval)
+}
+
+// Little helper for walking through binary rdata a piece at a time
+struct RdataReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> RdataReader<'a> {
+    fn u8(&mut self) -> Result<u8, String> {
+        match self.data.get(self.pos) {
+            Some(x) => { self.pos += 1; Ok(*x) },
+            None => Err("rdata too short".to_string())
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let hi = self.u8()? as u16;
+        let lo = self.u8()? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err("rdata too short".to_string());
+        }
+        let retval = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(retval)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let retval = &self.data[self.pos..];
+        self.pos = self.data.len();
+        retval
+    }
+
+    // A length-prefixed <character-string>, returned wrapped in quotes
+    fn charstr(&mut self) -> Result<String, String> {
+        let len = self.u8()? as usize;
+        let raw = self.bytes(len)?;
+        Ok(quote(&String::from_utf8_lossy(raw)))
+    }
+
+    // An uncompressed domain name in wire format (length-prefixed labels
+    // ending with a zero-length one)
+    fn name(&mut self) -> Result<String, String> {
+        let mut labels = Vec::new();
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                break;
+            }
+            let raw = self.bytes(len)?;
+            labels.push(String::from_utf8_lossy(raw).to_string());
+        }
+        match labels.is_empty() {
+            true => Ok(".".to_string()),
+            false => Ok(labels.join("."))
+        }
+    }
+
+    fn done(&self) -> Result<(), String> {
+        match self.pos == self.data.len() {
+            true => Ok(()),
+            false => Err("trailing bytes in rdata".to_string())
+        }
+    }
+}
+
+// Wrap a string in double quotes, escaping any quotes or backslashes in it
+pub fn quote(data: &str) -> String {
+    format!("\"{}\"", data.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>().join("")
+}
+
+// Decode raw rdata for the record types we know how to handle into the
+// type name and text form Route53 expects.  Anything else is an error.
+pub fn decode_rdata(n: u16, rdata: &[u8]) -> Result<(String, String), String> {
+    let mut r = RdataReader { data: rdata, pos: 0 };
+    let (rtype, text) = match n {
+        // TXT: one or more quoted strings
+        16 => {
+            let mut strings = Vec::new();
+            while r.pos < r.data.len() {
+                strings.push(r.charstr()?);
+            }
+            ("TXT", strings.join(" "))
+        },
+        // SRV: priority weight port target
+        33 => {
+            let (prio, weight, port) = (r.u16()?, r.u16()?, r.u16()?);
+            ("SRV", format!("{} {} {} {}", prio, weight, port, r.name()?))
+        },
+        // NAPTR: order preference flags service regexp replacement
+        35 => {
+            let (order, pref) = (r.u16()?, r.u16()?);
+            let (flags, service, regexp) = (r.charstr()?, r.charstr()?, r.charstr()?);
+            ("NAPTR", format!("{} {} {} {} {} {}", order, pref, flags, service,
+                              regexp, r.name()?))
+        },
+        // SSHFP: algorithm fp-type fingerprint
+        44 => {
+            let (algo, fptype) = (r.u8()?, r.u8()?);
+            ("SSHFP", format!("{} {} {}", algo, fptype, to_hex(r.rest())))
+        },
+        // TLSA: usage selector matching-type data
+        52 => {
+            let (usage, selector, matching) = (r.u8()?, r.u8()?, r.u8()?);
+            ("TLSA", format!("{} {} {} {}", usage, selector, matching, to_hex(r.rest())))
+        },
+        // CAA: flags tag "value"
+        257 => {
+            let flags = r.u8()?;
+            let taglen = r.u8()? as usize;
+            let tag = String::from_utf8_lossy(r.bytes(taglen)?).to_string();
+            ("CAA", format!("{} {} {}", flags, tag, quote(&String::from_utf8_lossy(r.rest()))))
+        },
+        _ => {
+            return Err(format!("unsupported record type number {}", n));
+        }
+    };
+    r.done()?;
+    Ok((rtype.to_string(), text))
+}
+
+// The reverse of unescape_octal: anything that isn't a printable ASCII
+// character (plus colons and backslashes, which mean something to
+// tinydns) gets written as \NNN.
+pub fn escape_octal(data: &[u8]) -> String {
+    let mut retval = String::new();
+    for b in data {
+        match *b {
+            b':' | b'\\' => { retval.push_str(&format!("\\{:03o}", b)); },
+            0x21..=0x7e => { retval.push(*b as char); },
+            _ => { retval.push_str(&format!("\\{:03o}", b)); }
+        };
+    }
     retval
 }
 
+// Split record text into whitespace-separated tokens, treating a quoted
+// string (with backslash escapes) as a single token.  Returns the tokens
+// along with whether each one was quoted.
+fn tokenize(text: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut retval = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let quoted = match chars.peek() {
+            None => { break; },
+            Some('"') => { chars.next(); true },
+            Some(_) => false
+        };
+        let mut token = String::new();
+        loop {
+            match chars.next() {
+                None if quoted => { return Err(format!("unterminated quote in {}", text)); },
+                None => { break; },
+                Some('"') if quoted => { break; },
+                Some('\\') if quoted => {
+                    match chars.next() {
+                        Some(c) => { token.push(c); },
+                        None => { return Err(format!("unterminated quote in {}", text)); }
+                    };
+                },
+                Some(c) if !quoted && c.is_whitespace() => { break; },
+                Some(c) => { token.push(c); }
+            };
+        }
+        retval.push((token, quoted));
+    }
+    Ok(retval)
+}
+
+// If 'text' is nothing but quoted strings (like a Route53 TXT value),
+// return them all run together; otherwise None.
+pub fn unquote(text: &str) -> Option<String> {
+    let tokens = match tokenize(text) {
+        Ok(x) => x,
+        Err(_) => { return None; }
+    };
+    if tokens.is_empty() || tokens.iter().any(|x| !x.1) {
+        return None;
+    }
+    Some(tokens.into_iter().map(|x| x.0).collect::<Vec<String>>().join(""))
+}
+
+// Wire-format pieces for encode_rdata
+fn put_charstr(out: &mut Vec<u8>, s: &str) -> Result<(), String> {
+    if s.len() > 255 {
+        return Err("character string longer than 255 bytes".to_string());
+    }
+    out.push(s.len() as u8);
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) -> Result<(), String> {
+    let trimmed = name.trim_end_matches('.');
+    if !trimmed.is_empty() {
+        for label in trimmed.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(format!("bad label in name {}", name));
+            }
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+    }
+    out.push(0);
+    Ok(())
+}
+
+fn put_num<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    match s.parse::<T>() {
+        Ok(x) => Ok(x),
+        Err(_) => Err(format!("bad number {}", s))
+    }
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.len() % 2 != 0 || !s.chars().all(|c| c.is_digit(16)) {
+        return Err(format!("bad hex string {}", s));
+    }
+    let mut retval = Vec::new();
+    for i in (0..s.len()).step_by(2) {
+        retval.push(u8::from_str_radix(&s[i..i + 2], 16).unwrap());
+    }
+    Ok(retval)
+}
+
+// The reverse of decode_rdata: turn the Route53 text form of one of the
+// record types we know about back into its type number and raw rdata.
+pub fn encode_rdata(rtype: &str, text: &str) -> Result<(u16, Vec<u8>), String> {
+    let tokens = tokenize(text)?;
+    let t: Vec<&str> = tokens.iter().map(|x| x.0.as_str()).collect();
+    let want = |n: usize| -> Result<(), String> {
+        match t.len() == n {
+            true => Ok(()),
+            false => Err(format!("expected {} fields for {}: {}", n, rtype, text))
+        }
+    };
+    let mut out = Vec::new();
+    let n = match rtype {
+        "TXT" => {
+            if t.is_empty() || tokens.iter().any(|x| !x.1) {
+                return Err(format!("TXT values must be quoted: {}", text));
+            }
+            for s in &t {
+                put_charstr(&mut out, s)?;
+            }
+            16
+        },
+        "SRV" => {
+            want(4)?;
+            for x in &t[..3] {
+                out.extend_from_slice(&put_num::<u16>(x)?.to_be_bytes());
+            }
+            put_name(&mut out, t[3])?;
+            33
+        },
+        "NAPTR" => {
+            want(6)?;
+            out.extend_from_slice(&put_num::<u16>(t[0])?.to_be_bytes());
+            out.extend_from_slice(&put_num::<u16>(t[1])?.to_be_bytes());
+            for x in &t[2..5] {
+                put_charstr(&mut out, x)?;
+            }
+            put_name(&mut out, t[5])?;
+            35
+        },
+        "SSHFP" => {
+            want(3)?;
+            out.push(put_num::<u8>(t[0])?);
+            out.push(put_num::<u8>(t[1])?);
+            out.append(&mut from_hex(t[2])?);
+            44
+        },
+        "TLSA" => {
+            want(4)?;
+            for x in &t[..3] {
+                out.push(put_num::<u8>(x)?);
+            }
+            out.append(&mut from_hex(t[3])?);
+            52
+        },
+        "CAA" => {
+            want(3)?;
+            out.push(put_num::<u8>(t[0])?);
+            put_charstr(&mut out, t[1])?;
+            out.extend_from_slice(t[2].as_bytes());
+            257
+        },
+        _ => {
+            return Err(format!("no generic encoding for type {}", rtype));
+        }
+    };
+    Ok((n, out))
+}
+
+// Parse a generic record into 1 TinyDNSRecord
+// :fqdn:n:rdata:ttl:timestamp:lo
+// n is the numeric record type and rdata is the raw record data with
+// octal escapes.  Known types are decoded into their Route53 text form.
+pub fn parse_generic(data: &str) -> Result<Vec<TinyDNSRecord>, ParseError> {
+    // Create return vec
+    let mut retval = Vec::new();
+
+    // Split on colon.  Colons inside rdata have to be escaped as \072 so
+    // this is safe.
+    let mut parts: Vec<&str> = data.split(':').collect();
+
+    // fqdn, type number, and rdata are all required
+    if parts.len() < 3 {
+        return Err(ParseError::new(None, "expected at least 3 fields for generic type"));
+    }
+
+    let fqdn = parts.remove(0);
+    let n = match parts.remove(0).parse::<u16>() {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(ParseError::new(Some(2), &format!("bad record type number: {}", e)));
+        }
+    };
+    let rdata = match unescape_octal(parts.remove(0)) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(ParseError::new(Some(3), &e));
+        }
+    };
+    let (rtype, target) = match decode_rdata(n, &rdata) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(ParseError::new(Some(3), &e));
+        }
+    };
+
+    // TTL check
+    let ttl = match parts.is_empty() {
+        true => 300,
+        false => parts.remove(0).parse::<i32>().unwrap_or(300)
+    };
+
+    // Timestamp and location come after the TTL
+    let (timestamp, location) = take_tslo(&mut parts, 5)?;
+
+    let tdr = TinyDNSRecord {
+        rtype:  rtype,
+        fqdn:   fqdn.to_string(),
+        target: target,
+        ttl:    ttl,
+        timestamp: timestamp,
+        location: location.clone(),
+        alias: None,
+        routing: None
+    };
+    retval.push(tdr);
+
+    // Return
+    Ok(retval)
+}
+
 // How about some tests everyone loves tests!
 #[cfg(test)]
 mod tests {
@@ -649,41 +1537,39 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let prec = TinyDNSRecord {
             rtype: "PTR".to_string(),
             fqdn:  "4.3.2.1.in-addr.arpa".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let crec = TinyDNSRecord {
             rtype: "CNAME".to_string(),
             fqdn:  "bar.test.com".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         
         let atext = "foo.test.com:1.2.3.4:300";
         let ptext = "4.3.2.1.in-addr.arpa:foo.test.com:300";
         let ctext = "bar.test.com:foo.test.com:300";
 
-        assert!(vec![arec] == parse("A", atext));
-        assert!(vec![prec] == parse("PTR", ptext));
-        assert!(vec![crec] == parse("CNAME", ctext));
+        assert!(vec![arec] == parse("A", atext).unwrap());
+        assert!(vec![prec] == parse("PTR", ptext).unwrap());
+        assert!(vec![crec] == parse("CNAME", ctext).unwrap());
     }
 
     #[test]
     fn test_bad_ip_a_record() {
-        // Make sure a bad IP in an A record returns an empty vec
+        // Make sure a bad IP in an A record returns an error
         let atext="foo.test.com:999.999.999.999:300";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse("A", atext));
+        assert!(parse("A", atext).is_err());
     }
 
     #[test]
     fn test_basic_bad_input() {
-        // Make sure we get an empty vec back if we send bad data to parse()
+        // Make sure we get an error back if we send bad data to parse()
         let text = "this is some crappy data";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse("A", text));
+        assert!(parse("A", text).is_err());
     }
 
     #[test]
@@ -693,10 +1579,10 @@ mod tests {
             rtype: "TXT".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "a string of data".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let text = "foo.test.com:\"a string of data\":300";
 
-        assert!(vec![trec] == parse_txt(text));
+        assert!(vec![trec] == parse_txt(text).unwrap());
     }
 
     #[test]
@@ -704,9 +1590,8 @@ mod tests {
         // Test parse_text with bad data
         let text = "foo.test.com:no quotes uhoh:300";
         let text2 = "foo.test.com:\"missing end quote:300";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_txt(text));
-        assert!(empty == parse_txt(text2));
+        assert!(parse_txt(text).is_err());
+        assert!(parse_txt(text2).is_err());
     }
 
     #[test]
@@ -716,14 +1601,14 @@ mod tests {
             rtype: "MX".to_string(),
             fqdn:  "test.com".to_string(),
             target: "20 foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let a  = TinyDNSRecord {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "test.com:1.2.3.4:foo.test.com:20:300";
-        let parsed = parse_mx(line);
+        let parsed = parse_mx(line).unwrap();
         assert!(mx == parsed[0]);
         assert!(a  == parsed[1]);
     }
@@ -733,9 +1618,8 @@ mod tests {
         // Test parse_mx with bad data
         let badip = "test.com:999.999.999.999:foo.test.com:20:300";
         let badstr = "bad data";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_mx(badip));
-        assert!(empty == parse_mx(badstr));
+        assert!(parse_mx(badip).is_err());
+        assert!(parse_mx(badstr).is_err());
     }
 
     #[test]
@@ -745,17 +1629,16 @@ mod tests {
             rtype: "SOA".to_string(),
             fqdn:  "test.com".to_string(),
             target: "foo.test.com person.test.com 1 2 3 4 5".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "test.com:foo.test.com:person.test.com:1:2:3:4:5:300";
-        assert!(vec![soa] == parse_soa(line));
+        assert!(vec![soa] == parse_soa(line).unwrap());
     }
 
     #[test]
     fn test_bad_parse_soa() {
         // Test parse_soa with bad data
         let line = "look at this bad data";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_soa(line));
+        assert!(parse_soa(line).is_err());
     }
 
     #[test]
@@ -765,19 +1648,19 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ns  = TinyDNSRecord {
             rtype: "NS".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let soa = TinyDNSRecord {
             rtype: "SOA".to_string(),
             fqdn:  "test.com".to_string(),
             target: "foo.test.com hostmaster.test.com 1 1 1 1 60".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "test.com:1.2.3.4:foo.test.com:300";
-        let parsed = parse_anssoa(line);
+        let parsed = parse_anssoa(line).unwrap();
         assert!(ns == parsed[0]);
         assert!(a  == parsed[1]);
         assert!(soa == parsed[2]);
@@ -788,9 +1671,8 @@ mod tests {
         // Test parse_anssoa with bad data
         let line = "super bad data";
         let badip = "fqdn:999.999.999.999:x:300";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_anssoa(line));
-        assert!(empty == parse_anssoa(badip));
+        assert!(parse_anssoa(line).is_err());
+        assert!(parse_anssoa(badip).is_err());
     }
 
     #[test]
@@ -800,14 +1682,14 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ns  = TinyDNSRecord {
             rtype: "NS".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "test.com:1.2.3.4:foo.test.com:300";
-        let parsed = parse_ans(line);
+        let parsed = parse_ans(line).unwrap();
         assert!(ns == parsed[0]);
         assert!(a  == parsed[1]);
     }
@@ -817,9 +1699,8 @@ mod tests {
         // Test parse_ans with bad data
         let line = "no good rotten data";
         let badip = "fqdn:9999.999.258.0:x:300";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_ans(line));
-        assert!(empty == parse_ans(badip));
+        assert!(parse_ans(line).is_err());
+        assert!(parse_ans(badip).is_err());
     }
 
     #[test]
@@ -829,14 +1710,14 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ptr = TinyDNSRecord {
             rtype: "PTR".to_string(),
             fqdn:  "4.3.2.1.in-addr.arpa".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "foo.test.com:1.2.3.4:300";
-        let parsed = parse_aptr(line);
+        let parsed = parse_aptr(line).unwrap();
         assert!(a == parsed[0]);
         assert!(ptr == parsed[1]);
     }
@@ -846,12 +1727,106 @@ mod tests {
         // Test parse_aptr with bad data
         let line = "oooooh this data!";
         let badip = "fqdn:99.999.598.10:x:300";
-        let empty: Vec<TinyDNSRecord> = Vec::new();
-        assert!(empty == parse_aptr(line));
-        assert!(empty == parse_aptr(badip));
+        assert!(parse_aptr(line).is_err());
+        assert!(parse_aptr(badip).is_err());
+    }
+
+    #[test]
+    fn test_parse_aaaa() {
+        // Test parse_aaaa with both the hex and colon forms
+        let aaaa = TinyDNSRecord {
+            rtype: "AAAA".to_string(),
+            fqdn:  "foo.test.com".to_string(),
+            target: "2001:db8::1".to_string(),
+            ttl: 300, ..Default::default() };
+        let hex = "foo.test.com:20010db8000000000000000000000001:300";
+        let bracket = "foo.test.com:[2001:db8::1]:300";
+        let bare = "foo.test.com:2001:db8:0:0:0:0:0:1:300";
+        let nottl = "foo.test.com:2001:db8::1";
+        assert!(vec![aaaa.clone()] == parse_aaaa(hex).unwrap());
+        assert!(vec![aaaa.clone()] == parse_aaaa(bracket).unwrap());
+        assert!(vec![aaaa.clone()] == parse_aaaa(bare).unwrap());
+        assert!(vec![aaaa] == parse_aaaa(nottl).unwrap());
+        assert_eq!(parse_aaaa(hex).unwrap()[0].ttl, 300);
+    }
+
+    #[test]
+    fn test_bad_parse_aaaa() {
+        // Test parse_aaaa with bad data
+        let line = "not an ipv6 record";
+        let badip = "foo.test.com:2001:zz8::1:300";
+        let ambiguous = "foo.test.com:2001:db8::1:300";
+        let unclosed = "foo.test.com:[2001:db8::1:300";
+        assert!(parse_aaaa(line).is_err());
+        assert!(parse_aaaa(badip).is_err());
+        assert!(parse_aaaa(ambiguous).is_err());
+        assert!(parse_aaaa(unclosed).is_err());
+    }
+
+    #[test]
+    fn test_parse_aaaaptr() {
+        // Test parse_aaaaptr with good data
+        let aaaa = TinyDNSRecord {
+            rtype: "AAAA".to_string(),
+            fqdn:  "foo.test.com".to_string(),
+            target: "2001:db8::1".to_string(),
+            ttl: 300, ..Default::default() };
+        let ptr = TinyDNSRecord {
+            rtype: "PTR".to_string(),
+            fqdn:  "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa".to_string(),
+            target: "foo.test.com".to_string(),
+            ttl: 300, ..Default::default() };
+        let line = "foo.test.com:20010db8000000000000000000000001:300";
+        let parsed = parse_aaaaptr(line).unwrap();
+        assert!(aaaa == parsed[0]);
+        assert!(ptr == parsed[1]);
     }
 
-    // Bring it all together and make sure from_string() can handle the 12
+    #[test]
+    fn test_parse_generic() {
+        // SRV: 10 20 5060 sip.test.com
+        let srv = TinyDNSRecord {
+            rtype: "SRV".to_string(),
+            fqdn:  "_sip._udp.test.com".to_string(),
+            target: "10 20 5060 sip.test.com".to_string(),
+            ttl: 300, ..Default::default() };
+        let line = "_sip._udp.test.com:33:\\000\\012\\000\\024\\023\\304\\003sip\\004test\\003com\\000:300";
+        assert!(vec![srv] == parse_generic(line).unwrap());
+
+        // CAA: 0 issue "letsencrypt.org"
+        let caa = TinyDNSRecord {
+            rtype: "CAA".to_string(),
+            fqdn:  "test.com".to_string(),
+            target: "0 issue \"letsencrypt.org\"".to_string(),
+            ttl: 300, ..Default::default() };
+        let line = "test.com:257:\\000\\005issueletsencrypt.org:300";
+        assert!(vec![caa] == parse_generic(line).unwrap());
+
+        // SSHFP: 1 1 hex
+        let parsed = parse_generic("foo.test.com:44:\\001\\001\\253\\315\\357:300").unwrap();
+        assert_eq!(parsed[0].rtype, "SSHFP");
+        assert_eq!(parsed[0].target, "1 1 ABCDEF");
+
+        // TXT: every string comes out quoted, as Route53 has them
+        let parsed = parse_generic("test.com:16:\\003a\\042b\\003c\\072d:300").unwrap();
+        assert_eq!(parsed[0].rtype, "TXT");
+        assert_eq!(parsed[0].target, "\"a\\\"b\" \"c:d\"");
+    }
+
+    #[test]
+    fn test_bad_parse_generic() {
+        // Unknown types, bad escapes, and short rdata should all fail
+        let unknown = "foo.test.com:99:whatever:300";
+        let badesc = "test.com:257:\\09x:300";
+        let short = "_sip._udp.test.com:33:\\000\\012:300";
+        let notnum = "foo.test.com:SRV:stuff:300";
+        assert!(parse_generic(unknown).is_err());
+        assert!(parse_generic(badesc).is_err());
+        assert!(parse_generic(short).is_err());
+        assert!(parse_generic(notnum).is_err());
+    }
+
+    // Bring it all together and make sure from_string() can handle the 16
     // possible arms of its match{} statement.  Most of this is just repeated
     // code from testing the individual parsing functions only passed to
     // from_string() instead of parse_X.
@@ -861,7 +1836,7 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "+foo.test.com:1.2.3.4:300";
         let parsed = from_string(line).unwrap();
         assert!(a == parsed[0]);
@@ -873,7 +1848,7 @@ mod tests {
             rtype: "PTR".to_string(),
             fqdn:  "4.3.2.1.in-addr.arpa".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "^4.3.2.1.in-addr.arpa:foo.test.com:300";
         let parsed = from_string(line).unwrap();
         assert!(prec == parsed[0]);
@@ -885,7 +1860,7 @@ mod tests {
             rtype: "CNAME".to_string(),
             fqdn:  "bar.test.com".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "Cbar.test.com:foo.test.com:300";
         let parsed = from_string(line).unwrap();
         assert!(crec == parsed[0]);
@@ -897,7 +1872,7 @@ mod tests {
             rtype: "TXT".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "a string of data".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "'foo.test.com:\"a string of data\":300";
         let parsed = from_string(line).unwrap();
         assert!(trec == parsed[0]);
@@ -909,12 +1884,12 @@ mod tests {
             rtype: "MX".to_string(),
             fqdn:  "test.com".to_string(),
             target: "20 foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let a  = TinyDNSRecord {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "@test.com:1.2.3.4:foo.test.com:20:300";
         let parsed = from_string(line).unwrap();
         assert!(mx == parsed[0]);
@@ -927,7 +1902,7 @@ mod tests {
             rtype: "SOA".to_string(),
             fqdn:  "test.com".to_string(),
             target: "foo.test.com person.test.com 1 2 3 4 5".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "Ztest.com:foo.test.com:person.test.com:1:2:3:4:5:300";
         let parsed = from_string(line).unwrap();
         assert!(soa == parsed[0]);
@@ -939,17 +1914,17 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ns  = TinyDNSRecord {
             rtype: "NS".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let soa = TinyDNSRecord {
             rtype: "SOA".to_string(),
             fqdn:  "test.com".to_string(),
             target: "foo.test.com hostmaster.test.com 1 1 1 1 60".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = ".test.com:1.2.3.4:foo.test.com:300";
         let parsed = from_string(line).unwrap();
         assert!(ns == parsed[0]);
@@ -963,12 +1938,12 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ns  = TinyDNSRecord {
             rtype: "NS".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "&test.com:1.2.3.4:foo.test.com:300";
         let parsed = from_string(line).unwrap();
         assert!(ns == parsed[0]);
@@ -981,18 +1956,43 @@ mod tests {
             rtype: "A".to_string(),
             fqdn:  "foo.test.com".to_string(),
             target: "1.2.3.4".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let ptr = TinyDNSRecord {
             rtype: "PTR".to_string(),
             fqdn:  "4.3.2.1.in-addr.arpa".to_string(),
             target: "foo.test.com".to_string(),
-            ttl: 300 };
+            ttl: 300, ..Default::default() };
         let line = "=foo.test.com:1.2.3.4:300";
         let parsed = from_string(line).unwrap();
         assert!(a == parsed[0]);
         assert!(ptr == parsed[1]);
     }
 
+    #[test]
+    fn test_from_string_aaaa() {
+        let line = "3foo.test.com:[2001:db8::1]:300";
+        let parsed = from_string(line).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].rtype, "AAAA");
+        assert_eq!(parsed[0].target, "2001:db8::1");
+    }
+
+    #[test]
+    fn test_from_string_aaaaptr() {
+        let line = "6foo.test.com:20010db8000000000000000000000001:300";
+        let parsed = from_string(line).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].rtype, "PTR");
+        assert!(parsed[1].fqdn.ends_with(".8.b.d.0.1.0.0.2.ip6.arpa"));
+    }
+
+    #[test]
+    fn test_from_string_generic() {
+        let line = ":test.com:257:\\000\\005issueletsencrypt.org:300";
+        let parsed = from_string(line).unwrap();
+        assert_eq!(parsed[0].rtype, "CAA");
+    }
+
     #[test]
     fn test_from_string_comment() {
         let line = "# A comment line";
@@ -1004,10 +2004,152 @@ mod tests {
         assert!(empty == parsed2);
     }
 
+    #[test]
+    fn test_parse_error_details() {
+        // The error should know which field was bad and the original line
+        let line = "+foo.test.com:999.1.1.1:300";
+        let err = from_string(line).unwrap_err();
+        assert_eq!(err.field, Some(2));
+        assert_eq!(err.text, line);
+
+        // Blank lines are fine
+        assert!(from_string("").unwrap().is_empty());
+        assert!(from_string("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_file_collects_errors() {
+        // Every bad line should be reported, with its line number
+        let fname = std::env::temp_dir().join(format!("macrotis-parse-{}.tiny", std::process::id()));
+        let contents = "+good.test.com:1.2.3.4:300\n+bad.test.com:1.2.3:300\n\nXnope\n";
+        std::fs::write(&fname, contents).unwrap();
+        let fname = fname.to_str().unwrap();
+        let errs = from_file(fname).unwrap_err();
+        std::fs::remove_file(fname).unwrap();
+        assert_eq!(errs.len(), 2);
+        assert_eq!(errs[0].file, fname);
+        assert_eq!(errs[0].line, 2);
+        assert_eq!(errs[1].line, 4);
+        assert!(errs[1].reason.contains("unsupported prefix"));
+    }
+
+    #[test]
+    fn test_timestamp_location() {
+        // TAI64 timestamps become unix seconds, and 'lo' is kept
+        let line = "+foo.test.com:1.2.3.4:300:400000005d1d6c8a:in";
+        let parsed = from_string(line).unwrap();
+        assert_eq!(parsed[0].timestamp, Some(1562209408));
+        assert_eq!(parsed[0].location, Some("in".to_string()));
+
+        // Empty timestamp with a location is fine too
+        let line = "=foo.test.com:1.2.3.4:300::ex";
+        let parsed = from_string(line).unwrap();
+        assert_eq!(parsed[1].timestamp, None);
+        assert_eq!(parsed[1].location, Some("ex".to_string()));
+
+        // Bad timestamps and locations should fail
+        assert!(from_string("+foo.test.com:1.2.3.4:300:nothex").is_err());
+        assert!(from_string("+foo.test.com:1.2.3.4:300::toolong").is_err());
+    }
+
+    #[test]
+    fn test_is_active() {
+        // Nonzero TTL: timestamp is a start time.  Zero TTL: an end time.
+        let start = TinyDNSRecord { ttl: 300, timestamp: Some(1000), ..Default::default() };
+        let end = TinyDNSRecord { ttl: 0, timestamp: Some(1000), ..Default::default() };
+        let always = TinyDNSRecord { ttl: 300, ..Default::default() };
+        assert!(!is_active(&start, 999));
+        assert!(is_active(&start, 1000));
+        assert!(is_active(&end, 999));
+        assert!(!is_active(&end, 1000));
+        assert!(is_active(&always, 0));
+    }
+
+    #[test]
+    fn test_parse_location() {
+        // '%' lines can't be pushed to Route53, so they don't parse at all
+        assert!(from_string("%in:10.1").is_err());
+        assert!(from_string("%ex").is_err());
+    }
+
+    #[test]
+    fn test_parse_alias() {
+        let parsed = from_string("~www.test.com:D111.cloudfront.net.").unwrap();
+        assert_eq!(parsed[0].rtype, "A");
+        assert_eq!(parsed[0].target, "d111.cloudfront.net");
+        assert_eq!(parsed[0].ttl, 0);
+        assert_eq!(parsed[0].alias, Some(AliasTarget {
+            hosted_zone_id: "".to_string(),
+            dns_name: "d111.cloudfront.net".to_string(),
+            evaluate_target_health: false
+        }));
+
+        let parsed = from_string("~test.com:lb.elb.amazonaws.com:Z35SXDOTRQ7X7K:aaaa:1::ex").unwrap();
+        assert_eq!(parsed[0].rtype, "AAAA");
+        assert_eq!(parsed[0].location, Some("ex".to_string()));
+        let alias = parsed[0].alias.as_ref().unwrap();
+        assert_eq!(alias.hosted_zone_id, "Z35SXDOTRQ7X7K");
+        assert!(alias.evaluate_target_health);
+
+        assert!(from_string("~test.com").is_err());
+        assert!(from_string("~test.com:foo.test.com::NS").is_err());
+        assert!(from_string("~test.com:foo.test.com::A:yes").is_err());
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let parsed = from_string("!www.test.com:a:Weight:70:hc-1").unwrap();
+        assert_eq!(parsed[0].rtype, "!");
+        assert_eq!(parsed[0].location, Some("a".to_string()));
+        let policy = parsed[0].routing.as_ref().unwrap();
+        assert_eq!(policy.weight, Some(70));
+        assert_eq!(policy.health_check_id, Some("hc-1".to_string()));
+
+        let parsed = from_string("!www.test.com:p:failover:primary").unwrap();
+        assert_eq!(parsed[0].routing.as_ref().unwrap().failover, Some("PRIMARY".to_string()));
+        let parsed = from_string("!www.test.com:us:latency:US-East-1").unwrap();
+        assert_eq!(parsed[0].routing.as_ref().unwrap().region, Some("us-east-1".to_string()));
+        let parsed = from_string("!www.test.com:m:multivalue").unwrap();
+        assert_eq!(parsed[0].routing.as_ref().unwrap().kind(), "multivalue");
+
+        assert!(from_string("!www.test.com:a").is_err());
+        assert!(from_string("!www.test.com:abc:weight:1").is_err());
+        assert!(from_string("!www.test.com:a:weight:256").is_err());
+        assert!(from_string("!www.test.com:a:latency").is_err());
+        assert!(from_string("!www.test.com:a:failover:tertiary").is_err());
+        assert!(from_string("!www.test.com:a:multivalue:1").is_err());
+        assert!(from_string("!www.test.com:a:geo:1").is_err());
+
+        // Only one policy per name and location
+        let recs = from_string("!www.test.com:a:weight:1").unwrap().into_iter()
+            .chain(from_string("!WWW.test.com:a:weight:2").unwrap()).collect();
+        assert!(policies(&recs).is_err());
+    }
+
+    #[test]
+    fn test_mx_without_ip() {
+        let parsed = from_string("@test.com::mx.other.com:10:300").unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].target, "10 mx.other.com");
+    }
+
+    #[test]
+    fn test_encode_rdata() {
+        let (n, data) = encode_rdata("SRV", "10 20 5060 sip.test.com").unwrap();
+        assert_eq!(n, 33);
+        assert_eq!(decode_rdata(n, &data).unwrap(), ("SRV".to_string(), "10 20 5060 sip.test.com".to_string()));
+        let (n, data) = encode_rdata("TXT", "\"v=spf1 -all\" \"two\"").unwrap();
+        assert_eq!(decode_rdata(n, &data).unwrap().1, "\"v=spf1 -all\" \"two\"");
+        let line = format!(":test.com:{}:{}:300", n, escape_octal(&data));
+        assert_eq!(from_string(&line).unwrap()[0].target, "\"v=spf1 -all\" \"two\"");
+        assert!(encode_rdata("TXT", "unquoted").is_err());
+        assert!(encode_rdata("SRV", "10 20 sip.test.com").is_err());
+    }
+
     #[test]
     fn test_from_string_baddata() {
         let line = "2098u983rjgq24gjadjgaNONSENSE";
         let parsed = from_string(line);
-        assert!(parsed == None);
+        assert!(parsed.is_err());
     }
 }
@@ -0,0 +1,609 @@
+// Module for writing Resources and TinyDNSRecords back out as TinyDNS lines,
+// and for putting TinyDNS files into a canonical form
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use resource::{self, Resource, AliasTarget, RoutingPolicy};
+use tinydns::{self, TinyDNSRecord, ParseError};
+
+// A single record we expect a line to turn back into: type, name, value,
+// and TTL, the same way they'd come out of resource::vec_from_tiny.
+type Expected = (String, String, String, i64);
+
+fn expect(rtype: &str, name: &str, value: &str, ttl: i64) -> Expected {
+    (rtype.to_string(), name.to_string(), value.to_string(), ttl)
+}
+
+// Make sure a line parses back into exactly the records we expect, so
+// nothing gets mangled on the way out (and back in).
+fn check_line(line: &str, expected: &Vec<Expected>) -> bool {
+    let parsed = match tinydns::from_string(line) {
+        Ok(x) => x,
+        Err(_) => { return false; }
+    };
+    let mut got: Vec<Expected> = parsed.iter()
+        .map(|t| expect(&t.rtype, &t.fqdn, &resource::record_value(t), t.ttl as i64))
+        .collect();
+    let mut want = expected.clone();
+    got.sort();
+    want.sort();
+    got == want
+}
+
+// Write a single value using the most compact single-record prefix for
+// its type, if there is one.
+fn simple_line(rtype: &str, name: &str, value: &str, ttl: i64) -> Option<String> {
+    let line = match rtype {
+        "A" => format!("+{}:{}:{}", name, value, ttl),
+        "CNAME" => format!("C{}:{}:{}", name, value, ttl),
+        "PTR" => format!("^{}:{}:{}", name, value, ttl),
+        "AAAA" => {
+            let ip = value.parse::<Ipv6Addr>().ok()?;
+            format!("3{}:{:032x}:{}", name, u128::from(ip), ttl)
+        },
+        "TXT" => format!("'{}:\"{}\":{}", name, tinydns::unquote(value)?, ttl),
+        "MX" => {
+            let mut mx = value.split_whitespace();
+            let (dist, host) = (mx.next()?, mx.next()?);
+            format!("@{}::{}:{}:{}", name, host, dist, ttl)
+        },
+        "SOA" => {
+            let f: Vec<&str> = value.split_whitespace().collect();
+            if f.len() != 7 {
+                return None;
+            }
+            format!("Z{}:{}:{}", name, f.join(":"), ttl)
+        },
+        _ => { return None; }
+    };
+    Some(line)
+}
+
+// Write a single value as a generic ':' record
+fn generic_line(rtype: &str, name: &str, value: &str, ttl: i64) -> Option<String> {
+    let (n, rdata) = tinydns::encode_rdata(rtype, value).ok()?;
+    Some(format!(":{}:{}:{}:{}", name, n, tinydns::escape_octal(&rdata), ttl))
+}
+
+// Write a single value, preferring the compact form and falling back to
+// a generic record.  Either way the line has to survive a round trip.
+fn value_line(res: &Resource, value: &str) -> Option<String> {
+    let want = vec![expect(&res.rtype, &res.name, value, res.ttl)];
+    if let Some(line) = simple_line(&res.rtype, &res.name, value, res.ttl) {
+        if check_line(&line, &want) {
+            return Some(line);
+        }
+    }
+    match generic_line(&res.rtype, &res.name, value, res.ttl) {
+        Some(line) => if check_line(&line, &want) { Some(line) } else { None },
+        None => None
+    }
+}
+
+// Write the optional timestamp and location fields that follow a TTL,
+// leaving off any that are empty.
+fn tslo_suffix(timestamp: &Option<u64>, location: &Option<String>) -> String {
+    match (timestamp, location) {
+        (Some(ts), Some(lo)) => format!(":{}:{}", tinydns::tai64_label(*ts), lo),
+        (Some(ts), None) => format!(":{}", tinydns::tai64_label(*ts)),
+        (None, Some(lo)) => format!("::{}", lo),
+        (None, None) => String::new()
+    }
+}
+
+// Write an alias as a '~' line, with the target's zone always spelled out
+// and the health flag only when it's set.  Like any other line it has to
+// parse back into the same alias.
+fn alias_line(rtype: &str, name: &str, alias: &AliasTarget, timestamp: &Option<u64>,
+              location: &Option<String>) -> Option<String> {
+    let health = if alias.evaluate_target_health { "1" } else { "" };
+    let mut line = format!("~{}:{}:{}:{}:{}", name, alias.dns_name, alias.hosted_zone_id, rtype, health);
+    match tslo_suffix(timestamp, location) {
+        ref x if x.is_empty() => { line = line.trim_end_matches(':').to_string(); },
+        x => { line.push_str(&x); }
+    };
+    let parsed = tinydns::from_string(&line).ok()?;
+    match parsed.len() == 1 && parsed[0].rtype == rtype && parsed[0].fqdn == name &&
+          parsed[0].alias.as_ref() == Some(alias) {
+        true => Some(line),
+        false => None
+    }
+}
+
+// Write a single TinyDNSRecord as one canonical line, using the compact
+// prefix for its type where there is one.
+pub fn record_line(rec: &TinyDNSRecord) -> Option<String> {
+    if let Some(alias) = &rec.alias {
+        return alias_line(&rec.rtype, &rec.fqdn, alias, &rec.timestamp, &rec.location);
+    }
+    let value = resource::record_value(rec);
+    let want = vec![expect(&rec.rtype, &rec.fqdn, &value, rec.ttl as i64)];
+    let line = match simple_line(&rec.rtype, &rec.fqdn, &value, rec.ttl as i64) {
+        Some(ref x) if check_line(x, &want) => x.to_string(),
+        _ => match generic_line(&rec.rtype, &rec.fqdn, &value, rec.ttl as i64) {
+            Some(ref x) if check_line(x, &want) => x.to_string(),
+            _ => { return None; }
+        }
+    };
+    Some(format!("{}{}", line, tslo_suffix(&rec.timestamp, &rec.location)))
+}
+
+// The 'lo' code a record set with a routing policy gets written with: its
+// set identifier, if that fits in a 'lo' field.  Located record sets (and
+// anything else) get None.
+fn policy_location(res: &Resource) -> Option<String> {
+    match res.routing_policy() {
+        "weight" | "latency" | "failover" | "multivalue" => {},
+        _ => { return None; }
+    };
+    match &res.set_identifier {
+        Some(x) if !x.is_empty() && x.len() <= 2 && !x.contains(':') => Some(x.to_string()),
+        _ => None
+    }
+}
+
+// Write the '!' line giving a name's records in one location a routing
+// policy.  Like any other line it has to parse back into the same policy.
+fn policy_line(name: &str, lo: &str, policy: &RoutingPolicy) -> Option<String> {
+    let value = match policy.kind() {
+        "weight" => policy.weight?.to_string(),
+        "latency" => policy.region.clone()?,
+        "failover" => policy.failover.clone()?.to_lowercase(),
+        _ => String::new()
+    };
+    let health = policy.health_check_id.clone().unwrap_or_default();
+    let line = format!("!{}:{}:{}:{}:{}", name, lo, policy.kind(), value, health);
+    let line = line.trim_end_matches(':').to_string();
+    let parsed = tinydns::from_string(&line).ok()?;
+    match parsed.len() == 1 && parsed[0].routing.as_ref() == Some(policy) {
+        true => Some(line),
+        false => None
+    }
+}
+
+// Write every value of a Resource out, one line each.  Record sets with a
+// routing policy get their set identifier as the location; the '!' line
+// that goes with them is left to the caller.
+pub fn resource_lines(res: &Resource) -> Option<Vec<String>> {
+    let lo = policy_location(res);
+    if let Some(alias) = &res.alias_target {
+        return Some(vec![alias_line(&res.rtype, &res.name, alias, &None, &lo)?]);
+    }
+    let mut lines = Vec::new();
+    for value in &res.records {
+        lines.push(format!("{}{}", value_line(res, value)?, tslo_suffix(&None, &lo)));
+    }
+    Some(lines)
+}
+
+// Build the in-addr.arpa name for an IPv4 address
+fn ip4_arpa(ip: &Ipv4Addr) -> String {
+    let o = ip.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+}
+
+// Turn a pile of Resources (from any number of zones) into TinyDNS lines,
+// grouped by zone_id.  Matching A/PTR pairs become '=' lines and MX records
+// whose host has an A record become '@' lines with glue; everything else
+// is written one value per line.  Output is sorted so the same records
+// always give the same files.  Record sets with a routing policy are
+// written with a '!' line for each name and location.  Also returns the
+// Resources that were written, since some (located records, types we
+// can't express) have to be skipped.
+pub fn export(resources: &Vec<Resource>) -> (HashMap<String, Vec<String>>, Vec<Resource>) {
+    let mut sorted: Vec<&Resource> = resources.iter().collect();
+    sorted.sort_by(|a, b| (&a.name, &a.rtype, &a.set_identifier).cmp(&(&b.name, &b.rtype, &b.set_identifier)));
+
+    // Single-valued, unrouted A records by name, for pairing up
+    let mut a_single: HashMap<&str, usize> = HashMap::new();
+    for (i, res) in sorted.iter().enumerate() {
+        if res.rtype == "A" && res.records.len() == 1 && res.set_identifier.is_none() {
+            a_single.insert(&res.name, i);
+        }
+    }
+
+    // Pre-written lines for resources handled as part of a pair, and the
+    // resources whose records are covered by somebody else's lines.
+    let mut lines_for: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut consumed: HashSet<usize> = HashSet::new();
+
+    // '=' lines: a PTR whose value is an A record's name, and whose name is
+    // that A record's reversed IP.
+    for (i, res) in sorted.iter().enumerate() {
+        if res.rtype != "PTR" || res.records.len() != 1 || res.set_identifier.is_some() {
+            continue;
+        }
+        let ai = match a_single.get(res.records[0].as_str()) {
+            Some(x) => *x,
+            None => { continue; }
+        };
+        let a = sorted[ai];
+        let ip = match a.records[0].parse::<Ipv4Addr>() {
+            Ok(x) => x,
+            Err(_) => { continue; }
+        };
+        if consumed.contains(&ai) || ip4_arpa(&ip) != res.name || a.ttl != res.ttl {
+            continue;
+        }
+        let line = format!("={}:{}:{}", a.name, ip, a.ttl);
+        let want = vec![expect("A", &a.name, &a.records[0], a.ttl),
+                        expect("PTR", &res.name, &res.records[0], res.ttl)];
+        if check_line(&line, &want) {
+            lines_for.insert(ai, vec![line]);
+            consumed.insert(ai);
+            consumed.insert(i);
+        }
+    }
+
+    // '@' lines: an MX value whose host has its own A record with the same
+    // TTL gets written with that A record as glue.
+    for (i, res) in sorted.iter().enumerate() {
+        if res.rtype != "MX" || res.set_identifier.is_some() {
+            continue;
+        }
+        let mut lines = Vec::new();
+        let mut glue = Vec::new();
+        for value in &res.records {
+            let mut mx = value.split_whitespace();
+            let (dist, host) = match (mx.next(), mx.next()) {
+                (Some(d), Some(h)) => (d, h),
+                _ => { break; }
+            };
+            let ai = match a_single.get(host) {
+                Some(x) if !consumed.contains(x) && !glue.contains(x) => *x,
+                _ => { continue; }
+            };
+            let a = sorted[ai];
+            if a.ttl != res.ttl || !host.contains('.') {
+                continue;
+            }
+            let line = format!("@{}:{}:{}:{}:{}", res.name, a.records[0], host, dist, res.ttl);
+            let want = vec![expect("MX", &res.name, value, res.ttl),
+                            expect("A", &a.name, &a.records[0], a.ttl)];
+            if check_line(&line, &want) {
+                lines.push((value.to_string(), line));
+                glue.push(ai);
+            }
+        }
+        if lines.is_empty() {
+            continue;
+        }
+
+        // Any values that didn't get glue are written the usual way; if one
+        // of those can't be, leave the whole thing for the main pass below.
+        let mut all = Vec::new();
+        let mut ok = true;
+        for value in &res.records {
+            match lines.iter().find(|x| &x.0 == value) {
+                Some(x) => { all.push(x.1.to_string()); },
+                None => match value_line(res, value) {
+                    Some(x) => { all.push(x); },
+                    None => { ok = false; }
+                }
+            };
+        }
+        if ok {
+            lines_for.insert(i, all);
+            for ai in glue {
+                consumed.insert(ai);
+            }
+        }
+    }
+
+    // Now go through everything in order and write it out.  A '!' line
+    // covers every type at its name and location, so types there with
+    // different policies can't all be written.
+    let mut by_zone: HashMap<String, Vec<String>> = HashMap::new();
+    let mut exported = Vec::new();
+    let mut policies: HashMap<(String, String), RoutingPolicy> = HashMap::new();
+    for (i, res) in sorted.iter().enumerate() {
+        let lines = match lines_for.remove(&i) {
+            Some(x) => x,
+            None if consumed.contains(&i) => Vec::new(),
+            None => {
+                let lo = policy_location(res);
+                if let (Some(x), None) = (&res.set_identifier, &lo) {
+                    println!("Skipping {} {} ({}): located records can't be exported yet", res.rtype, res.name, x);
+                    continue;
+                }
+                let mut lines = match resource_lines(res) {
+                    Some(x) => x,
+                    None => {
+                        println!("Skipping {} {}: can't be written as TinyDNS", res.rtype, res.name);
+                        continue;
+                    }
+                };
+                if let Some(lo) = lo {
+                    let key = (res.name.to_lowercase(), lo.to_string());
+                    match policies.get(&key) {
+                        Some(x) if *x != res.routing() => {
+                            println!("Skipping {} {} ({}): its routing policy differs from another type's",
+                                     res.rtype, res.name, lo);
+                            continue;
+                        },
+                        Some(_) => {},
+                        None => match policy_line(&res.name, &lo, &res.routing()) {
+                            Some(x) => {
+                                lines.insert(0, x);
+                                policies.insert(key, res.routing());
+                            },
+                            None => {
+                                println!("Skipping {} {} ({}): can't write its routing policy",
+                                         res.rtype, res.name, lo);
+                                continue;
+                            }
+                        }
+                    };
+                }
+                lines
+            }
+        };
+        by_zone.entry(res.zone_id.to_string()).or_insert(vec![]).extend(lines);
+        exported.push((*res).clone());
+    }
+    (by_zone, exported)
+}
+
+// Which fields of a line hold domain names (to be lowercased) and which
+// one holds the TTL, by prefix.  Fields are counted after the prefix,
+// starting from 0.  Aliases and routing policies have no TTL, so their
+// type or policy is the field that always gets written out instead.
+fn line_fields(prefix: char) -> Option<(Vec<usize>, usize)> {
+    match prefix {
+        '+' | '=' | '3' | '6' | '\'' => Some((vec![0], 2)),
+        '^' | 'C' => Some((vec![0, 1], 2)),
+        '@' => Some((vec![0, 2], 4)),
+        '.' | '&' => Some((vec![0, 2], 3)),
+        'Z' => Some((vec![0, 1, 2], 8)),
+        ':' => Some((vec![0], 3)),
+        '~' => Some((vec![0, 1], 3)),
+        '!' => Some((vec![0], 2)),
+        _ => None
+    }
+}
+
+// What a parsed record boils down to for deciding whether two lines say
+// the same thing, ignoring the case of names.
+type LineKey = (String, String, String, i32, Option<u64>, Option<String>, Option<AliasTarget>, Option<RoutingPolicy>);
+
+fn line_key(rec: &TinyDNSRecord) -> LineKey {
+    let target = match rec.rtype.as_str() {
+        "TXT" => rec.target.to_string(),
+        _ => rec.target.to_lowercase()
+    };
+    (rec.rtype.to_string(), rec.fqdn.to_lowercase(), target, rec.ttl,
+     rec.timestamp, rec.location.clone(), rec.alias.clone(), rec.routing.clone())
+}
+
+// Rewrite a single line in canonical form: names lowercased, the TTL
+// always written out, TXT data in plain double-quotes, generic rdata
+// escaped consistently, and no trailing empty fields.  Comments and
+// blank lines come back as-is (minus trailing whitespace).  If the
+// rewritten line wouldn't parse into the same records the original is
+// returned unchanged.
+pub fn format_line(line: &str) -> Result<String, ParseError> {
+    let line = line.trim_end();
+    let recs = tinydns::from_string(line)?;
+    let prefix = match line.chars().next() {
+        Some(x) => x,
+        None => { return Ok(String::new()); }
+    };
+    let (names, ttl_field) = match line_fields(prefix) {
+        Some(x) if !recs.is_empty() => x,
+        _ => { return Ok(line.to_string()); }
+    };
+
+    let mut fields: Vec<String> = line[prefix.len_utf8()..].split(':').map(|x| x.to_string()).collect();
+
+    // TXT data can contain colons, so glue it back together first
+    if prefix == '\'' {
+        while fields.len() > 2 && !(fields[1].len() > 1 && fields[1].ends_with('"')) {
+            let next = fields.remove(2);
+            fields[1] = format!("{}:{}", fields[1], next);
+        }
+        fields[1] = format!("\"{}\"", recs[0].target);
+    }
+    if prefix == ':' && fields.len() > 2 {
+        if let Ok(rdata) = tinydns::unescape_octal(&fields[2]) {
+            fields[2] = tinydns::escape_octal(&rdata);
+        }
+    }
+    for i in names {
+        if i < fields.len() {
+            fields[i] = fields[i].to_lowercase();
+        }
+    }
+
+    // Missing fields before the TTL are filled in empty, which the parser
+    // treats the same as leaving them off.
+    while fields.len() <= ttl_field {
+        fields.push(String::new());
+    }
+    fields[ttl_field] = match prefix {
+        '~' => recs[0].rtype.to_string(),
+        '!' => recs[0].routing.as_ref().map(|x| x.kind()).unwrap_or("").to_string(),
+        _ => recs[0].ttl.to_string()
+    };
+    while fields.len() > ttl_field + 1 && fields[fields.len() - 1].is_empty() {
+        fields.pop();
+    }
+
+    let formatted = format!("{}{}", prefix, fields.join(":"));
+    let same = match tinydns::from_string(&formatted) {
+        Ok(x) => {
+            let mut a: Vec<_> = recs.iter().map(line_key).collect();
+            let mut b: Vec<_> = x.iter().map(line_key).collect();
+            a.sort();
+            b.sort();
+            a == b
+        },
+        Err(_) => false
+    };
+    match same {
+        true => Ok(formatted),
+        false => Ok(line.to_string())
+    }
+}
+
+// Sort order for formatted lines: SOA and NS records first, then
+// everything else, each by name.
+fn line_order(line: &str) -> (u8, String, String) {
+    let rank = match line.chars().next() {
+        Some('Z') | Some('.') | Some('&') => 0,
+        _ => 1
+    };
+    let name = line.get(1..).unwrap_or("").split(':').next().unwrap_or("").to_lowercase();
+    (rank, name, line.to_string())
+}
+
+// Format the contents of a whole TinyDNS file.  Every record line is
+// run through format_line and the records are sorted.  Comments directly
+// above a record move with it; comments at the top of the file that are
+// set apart by a blank line stay put, as do any left at the very end.
+// Returns every ParseError in the file (with line numbers) on failure.
+pub fn format_text(text: &str) -> Result<String, Vec<ParseError>> {
+    let mut header: Vec<String> = Vec::new();
+    let mut pending: Vec<String> = Vec::new();
+    let mut blocks: Vec<(Vec<String>, String)> = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            // Comments above the first record but set apart from it belong
+            // to the file rather than to that record
+            if blocks.is_empty() && !pending.is_empty() {
+                header.append(&mut pending);
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            pending.push(line.to_string());
+            continue;
+        }
+        match format_line(line) {
+            Ok(x) => {
+                blocks.push((pending, x));
+                pending = Vec::new();
+            },
+            Err(mut e) => {
+                e.line = i + 1;
+                errors.push(e);
+            }
+        };
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    blocks.sort_by(|a, b| line_order(&a.1).cmp(&line_order(&b.1)));
+
+    // Put it all back together, with a blank line ahead of each comment
+    // so they stay easy to spot
+    let mut out = Vec::new();
+    if !header.is_empty() {
+        out.append(&mut header);
+        out.push(String::new());
+    }
+    for (mut comments, line) in blocks {
+        if !comments.is_empty() && !out.is_empty() && out[out.len() - 1] != "" {
+            out.push(String::new());
+        }
+        out.append(&mut comments);
+        out.push(line);
+    }
+    if !pending.is_empty() {
+        if !out.is_empty() {
+            out.push(String::new());
+        }
+        out.append(&mut pending);
+    }
+    let mut text = out.join("\n");
+    text.push('\n');
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_line() {
+        let rec = tinydns::from_string("'Test.com:\"a:b\":600:4000000060000000:ex").unwrap().remove(0);
+        let line = record_line(&rec).unwrap();
+        assert_eq!(line, "'Test.com:\"a:b\":600:4000000060000000:ex");
+        let rec = tinydns::from_string(":test.com:33:\\000\\001\\000\\002\\023\\304\\003sip\\004test\\003com\\000").unwrap().remove(0);
+        assert_eq!(record_line(&rec).unwrap(), ":test.com:33:\\000\\001\\000\\002\\023\\304\\003sip\\004test\\003com\\000:300");
+    }
+
+    #[test]
+    fn test_alias_lines() {
+        let rec = tinydns::from_string("~WWW.test.com:d111.CloudFront.net.:Z2FDTNDATAQYW2:aaaa:1").unwrap().remove(0);
+        assert_eq!(record_line(&rec).unwrap(), "~WWW.test.com:d111.cloudfront.net:Z2FDTNDATAQYW2:AAAA:1");
+        let res = Resource {
+            zone_id: "Z1".to_string(),
+            name: "test.com".to_string(),
+            rtype: "A".to_string(),
+            alias_target: Some(AliasTarget {
+                hosted_zone_id: "Z35SXDOTRQ7X7K".to_string(),
+                dns_name: "lb-1.us-east-1.elb.amazonaws.com".to_string(),
+                evaluate_target_health: false
+            }),
+            ..Default::default()
+        };
+        assert_eq!(resource_lines(&res).unwrap(), vec!["~test.com:lb-1.us-east-1.elb.amazonaws.com:Z35SXDOTRQ7X7K:A"]);
+        assert_eq!(format_line("~Foo.test.com:Bar.test.com").unwrap(), "~foo.test.com:bar.test.com::A");
+        assert_eq!(format_line("~foo.test.com:bar.test.com::txt:0:4000000060000000").unwrap(),
+                   "~foo.test.com:bar.test.com::TXT:0:4000000060000000");
+    }
+
+    #[test]
+    fn test_policy_lines() {
+        let res = Resource {
+            zone_id: "Z1".to_string(),
+            name: "www.test.com".to_string(),
+            rtype: "A".to_string(),
+            records: vec!["1.2.3.4".to_string()],
+            ttl: 60,
+            set_identifier: Some("a".to_string()),
+            failover: Some("PRIMARY".to_string()),
+            health_check_id: Some("hc-1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(resource_lines(&res).unwrap(), vec!["+www.test.com:1.2.3.4:60::a"]);
+        assert_eq!(policy_line(&res.name, "a", &res.routing()).unwrap(), "!www.test.com:a:failover:primary:hc-1");
+
+        // Both types share the '!' line
+        let aaaa = Resource { rtype: "AAAA".to_string(), records: vec!["::1".to_string()], ..res.clone() };
+        let (by_zone, exported) = export(&vec![res.clone(), aaaa]);
+        assert_eq!(exported.len(), 2);
+        assert_eq!(by_zone["Z1"].iter().filter(|x| x.starts_with('!')).count(), 1);
+
+        // Set identifiers too long for a 'lo' field, and located sets, are skipped
+        let long = Resource { set_identifier: Some("primary".to_string()), ..res.clone() };
+        let geo = Resource { failover: None, geolocation: Some(Default::default()), ..res.clone() };
+        assert!(export(&vec![long, geo]).1.is_empty());
+
+        assert_eq!(format_line("!WWW.test.com:a:WEIGHT:10:").unwrap(), "!www.test.com:a:weight:10");
+    }
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(format_line("+Foo.Test.com:1.2.3.4").unwrap(), "+foo.test.com:1.2.3.4:300");
+        assert_eq!(format_line("CWWW.test.com:Foo.test.com:0600:::  ").unwrap(), "Cwww.test.com:foo.test.com:600");
+        assert_eq!(format_line("@test.com::MX.test.com").unwrap(), "@test.com::mx.test.com::300");
+        assert_eq!(format_line("'test.com:\"v=spf1 a:b\":60::ex").unwrap(), "'test.com:\"v=spf1 a:b\":60::ex");
+        assert_eq!(format_line(":test.com:16:\\003abc").unwrap(), ":test.com:16:\\003abc:300");
+        assert_eq!(format_line("# Some Comment").unwrap(), "# Some Comment");
+        assert!(format_line("+foo.test.com:1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_format_text() {
+        let text = "# Header\n\n# bar is important\n+bar.test.com:1.2.3.5\n+Foo.test.com:1.2.3.4:300\n\n\nZtest.com:ns.test.com:hostmaster.test.com:1\n# trailing\n";
+        let want = "# Header\n\nZtest.com:ns.test.com:hostmaster.test.com:1:::::300\n\n# bar is important\n+bar.test.com:1.2.3.5:300\n+foo.test.com:1.2.3.4:300\n\n# trailing\n";
+        assert_eq!(format_text(text).unwrap(), want);
+        assert_eq!(format_text(want).unwrap(), want);
+        let errs = format_text("+foo.test.com:1.2.3.4\n+bad\n").unwrap_err();
+        assert_eq!(errs[0].line, 2);
+    }
+}
@@ -0,0 +1,271 @@
+// Module for the hosted zones themselves, rather than the records in them.
+// Zones in the config can be given by domain alone, in which case their
+// IDs are looked up by name, and zones that don't exist yet can be created
+// (or deleted again once they're empty).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use Zone;
+use resource::ResHash;
+use provider::DnsProvider;
+
+// A hosted zone as the provider lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteZone {
+    pub id: String,
+    pub private: bool
+}
+
+impl Zone {
+    // Zones served to VPCs are private, everything else is public
+    pub fn is_private(&self) -> bool {
+        self.vpcs.as_ref().map_or(false, |x| !x.is_empty())
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.is_private() { "private" } else { "public" }
+    }
+}
+
+// Look up the ID of a zone that wasn't given one.  If there's no such zone
+// and the config allows creating it, it's created when 'create' is set and
+// otherwise reported as one to create, keeping an empty ID so it's treated
+// as having nothing in it.  Returns false if the zone can't be worked out.
+pub fn resolve_zone(prov: &dyn DnsProvider, zone: &mut Zone, create: bool) -> bool {
+    if !zone.id.is_empty() {
+        return true;
+    }
+    if zone.is_private() && zone.delegation_set_id.is_some() {
+        println!("Error: zone {}: reusable delegation sets are only for public zones", zone.name);
+        return false;
+    }
+    let found = match prov.find_zones(&zone.domain) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Error looking up hosted zone for {}: {}", zone.domain, e);
+            return false;
+        }
+    };
+    let found: Vec<RemoteZone> = found.into_iter().filter(|x| x.private == zone.is_private()).collect();
+    match found.len() {
+        1 => {
+            println!("Found {} hosted zone {} for {}", zone.kind(), found[0].id, zone.domain);
+            zone.id = found[0].id.to_string();
+            true
+        },
+        0 if zone.create != Some(true) => {
+            println!("Error: no {} hosted zone for {}; give its id, or set create to have one made",
+                     zone.kind(), zone.domain);
+            false
+        },
+        0 if create => {
+            match create_zone(prov, zone) {
+                Ok(id) => {
+                    zone.id = id;
+                    true
+                },
+                Err(e) => {
+                    println!("Error creating hosted zone for {}: {}", zone.domain, e);
+                    false
+                }
+            }
+        },
+        0 => {
+            println!("[ADD] {} hosted zone {}", zone.kind(), zone.domain);
+            true
+        },
+        n => {
+            println!("Error: {} {} hosted zones for {}; give the id of the one to use",
+                     n, zone.kind(), zone.domain);
+            false
+        }
+    }
+}
+
+// Look up every zone that needs it; see resolve_zone
+pub fn resolve(prov: &dyn DnsProvider, zones: &mut Vec<Zone>, create: bool) -> bool {
+    let mut retval = true;
+    for z in zones.iter_mut() {
+        retval &= resolve_zone(prov, z, create);
+    }
+    retval
+}
+
+// Zones that are still to be created
+pub fn pending(zones: &Vec<Zone>) -> Vec<&Zone> {
+    zones.iter().filter(|z| z.id.is_empty()).collect()
+}
+
+// Records are matched to zones by domain alone, so two zones with the same
+// domain (a public and a private one, say) can't both be managed: every
+// record would land in whichever came first.  Prints any domain that's
+// declared more than once and returns false.
+pub fn check_domains(zones: &Vec<Zone>) -> bool {
+    let mut retval = true;
+    let mut seen: Vec<(String, &str)> = Vec::new();
+    for z in zones {
+        let domain = z.domain.trim_end_matches('.').to_lowercase();
+        match seen.iter().find(|x| x.0 == domain) {
+            Some(x) => {
+                println!("Error: zones {} and {} are both for {}; a domain can only be declared once",
+                         x.1, z.name, domain);
+                retval = false;
+            },
+            None => { seen.push((domain, &z.name)); }
+        };
+    }
+    retval
+}
+
+// Route53 wants a caller reference it's never seen before for every zone
+// created, and allows 128 characters of it
+fn caller_reference(domain: &str) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_nanos()).unwrap_or_default();
+    let short: String = domain.chars().take(64).collect();
+    format!("macrotis-{}-{}", short, now)
+}
+
+fn create_zone(prov: &dyn DnsProvider, zone: &Zone) -> Result<String, String> {
+    let id = prov.create_zone(zone, &caller_reference(&zone.domain))?;
+    println!("Created {} hosted zone {} for {}", zone.kind(), id, zone.domain);
+    print_nameservers(prov, zone, &id);
+    Ok(id)
+}
+
+// Print the nameservers a public zone is served from, which the registrar
+// needs to delegate the domain to it
+pub fn print_nameservers(prov: &dyn DnsProvider, zone: &Zone, id: &str) -> bool {
+    match prov.zone_nameservers(&id) {
+        Ok(ref x) if x.is_empty() => {
+            println!("{} ({}) is private, there's nothing to delegate", zone.domain, id);
+            true
+        },
+        Ok(x) => {
+            println!("Nameservers for {} ({}), to give the registrar:", zone.domain, id);
+            for ns in x {
+                println!("  {}", ns);
+            }
+            true
+        },
+        Err(e) => {
+            println!("Error getting nameservers for {} ({}): {}", zone.domain, id, e);
+            false
+        }
+    }
+}
+
+// Delete a hosted zone.  Refuses while the statefile still has records in
+// it, or while it has any records besides the SOA and NS records it was
+// created with.
+pub fn delete_zone(prov: &dyn DnsProvider, zone: &Zone, state: &ResHash) -> bool {
+    if zone.id.is_empty() {
+        println!("There's no hosted zone for {} to delete", zone.domain);
+        return false;
+    }
+    let managed = state.0.values().filter(|r| r.zone_id == zone.id).count();
+    if managed > 0 {
+        println!("Error: the statefile still has {} resource(s) in {}; remove them from the input and run execute first",
+                 managed, zone.domain);
+        return false;
+    }
+    let recs = match prov.fetch_zone(&zone.id) {
+        Some(x) => x,
+        None => {
+            println!("Error fetching zone {}", zone.domain);
+            return false;
+        }
+    };
+    let apex = zone.domain.trim_end_matches('.').to_lowercase();
+    let others = recs.iter().filter(|r| {
+        !(r.name.trim_end_matches('.').to_lowercase() == apex && (r.rtype == "SOA" || r.rtype == "NS"))
+    }).count();
+    if others > 0 {
+        println!("Error: {} still has {} other resource(s) in it", zone.domain, others);
+        return false;
+    }
+    match prov.delete_zone(&zone.id) {
+        Ok(_) => {
+            println!("Deleted hosted zone {} for {}; remove it from the config too", zone.id, zone.domain);
+            true
+        },
+        Err(e) => {
+            println!("Error deleting hosted zone {}: {}", zone.id, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use mock::MockProvider;
+    use resource::Resource;
+    use ZoneVpc;
+
+    fn zone(domain: &str, create: bool) -> Zone {
+        Zone {
+            name: domain.to_string(),
+            domain: domain.to_string(),
+            id: String::new(),
+            create: Some(create),
+            vpcs: None,
+            delegation_set_id: None,
+            ownership: None
+        }
+    }
+
+    #[test]
+    fn test_resolve_zone() {
+        let prov = MockProvider::from_zones(HashMap::new());
+
+        // Missing zones are only made when asked to
+        let mut z = zone("test.com", false);
+        assert!(!resolve_zone(&prov, &mut z, true));
+        let mut z = zone("test.com", true);
+        assert!(resolve_zone(&prov, &mut z, false));
+        assert!(z.id.is_empty());
+        assert!(resolve_zone(&prov, &mut z, true));
+        assert!(!z.id.is_empty());
+        assert_eq!(prov.zone_nameservers(&z.id).unwrap().len(), 2);
+
+        // And found again by name afterwards
+        let mut again = zone("TEST.com", false);
+        assert!(resolve_zone(&prov, &mut again, false));
+        assert_eq!(again.id, z.id);
+
+        // A private zone of the same name is a different zone
+        let mut private = zone("test.com", true);
+        private.vpcs = Some(vec![ZoneVpc { vpc_id: "vpc-1".to_string(), region: "us-west-2".to_string() }]);
+        assert!(resolve_zone(&prov, &mut private, true));
+        assert!(private.id != z.id);
+        assert!(prov.zone_nameservers(&private.id).unwrap().is_empty());
+        private.id = String::new();
+        private.delegation_set_id = Some("N1".to_string());
+        assert!(!resolve_zone(&prov, &mut private, true));
+    }
+
+    #[test]
+    fn test_check_domains() {
+        let mut zones = vec![zone("test.com", false), zone("example.com", false)];
+        assert!(check_domains(&zones));
+        zones.push(zone("Test.com.", false));
+        assert!(!check_domains(&zones));
+    }
+
+    #[test]
+    fn test_delete_zone() {
+        let prov = MockProvider::from_zones(HashMap::new());
+        let mut z = zone("test.com", true);
+        assert!(resolve_zone(&prov, &mut z, true));
+        let mut st = ResHash(HashMap::new());
+        st.0.insert("x".to_string(), Resource {
+            zone_id: z.id.to_string(),
+            name: "www.test.com".to_string(),
+            rtype: "A".to_string(),
+            ..Default::default()
+        });
+        assert!(!delete_zone(&prov, &z, &st));
+        assert!(delete_zone(&prov, &z, &ResHash(HashMap::new())));
+        assert!(prov.find_zones("test.com").unwrap().is_empty());
+    }
+}
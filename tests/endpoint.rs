@@ -0,0 +1,68 @@
+// End-to-end tests against a local Route53 stand-in (a moto server, say)
+// instead of the mock provider, so the real client and protocol get used.
+// Skipped unless MACROTIS_TEST_ENDPOINT is set to the stand-in's URL and
+// MACROTIS_TEST_ZONE_ID to a hosted zone created there for test.com.  The
+// usual AWS credential variables need to be set too, to anything.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// Scratch directory with a config file pointing at the stand-in, or None
+// if there isn't one to talk to
+fn setup(name: &str) -> Option<PathBuf> {
+    let (endpoint, zone_id) = match (std::env::var("MACROTIS_TEST_ENDPOINT"), std::env::var("MACROTIS_TEST_ZONE_ID")) {
+        (Ok(e), Ok(z)) => (e, z),
+        _ => {
+            println!("MACROTIS_TEST_ENDPOINT or MACROTIS_TEST_ZONE_ID unset, skipping");
+            return None;
+        }
+    };
+    let dir = std::env::temp_dir().join(format!("macrotis-endpoint-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let conf = format!(r#"{{
+        "provider": {{
+            "name": "route53",
+            "region": "us-east-1",
+            "assume_role": false,
+            "endpoint": "{}"
+        }},
+        "statefile": {{
+            "backend": "local",
+            "filename": "{}"
+        }},
+        "zones": [ {{ "name": "test", "domain": "test.com", "id": "{}" }} ]
+    }}"#, endpoint, dir.join("state.json").display(), zone_id);
+    fs::write(dir.join("macrotis.conf"), conf).unwrap();
+    Some(dir)
+}
+
+fn run(dir: &Path, sub: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_macrotis"))
+        .arg("-i").arg(dir.join("test.tiny"))
+        .arg("-c").arg(dir.join("macrotis.conf"))
+        .arg(sub)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_execute_against_endpoint() {
+    let dir = match setup("execute") {
+        Some(x) => x,
+        None => { return; }
+    };
+    fs::write(dir.join("test.tiny"), "+endpoint.test.com:1.2.3.4:300\n").unwrap();
+    let out = run(&dir, "execute");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+
+    // The record should now be there, and nothing left to do
+    let out = run(&dir, "noop");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+
+    // Clean up after ourselves
+    fs::write(dir.join("test.tiny"), "").unwrap();
+    assert!(run(&dir, "execute").status.success());
+}
@@ -0,0 +1,607 @@
+// End-to-end tests that drive the macrotis binary against the mock provider
+// and mock state backend, using a JSON fixture as the fake remote.
+extern crate serde_json;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use serde_json::Value;
+
+// Set up a scratch directory holding a copy of the remote fixture plus a
+// config file pointing the mock provider and mock statefile at it.
+fn setup(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("macrotis-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    fs::copy(fixtures.join("mock_remote.json"), dir.join("remote.json")).unwrap();
+    fs::copy(fixtures.join("mock.tiny"), dir.join("mock.tiny")).unwrap();
+    let conf = format!(r#"{{
+        "provider": {{
+            "name": "mock",
+            "assume_role": false,
+            "fixture": "{}"
+        }},
+        "statefile": {{
+            "backend": "mock",
+            "filename": "{}"
+        }},
+        "zones": [ {{ "name": "test", "domain": "test.com", "id": "ZMOCK" }} ]
+    }}"#, dir.join("remote.json").display(), dir.join("state.json").display());
+    fs::write(dir.join("macrotis.conf"), conf).unwrap();
+    dir
+}
+
+fn run(dir: &Path, sub: &str) -> Output {
+    run_input(dir, &dir.join("mock.tiny"), &[sub])
+}
+
+fn run_input(dir: &Path, input: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_macrotis"))
+        .arg("-i").arg(input)
+        .arg("-c").arg(dir.join("macrotis.conf"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Pull the records for a given type and name out of the remote fixture
+fn remote_records(dir: &Path, rtype: &str, name: &str) -> Option<Vec<String>> {
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    let zones: Value = serde_json::from_str(&text).unwrap();
+    for res in zones["ZMOCK"].as_array().unwrap() {
+        if res["rtype"] == rtype && res["name"] == name {
+            let recs = res["records"].as_array().unwrap();
+            return Some(recs.iter().map(|r| r.as_str().unwrap().to_string()).collect());
+        }
+    }
+    None
+}
+
+#[test]
+fn test_noop_reports_changes() {
+    let dir = setup("noop");
+    let out = run(&dir, "noop");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("[ADD] A bar.test.com"));
+    assert!(stdout.contains("[UPD] A foo.test.com"));
+    assert!(!stdout.contains("CNAME www.test.com"));
+
+    // Nothing should have been pushed or saved
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["9.9.9.9".to_string()]));
+    assert!(!dir.join("state.json").exists());
+}
+
+#[test]
+fn test_execute_pushes_and_saves() {
+    let dir = setup("execute");
+    let out = run(&dir, "execute");
+    assert!(out.status.success());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.4".to_string()]));
+    assert_eq!(remote_records(&dir, "A", "bar.test.com"), Some(vec!["1.2.3.5".to_string()]));
+    assert!(dir.join("state.json").exists());
+
+    // A second run should find nothing left to do
+    let out = run(&dir, "noop");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("No changes detected."));
+}
+
+#[test]
+fn test_unknown_provider_fails() {
+    // A typo mustn't fall back to the live Route53 provider
+    let dir = setup("badprovider");
+    set_config(&dir, "provider", serde_json::json!({ "name": "mokc", "assume_role": false }));
+    let out = run(&dir, "execute");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Unknown provider name 'mokc'"));
+}
+
+#[test]
+fn test_execute_deletes_removed_records() {
+    let dir = setup("delete");
+    assert!(run(&dir, "execute").status.success());
+
+    // Drop bar from the local file and make sure it's removed remotely
+    fs::write(dir.join("mock.tiny"), "+foo.test.com:1.2.3.4:300\nCwww.test.com:foo.test.com:600\n").unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("[DEL] A bar.test.com"));
+    assert_eq!(remote_records(&dir, "A", "bar.test.com"), None);
+}
+
+#[test]
+fn test_import_matches_remote() {
+    let dir = setup("import");
+    let out = run_input(&dir, &dir.join("imported"), &["import"]);
+    assert!(out.status.success());
+    let tiny = fs::read_to_string(dir.join("imported/test.tiny")).unwrap();
+    assert!(tiny.contains("+foo.test.com:9.9.9.9:300"));
+    assert!(tiny.contains("Cwww.test.com:foo.test.com:600"));
+
+    // The imported files and statefile should agree with remote
+    let out = run_input(&dir, &dir.join("imported"), &["noop"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("No changes detected."));
+
+    // And a second import shouldn't clobber the first
+    assert!(!run_input(&dir, &dir.join("imported"), &["import"]).status.success());
+}
+
+#[test]
+fn test_apply_saved_plan() {
+    let dir = setup("plan");
+    let plan = dir.join("plan.json");
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["noop", "--out", plan.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert!(plan.exists());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["9.9.9.9".to_string()]));
+
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["apply", plan.to_str().unwrap()]);
+    assert!(out.status.success());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.4".to_string()]));
+    assert_eq!(remote_records(&dir, "A", "bar.test.com"), Some(vec!["1.2.3.5".to_string()]));
+    assert!(dir.join("state.json").exists());
+
+    // Once applied the state has moved on, so the same plan is stale
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["apply", plan.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(!out.status.success());
+    assert!(stdout.contains("Plan is stale"));
+}
+
+#[test]
+fn test_apply_refuses_remote_change() {
+    let dir = setup("planremote");
+    let plan = dir.join("plan.json");
+    assert!(run_input(&dir, &dir.join("mock.tiny"), &["noop", "-o", plan.to_str().unwrap()]).status.success());
+
+    // Somebody else changes the remote in the meantime
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    fs::write(dir.join("remote.json"), text.replace("9.9.9.9", "8.8.8.8")).unwrap();
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["apply", plan.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(!out.status.success());
+    assert!(stdout.contains("remote records have changed"));
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["8.8.8.8".to_string()]));
+}
+
+#[test]
+fn test_old_txt_state() {
+    // Statefiles from before TXT values were quoted shouldn't make every
+    // TXT record look like it was changed behind Macrotis's back
+    let dir = setup("oldtxt");
+    fs::write(dir.join("mock.tiny"),
+              "+foo.test.com:9.9.9.9:300\nCwww.test.com:foo.test.com:600\n'test.com:\"v=spf1 -all\":300\n").unwrap();
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    let mut zones: Value = serde_json::from_str(&text).unwrap();
+    let txt: Value = serde_json::from_str(r#"{ "zone_id": "ZMOCK", "name": "test.com", "rtype": "TXT",
+                                              "records": ["\"v=spf1 -all\""], "ttl": 300 }"#).unwrap();
+    zones["ZMOCK"].as_array_mut().unwrap().push(txt);
+    fs::write(dir.join("remote.json"), zones.to_string()).unwrap();
+    fs::write(dir.join("state.json"), r#"{
+        "version": 1, "appversion": "0.6.22", "serial": 1,
+        "records": {
+            "a-foo-test-com": { "zone_id": "ZMOCK", "name": "foo.test.com", "rtype": "A",
+                                "records": ["9.9.9.9"], "ttl": 300 },
+            "cname-www-test-com": { "zone_id": "ZMOCK", "name": "www.test.com", "rtype": "CNAME",
+                                    "records": ["foo.test.com"], "ttl": 600 },
+            "txt-test-com": { "zone_id": "ZMOCK", "name": "test.com", "rtype": "TXT",
+                              "records": ["v=spf1 -all"], "ttl": 300 }
+        }
+    }"#).unwrap();
+    let out = run(&dir, "noop");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+    assert!(!stdout.contains("does not match statefile"), "{}", stdout);
+}
+
+#[test]
+fn test_locked_state_blocks_execute() {
+    let dir = setup("lock");
+    let lock = r#"{ "id": "abc123", "owner": "someone@elsewhere", "operation": "execute",
+                    "created": 1, "expires": 9999999999 }"#;
+    fs::write(dir.join("state.json.lock"), lock).unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(!out.status.success());
+    assert!(stdout.contains("Statefile is locked"));
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["9.9.9.9".to_string()]));
+
+    // An unexpired lock needs its ID to be cleared
+    assert!(!run(&dir, "force-unlock").status.success());
+    assert!(!run_input(&dir, &dir.join("mock.tiny"), &["force-unlock", "wrong"]).status.success());
+    assert!(run_input(&dir, &dir.join("mock.tiny"), &["force-unlock", "abc123"]).status.success());
+    assert!(!dir.join("state.json.lock").exists());
+
+    // And the lock is released after a successful run
+    assert!(run(&dir, "execute").status.success());
+    assert!(!dir.join("state.json.lock").exists());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.4".to_string()]));
+}
+
+#[test]
+fn test_force_unlock_expired() {
+    let dir = setup("unlock");
+    let lock = r#"{ "id": "old", "owner": "someone@elsewhere", "operation": "execute",
+                    "created": 1, "expires": 2 }"#;
+    fs::write(dir.join("state.json.lock"), lock).unwrap();
+    let out = run(&dir, "execute");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("That lock has expired"));
+    assert!(run(&dir, "force-unlock").status.success());
+    assert!(!dir.join("state.json.lock").exists());
+}
+
+#[test]
+fn test_history_and_rollback() {
+    let dir = setup("rollback");
+    assert!(run(&dir, "execute").status.success());
+    assert!(dir.join("state.json.1").exists());
+
+    // Change foo and drop bar
+    fs::write(dir.join("mock.tiny"), "+foo.test.com:5.5.5.5:300\nCwww.test.com:foo.test.com:600\n").unwrap();
+    assert!(run(&dir, "execute").status.success());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["5.5.5.5".to_string()]));
+    assert_eq!(remote_records(&dir, "A", "bar.test.com"), None);
+
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["state", "history"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("Serial 1, 3 resources"));
+    assert!(stdout.contains("Serial 2, 2 resources"));
+
+    // Going back to serial 1 puts both records back as they were
+    let out = run_input(&dir, &dir.join("mock.tiny"), &["state", "rollback", "1"]);
+    assert!(out.status.success());
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.4".to_string()]));
+    assert_eq!(remote_records(&dir, "A", "bar.test.com"), Some(vec!["1.2.3.5".to_string()]));
+    assert!(dir.join("state.json.3").exists());
+    assert!(!run_input(&dir, &dir.join("mock.tiny"), &["state", "rollback", "7"]).status.success());
+}
+
+#[test]
+fn test_state_commands() {
+    let dir = setup("statecmds");
+    assert!(run(&dir, "execute").status.success());
+    let state = |args: &[&str]| {
+        let mut all = vec!["state"];
+        all.extend_from_slice(args);
+        run_input(&dir, &dir.join("mock.tiny"), &all)
+    };
+
+    let out = state(&["list"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("a-foo-test-com\n"));
+    assert!(stdout.contains("cname-www-test-com\n"));
+
+    let out = state(&["show", "a-bar-test-com"]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"1.2.3.5\""));
+    assert!(!state(&["show", "a-nope-test-com"]).status.success());
+
+    // mv and rm only touch the state, never the remote
+    assert!(state(&["mv", "a-bar-test-com", "a-baz-test-com"]).status.success());
+    assert!(!state(&["mv", "a-foo-test-com", "a-baz-test-com"]).status.success());
+    assert!(state(&["rm", "a-foo-test-com"]).status.success());
+    let out = state(&["list"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("a-baz-test-com"));
+    assert!(!stdout.contains("a-foo-test-com"));
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.4".to_string()]));
+
+    // Pull, then push it back; a stale copy needs --force
+    let pulled = dir.join("pulled.json");
+    assert!(state(&["pull", pulled.to_str().unwrap()]).status.success());
+    assert!(state(&["push", pulled.to_str().unwrap()]).status.success());
+    assert!(!state(&["push", pulled.to_str().unwrap()]).status.success());
+    assert!(state(&["push", "--force", pulled.to_str().unwrap()]).status.success());
+    assert!(!dir.join("state.json.lock").exists());
+
+    // A file that isn't there is an error, not an empty state
+    let before = fs::read_to_string(dir.join("state.json")).unwrap();
+    let missing = dir.join("missing.json");
+    assert!(!state(&["push", "--force", missing.to_str().unwrap()]).status.success());
+    assert_eq!(fs::read_to_string(dir.join("state.json")).unwrap(), before);
+}
+
+#[test]
+fn test_state_pull_encrypted() {
+    let dir = setup("pullcrypt");
+    let keyfile = dir.join("state.key");
+    fs::write(&keyfile, "0123456789abcdef".repeat(4)).unwrap();
+    let text = fs::read_to_string(dir.join("macrotis.conf")).unwrap();
+    let conf: Value = serde_json::from_str(&text).unwrap();
+    let mut statefile = conf["statefile"].clone();
+    statefile["encryption_key_file"] = Value::from(keyfile.to_str().unwrap());
+    set_config(&dir, "statefile", statefile);
+    assert!(run(&dir, "execute").status.success());
+    let state = |args: &[&str]| {
+        let mut all = vec!["state"];
+        all.extend_from_slice(args);
+        run_input(&dir, &dir.join("mock.tiny"), &all)
+    };
+
+    // The pulled copy is as encrypted as the statefile, and pushes back
+    let pulled = dir.join("pulled.json");
+    assert!(state(&["pull", pulled.to_str().unwrap()]).status.success());
+    assert!(!fs::read_to_string(&pulled).unwrap().contains("appversion"));
+    assert!(state(&["push", pulled.to_str().unwrap()]).status.success());
+
+    let plain = dir.join("plain.json");
+    assert!(state(&["pull", "--plaintext", plain.to_str().unwrap()]).status.success());
+    assert!(fs::read_to_string(&plain).unwrap().contains("appversion"));
+    assert!(state(&["push", plain.to_str().unwrap()]).status.success());
+    assert!(!fs::read_to_string(dir.join("state.json")).unwrap().contains("appversion"));
+}
+
+#[test]
+fn test_execute_waits_for_insync() {
+    let dir = setup("wait");
+    let conf = fs::read_to_string(dir.join("macrotis.conf")).unwrap();
+    let conf = conf.replace("\"assume_role\": false,",
+                            "\"assume_role\": false, \"pending_polls\": 2, \"poll_interval\": 0,");
+    fs::write(dir.join("macrotis.conf"), &conf).unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("Waiting for 1 of 1 changes to reach INSYNC"));
+    assert!(stdout.contains("All 1 changes are INSYNC"));
+
+    // Changes that never settle time out, but still get saved to state
+    let conf = conf.replace("\"pending_polls\": 2, \"poll_interval\": 0",
+                            "\"pending_polls\": 1000, \"poll_interval\": 1, \"wait_timeout\": 1");
+    fs::write(dir.join("macrotis.conf"), conf).unwrap();
+    fs::write(dir.join("mock.tiny"), "+foo.test.com:1.2.3.9:300\n").unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(!out.status.success());
+    assert!(stdout.contains("Timed out after 1s with 1 of 1 changes still pending"));
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["1.2.3.9".to_string()]));
+    assert!(!dir.join("state.json.lock").exists());
+    let out = run(&dir, "noop");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("No changes detected."));
+}
+
+#[test]
+fn test_cidr_locations_rejected() {
+    // Nothing could push these, so they mustn't get as far as a plan
+    let dir = setup("cidr");
+    fs::write(dir.join("mock.tiny"), "%in:10.1\n+foo.test.com:1.2.3.4:300::in\n").unwrap();
+    for sub in &["lint", "noop"] {
+        let out = run(&dir, sub);
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stdout).contains("'%' location lines aren't supported"));
+    }
+}
+
+#[test]
+fn test_alias_round_trip() {
+    let dir = setup("alias");
+    fs::write(dir.join("mock.tiny"), "+foo.test.com:1.2.3.4:300\n~test.com:foo.test.com\n~cdn.test.com:d111.cloudfront.net::AAAA:1\n").unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("[ADD] A test.com"));
+
+    // Zones for in-zone and CloudFront targets get filled in
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    let zones: Value = serde_json::from_str(&text).unwrap();
+    let alias = |name: &str| zones["ZMOCK"].as_array().unwrap().iter()
+        .find(|r| r["name"] == name).unwrap()["alias_target"].clone();
+    assert_eq!(alias("test.com")["hosted_zone_id"], "ZMOCK");
+    assert_eq!(alias("cdn.test.com")["hosted_zone_id"], "Z2FDTNDATAQYW2");
+    assert_eq!(alias("cdn.test.com")["evaluate_target_health"], true);
+
+    let out = run(&dir, "noop");
+    assert!(String::from_utf8_lossy(&out.stdout).contains("No changes detected."));
+
+    // Without a zone we can work out, the alias is refused
+    fs::write(dir.join("mock.tiny"), "~test.com:lb.elb.amazonaws.com\n").unwrap();
+    assert!(!run(&dir, "noop").status.success());
+
+    // And imports write them back out as '~' lines
+    fs::remove_file(dir.join("state.json")).unwrap();
+    assert!(run_input(&dir, &dir.join("imported"), &["import"]).status.success());
+    let tiny = fs::read_to_string(dir.join("imported/test.tiny")).unwrap();
+    assert!(tiny.contains("~cdn.test.com:d111.cloudfront.net:Z2FDTNDATAQYW2:AAAA:1"));
+    assert!(tiny.contains("~test.com:foo.test.com:ZMOCK:A"));
+}
+
+#[test]
+fn test_weighted_sets() {
+    let dir = setup("weighted");
+    assert!(run(&dir, "execute").status.success());
+
+    // foo becomes two weighted sets, which only works if the old simple
+    // set is deleted before they're created
+    fs::write(dir.join("mock.tiny"), "!foo.test.com:a:weight:70\n!foo.test.com:b:weight:30:abcdef11-2222-3333-4444-555555fedcba\n+foo.test.com:1.2.3.4:300::a\n+foo.test.com:1.2.3.6:300::b\n+bar.test.com:1.2.3.5:300\nCwww.test.com:foo.test.com:600\n").unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("[ADD] A foo.test.com (a)"));
+    assert!(stdout.contains("[DEL] A foo.test.com\t"));
+
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    let zones: Value = serde_json::from_str(&text).unwrap();
+    let foo: Vec<&Value> = zones["ZMOCK"].as_array().unwrap().iter().filter(|r| r["name"] == "foo.test.com").collect();
+    assert_eq!(foo.len(), 2);
+    let b = foo.iter().find(|r| r["set_identifier"] == "b").unwrap();
+    assert_eq!(b["weight"], 30);
+    assert_eq!(b["health_check_id"], "abcdef11-2222-3333-4444-555555fedcba");
+
+    // Changing a weight is an update of just that set
+    fs::write(dir.join("mock.tiny"), "!foo.test.com:a:weight:50\n!foo.test.com:b:weight:30:abcdef11-2222-3333-4444-555555fedcba\n+foo.test.com:1.2.3.4:300::a\n+foo.test.com:1.2.3.6:300::b\n+bar.test.com:1.2.3.5:300\nCwww.test.com:foo.test.com:600\n").unwrap();
+    let stdout = String::from_utf8_lossy(&run(&dir, "noop").stdout).to_string();
+    assert!(stdout.contains("[UPD] A foo.test.com (a)"));
+    assert!(!stdout.contains("(b)"));
+
+    // A weighted set can't share a name with a simple one
+    fs::write(dir.join("mock.tiny"), "!foo.test.com:a:weight:50\n+foo.test.com:1.2.3.4:300::a\n+foo.test.com:1.2.3.6:300\n").unwrap();
+    assert!(!run(&dir, "noop").status.success());
+}
+
+// Set one of the top-level sections of the scratch config
+fn set_config(dir: &Path, key: &str, value: Value) {
+    let text = fs::read_to_string(dir.join("macrotis.conf")).unwrap();
+    let mut conf: Value = serde_json::from_str(&text).unwrap();
+    conf[key] = value;
+    fs::write(dir.join("macrotis.conf"), conf.to_string()).unwrap();
+}
+
+#[test]
+fn test_health_checks() {
+    let dir = setup("health");
+    set_config(&dir, "health_checks", serde_json::json!({
+        "web": { "type": "HTTPS", "fqdn": "lb.test.com", "resource_path": "/health" }
+    }));
+    fs::write(dir.join("mock.tiny"), "!lb.test.com:a:weight:70:web\n!lb.test.com:b:weight:30\n+lb.test.com:1.2.3.4:300::a\n+lb.test.com:1.2.3.6:300::b\n").unwrap();
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("[ADD] health check web (HTTPS)"));
+
+    // The record gets the check's ID, the statefile keeps its name
+    let checks: Value = serde_json::from_str(&fs::read_to_string(dir.join("remote.json.health")).unwrap()).unwrap();
+    let ids: Vec<&String> = checks.as_object().unwrap().keys().collect();
+    assert_eq!(ids.len(), 1);
+    let text = fs::read_to_string(dir.join("remote.json")).unwrap();
+    let zones: Value = serde_json::from_str(&text).unwrap();
+    let a = zones["ZMOCK"].as_array().unwrap().iter().find(|r| r["set_identifier"] == "a").unwrap();
+    assert_eq!(a["health_check_id"], ids[0].as_str());
+    let stdout = String::from_utf8_lossy(&run(&dir, "noop").stdout).to_string();
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+
+    // Changing the path updates the check in place
+    set_config(&dir, "health_checks", serde_json::json!({
+        "web": { "type": "HTTPS", "fqdn": "lb.test.com", "resource_path": "/status" }
+    }));
+    let stdout = String::from_utf8_lossy(&run(&dir, "execute").stdout).to_string();
+    assert!(stdout.contains("[UPD] health check web"), "{}", stdout);
+    assert!(!stdout.contains("[UPD] A"));
+
+    // Changing the interval replaces it, moving the record over before
+    // the old one goes
+    set_config(&dir, "health_checks", serde_json::json!({
+        "web": { "type": "HTTPS", "fqdn": "lb.test.com", "resource_path": "/status", "request_interval": 10 }
+    }));
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("[REP] health check web"));
+    assert!(stdout.contains("[UPD] A lb.test.com (a)"));
+    let checks: Value = serde_json::from_str(&fs::read_to_string(dir.join("remote.json.health")).unwrap()).unwrap();
+    assert_eq!(checks.as_object().unwrap().len(), 1);
+    assert!(!checks.as_object().unwrap().contains_key(ids[0].as_str()));
+
+    // Pushing a statefile pushes its checks as well as its records
+    let state = |args: &[&str]| {
+        let mut all = vec!["state"];
+        all.extend_from_slice(args);
+        run_input(&dir, &dir.join("mock.tiny"), &all)
+    };
+    let pulled = dir.join("pulled.json");
+    let edited = dir.join("edited.json");
+    assert!(state(&["pull", pulled.to_str().unwrap()]).status.success());
+    let mut st: Value = serde_json::from_str(&fs::read_to_string(&pulled).unwrap()).unwrap();
+    st["health_checks"] = serde_json::json!({});
+    fs::write(&edited, st.to_string()).unwrap();
+    assert!(state(&["push", edited.to_str().unwrap()]).status.success());
+    let st: Value = serde_json::from_str(&fs::read_to_string(dir.join("state.json")).unwrap()).unwrap();
+    assert_eq!(st["health_checks"], serde_json::json!({}));
+    assert!(state(&["push", "--force", pulled.to_str().unwrap()]).status.success());
+    let stdout = String::from_utf8_lossy(&run(&dir, "noop").stdout).to_string();
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+
+    // A check records still use can't be dropped
+    set_config(&dir, "health_checks", serde_json::json!({}));
+    assert!(!run(&dir, "noop").status.success());
+
+    // Nor can a record name a check that isn't there
+    fs::write(dir.join("mock.tiny"), "!lb.test.com:a:weight:70:wbe\n+lb.test.com:1.2.3.4:300::a\n").unwrap();
+    let out = run(&dir, "lint");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("uses health check 'wbe', which isn't declared"));
+}
+
+#[test]
+fn test_zone_lifecycle() {
+    let dir = setup("zones");
+    set_config(&dir, "zones", serde_json::json!([
+        { "name": "test", "domain": "test.com", "id": "ZMOCK" },
+        { "name": "new", "domain": "new.test", "create": true, "delegation_set_id": "N1" }
+    ]));
+    fs::write(dir.join("new.tiny"), "+www.new.test:1.2.3.4:300\n").unwrap();
+    let input = dir.join("new.tiny");
+
+    // noop only says the zone would be made, and won't plan without it
+    let stdout = String::from_utf8_lossy(&run_input(&dir, &input, &["noop"]).stdout).to_string();
+    assert!(stdout.contains("[ADD] public hosted zone new.test"), "{}", stdout);
+    assert!(stdout.contains("[ADD] A www.new.test"));
+    assert!(!run_input(&dir, &input, &["noop", "--out", dir.join("plan.json").to_str().unwrap()]).status.success());
+
+    // execute makes it, says where to delegate it, and fills it in
+    let out = run_input(&dir, &input, &["execute"]);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("Created public hosted zone"));
+    assert!(stdout.contains("ns1.n1.mock.test"));
+    let stdout = String::from_utf8_lossy(&run_input(&dir, &input, &["noop"]).stdout).to_string();
+    assert!(stdout.contains("Found public hosted zone"));
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+    let out = run_input(&dir, &input, &["zone", "list"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("ns2.n1.mock.test"));
+
+    // It can only be deleted once Macrotis has emptied it out
+    assert!(!run_input(&dir, &input, &["zone", "delete", "new.test"]).status.success());
+    fs::write(&input, "").unwrap();
+    assert!(run_input(&dir, &input, &["execute"]).status.success());
+    let out = run_input(&dir, &input, &["zone", "delete", "new.test"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stdout));
+    let hosted: Value = serde_json::from_str(&fs::read_to_string(dir.join("remote.json.zones")).unwrap()).unwrap();
+    assert!(hosted.as_object().unwrap().is_empty());
+
+    // Without create, a missing zone is an error
+    set_config(&dir, "zones", serde_json::json!([{ "name": "new", "domain": "new.test" }]));
+    assert!(!run_input(&dir, &input, &["noop"]).status.success());
+}
+
+#[test]
+fn test_ownership() {
+    let dir = setup("ownership");
+    set_config(&dir, "zones", serde_json::json!([
+        { "name": "test", "domain": "test.com", "id": "ZMOCK", "ownership": { "owner_id": "prod" } }
+    ]));
+
+    // foo and www were already there and aren't marked, so they're left be
+    let out = run(&dir, "execute");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success(), "{}", stdout);
+    assert!(stdout.contains("[UNOWNED] A foo.test.com"));
+    assert!(stdout.contains("[SKIP] A foo.test.com exists remotely"));
+    assert!(stdout.contains("[ADD] A bar.test.com"));
+    assert!(!stdout.contains("[UPD]"));
+    assert_eq!(remote_records(&dir, "A", "foo.test.com"), Some(vec!["9.9.9.9".to_string()]));
+    assert_eq!(remote_records(&dir, "TXT", "_macrotis.bar.test.com"),
+               Some(vec!["\"heritage=macrotis,macrotis/owner=prod\"".to_string()]));
+    let stdout = String::from_utf8_lossy(&run(&dir, "noop").stdout).to_string();
+    assert!(stdout.contains("No changes detected."), "{}", stdout);
+
+    // Records outside the rules can't be in the input at all
+    set_config(&dir, "zones", serde_json::json!([
+        { "name": "test", "domain": "test.com", "id": "ZMOCK", "ownership": { "types": ["A"] } }
+    ]));
+    let out = run(&dir, "lint");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).contains("CNAME www.test.com is outside what zone test owns"));
+
+    // And import only takes what's owned
+    let out = run_input(&dir, &dir.join("imported"), &["import"]);
+    assert!(out.status.success());
+    let tiny = fs::read_to_string(dir.join("imported/test.tiny")).unwrap();
+    assert!(tiny.contains("+foo.test.com:9.9.9.9:300"));
+    assert!(!tiny.contains("www.test.com"));
+    assert!(!tiny.contains("_macrotis"));
+}